@@ -0,0 +1,150 @@
+//! Channels we're in, with their keys, to rejoin after a reconnect. Updated from our own JOIN,
+//! PART and KICK messages.
+
+use isupport::CaseMapping;
+
+struct Chan {
+    name: String,
+    key: Option<String>,
+}
+
+pub struct JoinedChans {
+    chans: Vec<Chan>,
+    /// Keys given in `/join`, moved to `chans` when the JOIN succeeds.
+    pending_keys: Vec<(String, String)>,
+}
+
+impl JoinedChans {
+    /// `chans` are the channels in the config, joined after registration.
+    pub fn new(chans: Vec<String>) -> JoinedChans {
+        JoinedChans {
+            chans: chans
+                .into_iter()
+                .map(|name| Chan { name, key: None })
+                .collect(),
+            pending_keys: vec![],
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.chans.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chans.is_empty()
+    }
+
+    pub fn get_name(&self, idx: usize) -> &str {
+        &self.chans[idx].name
+    }
+
+    pub fn get_names(&self) -> Vec<String> {
+        self.chans.iter().map(|chan| chan.name.clone()).collect()
+    }
+
+    pub fn get_key(&self, casemapping: CaseMapping, chan: &str) -> Option<&str> {
+        self.chans
+            .iter()
+            .find(|chan_| casemapping.eq(chan, &chan_.name))
+            .and_then(|chan_| chan_.key.as_ref().map(String::as_str))
+    }
+
+    /// Remember the key of a channel we're joining, to be used on reconnect if the JOIN
+    /// succeeds.
+    pub fn set_pending_key(&mut self, casemapping: CaseMapping, chan: &str, key: &str) {
+        self.pending_keys
+            .retain(|&(ref chan_, _)| !casemapping.eq(chan, chan_));
+        self.pending_keys.push((chan.to_owned(), key.to_owned()));
+    }
+
+    /// We joined a channel. Keeps the old key if the JOIN didn't have a new one.
+    pub fn add(&mut self, casemapping: CaseMapping, chan: &str) {
+        let key = self.pending_keys
+            .iter()
+            .position(|&(ref chan_, _)| casemapping.eq(chan, chan_))
+            .map(|idx| self.pending_keys.remove(idx).1);
+        match self.chans
+            .iter_mut()
+            .find(|chan_| casemapping.eq(chan, &chan_.name))
+        {
+            Some(chan_) =>
+                if key.is_some() {
+                    chan_.key = key;
+                },
+            None =>
+                self.chans.push(Chan {
+                    name: chan.to_owned(),
+                    key,
+                }),
+        }
+    }
+
+    /// We left a channel (or got kicked).
+    pub fn remove(&mut self, casemapping: CaseMapping, chan: &str) {
+        self.chans.retain(|chan_| !casemapping.eq(chan, &chan_.name));
+    }
+
+    /// Channels and keys of a JOIN message for joining `chans`. Channels with keys come first,
+    /// as servers match keys to channels by position.
+    pub fn join_params(
+        &self,
+        casemapping: CaseMapping,
+        chans: &[String],
+    ) -> (Vec<String>, Vec<String>) {
+        let mut keyed = vec![];
+        let mut keys = vec![];
+        let mut rest = vec![];
+        for chan in chans {
+            match self.get_key(casemapping, chan) {
+                Some(key) => {
+                    keyed.push(chan.to_owned());
+                    keys.push(key.to_owned());
+                }
+                None =>
+                    rest.push(chan.to_owned()),
+            }
+        }
+        keyed.extend(rest);
+        (keyed, keys)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strs(strs: &[&str]) -> Vec<String> {
+        strs.iter().map(|s| (*s).to_owned()).collect()
+    }
+
+    #[test]
+    fn test_joined_chans() {
+        let cm = CaseMapping::Rfc1459;
+        let mut chans = JoinedChans::new(strs(&["#tiny"]));
+        chans.set_pending_key(cm, "#secret", "hunter2");
+        chans.set_pending_key(cm, "#rust", "rustkey");
+        chans.add(cm, "#rust");
+        // JOIN with a wrong key fails, the old key is kept
+        chans.set_pending_key(cm, "#rust", "wrong");
+        chans.add(cm, "#haskell");
+        chans.add(cm, "#SECRET");
+        chans.add(cm, "#tiny");
+        assert_eq!(chans.get_names(), strs(&["#tiny", "#rust", "#haskell", "#SECRET"]));
+        assert_eq!(chans.get_key(cm, "#secret"), Some("hunter2"));
+        assert_eq!(chans.get_key(cm, "#rust"), Some("rustkey"));
+
+        chans.remove(cm, "#Rust");
+        assert_eq!(chans.get_names(), strs(&["#tiny", "#haskell", "#SECRET"]));
+        assert_eq!(
+            chans.join_params(cm, &chans.get_names()),
+            (strs(&["#SECRET", "#tiny", "#haskell"]), strs(&["hunter2"]))
+        );
+        // a kicked channel that's not in the list anymore is joined without a key
+        assert_eq!(
+            chans.join_params(cm, &strs(&["#rust", "#secret"])),
+            (strs(&["#secret", "#rust"]), strs(&["hunter2"]))
+        );
+    }
+}
@@ -1,12 +1,15 @@
 use config;
 use conn::Conn;
+use ctcp::{self, CtcpCmd};
 use mio::Poll;
 use serde::Deserialize;
 use std::error::Error;
+use std::path::Path;
 use super::Tiny;
 use tui::tabbed::MsgSource;
 use tui::{MsgTarget, Timestamp};
 use utils;
+use who::Member;
 use serde::de::{Deserializer, Visitor};
 
 use notifier::Notifier;
@@ -127,11 +130,15 @@ impl<'de> Deserialize<'de> for AutoCmd {
 
 ////////////////////////////////////////////////////////////////////////////////////////////////////
 
-static CMDS: [&'static Cmd; 14] = [
+static CMDS: [&'static Cmd; 20] = [
+    &ACCEPT_CMD,
     &AWAY_CMD,
     &CLEAR_CMD,
     &CLOSE_CMD,
     &CONNECT_CMD,
+    &CTCP_CMD,
+    &DCC_CMD,
+    &FRIENDS_CMD,
     &HELP_CMD,
     &IGNORE_CMD,
     &JOIN_CMD,
@@ -140,12 +147,35 @@ static CMDS: [&'static Cmd; 14] = [
     &NAMES_CMD,
     &NICK_CMD,
     &NOTIFY_CMD,
+    &RECONNECT_CMD,
     &RELOAD_CMD,
     &SWITCH_CMD,
+    &WHOIS_CMD,
 ];
 
 ////////////////////////////////////////////////////////////////////////////////////////////////////
 
+static ACCEPT_CMD: Cmd = Cmd {
+    name: "accept",
+    cmd_fn: accept,
+};
+
+fn accept(_: &str, _: &Poll, tiny: &mut Tiny, src: MsgSource) {
+    match super::find_conn(&mut tiny.conns, src.serv_name()) {
+        Some(conn) =>
+            if conn.accept_invite().is_none() {
+                tiny.tui.add_client_err_msg("No invites to accept", &MsgTarget::CurrentTab);
+            },
+        None =>
+            tiny.tui.add_client_err_msg(
+                &format!("Can't accept: Not connected to server {}", src.serv_name()),
+                &MsgTarget::CurrentTab,
+            ),
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
 static AWAY_CMD: Cmd = Cmd {
     name: "away",
     cmd_fn: away,
@@ -186,8 +216,14 @@ fn close(_: &str, _: &Poll, tiny: &mut Tiny, src: MsgSource) {
         MsgSource::Serv { ref serv_name } if serv_name == "mentions" => {
             // ignore
         }
+        MsgSource::Serv { ref serv_name } if serv_name == "transfers" || serv_name == "friends" => {
+            // created again on the next event
+            tiny.tui.close_server_tab(serv_name);
+        }
         MsgSource::Serv { serv_name } => {
             tiny.tui.close_server_tab(&serv_name);
+            tiny.chats.close_serv(&serv_name);
+            tiny.netsplits.clear_serv(&serv_name);
             let conn_idx = super::find_conn_idx(&tiny.conns, &serv_name).unwrap();
             tiny.conns.remove(conn_idx);
         }
@@ -201,6 +237,10 @@ fn close(_: &str, _: &Poll, tiny: &mut Tiny, src: MsgSource) {
         MsgSource::User { serv_name, nick } => {
             tiny.tui.close_user_tab(&serv_name, &nick);
         }
+        MsgSource::Dcc { serv_name, nick } => {
+            tiny.tui.close_dcc_tab(&serv_name, &nick);
+            tiny.chats.close(&serv_name, &nick);
+        }
     }
 }
 
@@ -216,7 +256,7 @@ fn connect<'a, 'b>(args: &str, poll: &'b Poll, tiny: &'a mut Tiny<'b>, src: MsgS
 
     match words.len() {
         0 =>
-            reconnect(tiny, src),
+            reconnect_(tiny, src),
         1 =>
             connect_(words[0], None, poll, tiny),
         2 =>
@@ -230,7 +270,7 @@ fn connect<'a, 'b>(args: &str, poll: &'b Poll, tiny: &'a mut Tiny<'b>, src: MsgS
     }
 }
 
-fn reconnect(tiny: &mut Tiny, src: MsgSource) {
+fn reconnect_(tiny: &mut Tiny, src: MsgSource) {
     tiny.tui.add_client_msg(
         "Reconnecting...",
         &MsgTarget::AllServTabs {
@@ -244,7 +284,7 @@ fn reconnect(tiny: &mut Tiny, src: MsgSource) {
                     {}
                 Err(err) => {
                     tiny.tui.add_err_msg(
-                        &super::reconnect_err_msg(&err),
+                        &super::reconnect_err_msg(&err, conn),
                         Timestamp::now(),
                         &MsgTarget::AllServTabs {
                             serv_name: conn.get_serv_name(),
@@ -299,7 +339,7 @@ fn connect_<'a, 'b>(serv_addr: &str, pass: Option<&str>, poll: &'b Poll, tiny: &
                 {}
             Err(err) => {
                 tiny.tui.add_err_msg(
-                    &super::reconnect_err_msg(&err),
+                    &super::reconnect_err_msg(&err, conn),
                     Timestamp::now(),
                     &MsgTarget::AllServTabs {
                         serv_name: conn.get_serv_name(),
@@ -325,12 +365,27 @@ fn connect_<'a, 'b>(serv_addr: &str, pass: Option<&str>, poll: &'b Poll, tiny: &
             addr: serv_name.to_owned(),
             port: serv_port,
             tls: tiny.defaults.tls,
+            alt_endpoints: vec![],
+            tls_client_cert: None,
             hostname: tiny.defaults.hostname.clone(),
             realname: tiny.defaults.realname.clone(),
             pass: pass.map(str::to_owned),
             nicks: tiny.defaults.nicks.clone(),
             auto_cmds: tiny.defaults.auto_cmds.clone(),
             join: tiny.defaults.join.clone(),
+            caps: tiny.defaults.caps.clone(),
+            sasl: None,
+            auto_rejoin: false,
+            auto_rejoin_delay: config::default_auto_rejoin_delay(),
+            auto_accept_invites: vec![],
+            ctcp_replies: config::CtcpReplies::default(),
+            friends: vec![],
+            send_burst: config::default_send_burst(),
+            send_interval_ms: config::default_send_interval_ms(),
+            ping_interval: config::default_ping_interval(),
+            pong_timeout: config::default_pong_timeout(),
+            reconnect_delay: config::default_reconnect_delay(),
+            max_reconnect_delay: config::default_max_reconnect_delay(),
         },
         poll,
     );
@@ -348,6 +403,222 @@ fn connect_<'a, 'b>(serv_addr: &str, pass: Option<&str>, poll: &'b Poll, tiny: &
 
 ////////////////////////////////////////////////////////////////////////////////////////////////////
 
+static CTCP_CMD: Cmd = Cmd {
+    name: "ctcp",
+    cmd_fn: ctcp,
+};
+
+fn ctcp(args: &str, _: &Poll, tiny: &mut Tiny, src: MsgSource) {
+    let mut words = args.splitn(3, ' ').filter(|s| !s.is_empty());
+    let (target, cmd) = match (words.next(), words.next()) {
+        (Some(target), Some(cmd)) =>
+            (target, CtcpCmd::from_name(cmd)),
+        _ =>
+            return tiny.tui.add_client_err_msg(
+                "/ctcp usage: /ctcp <nick> <command> [args]",
+                &MsgTarget::CurrentTab,
+            ),
+    };
+    let args = match words.next() {
+        Some(args) =>
+            args.to_owned(),
+        None if cmd == CtcpCmd::Ping =>
+            // to calculate round-trip time when the reply arrives
+            ctcp::now_millis().to_string(),
+        None =>
+            String::new(),
+    };
+
+    match super::find_conn(&mut tiny.conns, src.serv_name()) {
+        Some(conn) =>
+            conn.ctcp_request(target, cmd.name(), &args),
+        None =>
+            tiny.tui.add_client_err_msg(
+                &format!("Can't send CTCP: Not connected to server {}", src.serv_name()),
+                &MsgTarget::CurrentTab,
+            ),
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+static DCC_CMD: Cmd = Cmd {
+    name: "dcc",
+    cmd_fn: dcc,
+};
+
+fn dcc(args: &str, _: &Poll, tiny: &mut Tiny, src: MsgSource) {
+    let words: Vec<&str> = args.split_whitespace().collect();
+    match words.first().map(|s| *s) {
+        None | Some("list") =>
+            dcc_list(tiny),
+        Some("accept") =>
+            match parse_transfer_id(tiny, words.get(1)) {
+                None =>
+                    {}
+                Some(id) => {
+                    let ret = tiny.transfers.accept(id, &tiny.download_dir);
+                    match ret {
+                        Ok(path) => {
+                            let msg = format!(
+                                "{}: saving to {}",
+                                tiny.transfers.get(id).unwrap().name(),
+                                path.to_string_lossy()
+                            );
+                            super::show_transfer_msg(&mut tiny.tui, &msg, false);
+                        }
+                        Err(err) =>
+                            tiny.tui.add_client_err_msg(
+                                &format!("Can't accept transfer: {}", err),
+                                &MsgTarget::CurrentTab,
+                            ),
+                    }
+                }
+            },
+        Some("reject") =>
+            if let Some(id) = parse_transfer_id(tiny, words.get(1)) {
+                match tiny.transfers.cancel(id) {
+                    Some(transfer) => {
+                        let msg = format!("{}: cancelled", transfer.name());
+                        super::show_transfer_msg(&mut tiny.tui, &msg, false);
+                    }
+                    None =>
+                        tiny.tui.add_client_err_msg(
+                            &format!("No such transfer: {}", id),
+                            &MsgTarget::CurrentTab,
+                        ),
+                }
+            },
+        Some("chat") if words.len() == 2 =>
+            dcc_chat(tiny, src.serv_name(), words[1]),
+        Some("send") if words.len() >= 3 => {
+            // file names can have spaces
+            let nick = words[1];
+            let path = args.trim()[4..].trim_left()[nick.len()..].trim();
+            dcc_send(tiny, src.serv_name(), nick, path);
+        }
+        Some(_) =>
+            tiny.tui.add_client_err_msg(
+                "/dcc usage: /dcc [list | accept [id] | reject [id] | send <nick> <file> | \
+                 chat <nick>]",
+                &MsgTarget::CurrentTab,
+            ),
+    }
+}
+
+fn dcc_list(tiny: &mut Tiny) {
+    let lines: Vec<String> = tiny.transfers.iter().map(|transfer| transfer.describe()).collect();
+    if lines.is_empty() {
+        tiny.tui.add_client_msg("No file transfers", &MsgTarget::CurrentTab);
+    }
+    for line in lines {
+        tiny.tui.add_client_msg(&line, &MsgTarget::CurrentTab);
+    }
+}
+
+/// Parse id argument of `/dcc accept` and `/dcc reject`. Without an argument the most recent
+/// offer is used.
+fn parse_transfer_id(tiny: &mut Tiny, arg: Option<&&str>) -> Option<usize> {
+    let id = match arg {
+        Some(arg) =>
+            arg.trim_left_matches('#').parse::<usize>().ok(),
+        None =>
+            tiny.transfers.last_offer(),
+    };
+    if id.is_none() {
+        tiny.tui.add_client_err_msg(
+            match arg {
+                Some(_) =>
+                    "/dcc: transfer id should be a number",
+                None =>
+                    "No file offers",
+            },
+            &MsgTarget::CurrentTab,
+        );
+    }
+    id
+}
+
+/// Accept a chat offer from the nick, or offer a chat if we don't have one.
+fn dcc_chat(tiny: &mut Tiny, serv_name: &str, nick: &str) {
+    if tiny.chats.has_offer(serv_name, nick) {
+        if let Err(err) = tiny.chats.accept(serv_name, nick) {
+            tiny.tui.add_client_err_msg(
+                &format!("Can't accept chat: {}", err),
+                &MsgTarget::CurrentTab,
+            );
+        }
+        return;
+    }
+    let conn = super::find_conn(&mut tiny.conns, serv_name);
+    let local = conn.and_then(|conn| conn.get_local_ip().map(|ip| (conn.get_casemapping(), ip)));
+    let (casemapping, ip) = match local {
+        Some(local) =>
+            local,
+        None =>
+            return tiny.tui.add_client_err_msg(
+                &format!("Can't offer chat: Not connected to server {}", serv_name),
+                &MsgTarget::CurrentTab,
+            ),
+    };
+    match tiny.chats.listen(casemapping, serv_name, nick, ip) {
+        Ok(args) => {
+            if let Some(conn) = super::find_conn(&mut tiny.conns, serv_name) {
+                conn.ctcp_request(nick, "DCC", &args);
+            }
+            tiny.tui.add_client_msg(
+                &format!("DCC CHAT offered to {}, waiting for the peer to connect", nick),
+                &MsgTarget::CurrentTab,
+            );
+        }
+        Err(err) =>
+            tiny.tui.add_client_err_msg(
+                &format!("Can't offer chat: {}", err),
+                &MsgTarget::CurrentTab,
+            ),
+    }
+}
+
+fn dcc_send(tiny: &mut Tiny, serv_name: &str, nick: &str, path: &str) {
+    let ip = match super::find_conn(&mut tiny.conns, serv_name) {
+        Some(conn) =>
+            conn.get_local_ip(),
+        None =>
+            return tiny.tui.add_client_err_msg(
+                &format!("Can't send file: Not connected to server {}", serv_name),
+                &MsgTarget::CurrentTab,
+            ),
+    };
+    let ip = match ip {
+        Some(ip) =>
+            ip,
+        None =>
+            return tiny.tui.add_client_err_msg(
+                "Can't send file: Not connected",
+                &MsgTarget::CurrentTab,
+            ),
+    };
+    match tiny.transfers.send(serv_name, nick, Path::new(path), ip) {
+        Ok((id, args)) => {
+            let msg = format!(
+                "{}: waiting for the peer to connect",
+                tiny.transfers.get(id).unwrap().name()
+            );
+            super::show_transfer_msg(&mut tiny.tui, &msg, false);
+            if let Some(conn) = super::find_conn(&mut tiny.conns, serv_name) {
+                conn.ctcp_request(nick, "DCC", &args);
+            }
+        }
+        Err(err) =>
+            tiny.tui.add_client_err_msg(
+                &format!("Can't send {}: {}", path, err),
+                &MsgTarget::CurrentTab,
+            ),
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
 static HELP_CMD: Cmd = Cmd {
     name: "help",
     cmd_fn: help,
@@ -359,6 +630,43 @@ fn help(_: &str, _: &Poll, _: &mut Tiny, _: MsgSource) {
 
 ////////////////////////////////////////////////////////////////////////////////////////////////////
 
+static FRIENDS_CMD: Cmd = Cmd {
+    name: "friends",
+    cmd_fn: friends,
+};
+
+fn friends(_: &str, _: &Poll, tiny: &mut Tiny, _: MsgSource) {
+    let mut lines = vec![];
+    for conn in &tiny.conns {
+        let friends = conn.get_friends();
+        if friends.get_nicks().is_empty() {
+            continue;
+        }
+        let online = friends.get_online();
+        if online.is_empty() {
+            lines.push(format!("{}: no friends online", conn.get_serv_name()));
+        } else {
+            lines.push(format!(
+                "{}: {} online: {}",
+                conn.get_serv_name(),
+                online.len(),
+                online.join(", ")
+            ));
+        }
+    }
+    if lines.is_empty() {
+        return tiny.tui.add_client_err_msg(
+            "No friends. Friends are configured in the `friends` server config field",
+            &MsgTarget::CurrentTab,
+        );
+    }
+    for line in lines {
+        tiny.tui.add_client_msg(&line, &MsgTarget::CurrentTab);
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
 static IGNORE_CMD: Cmd = Cmd {
     name: "ignore",
     cmd_fn: ignore,
@@ -386,6 +694,12 @@ fn ignore(_: &str, _: &Poll, tiny: &mut Tiny, src: MsgSource) {
                 nick: &nick,
             });
         }
+        MsgSource::Dcc { serv_name, nick } => {
+            tiny.tui.toggle_ignore(&MsgTarget::Dcc {
+                serv_name: &serv_name,
+                nick: &nick,
+            });
+        }
     }
 }
 
@@ -398,14 +712,21 @@ static JOIN_CMD: Cmd = Cmd {
 
 fn join(args: &str, _: &Poll, tiny: &mut Tiny, src: MsgSource) {
     let words = args.split_whitespace().collect::<Vec<_>>();
-    if words.is_empty() {
+    if words.is_empty() || words.len() > 2 {
         return tiny.tui.add_client_err_msg(
-            "/join usage: /join chan1[,chan2...]", &MsgTarget::CurrentTab);
+            "/join usage: /join chan1[,chan2...] [key1[,key2...]]", &MsgTarget::CurrentTab);
     }
+    let chans = words[0].split(',').collect::<Vec<_>>();
+    let keys = match words.get(1) {
+        Some(keys) =>
+            keys.split(',').collect::<Vec<_>>(),
+        None =>
+            vec![],
+    };
 
     match super::find_conn(&mut tiny.conns, src.serv_name()) {
         Some(conn) =>
-            conn.join(&words),
+            conn.join(&chans, &keys),
         None =>
             tiny.tui.add_client_err_msg(
                 &format!("Can't JOIN: Not connected to server {}", src.serv_name()),
@@ -455,9 +776,21 @@ fn msg(args: &str, _: &Poll, tiny: &mut Tiny, src: MsgSource) {
             }
         } else {
             let serv = src.serv_name();
-            MsgSource::User {
-                serv_name: serv.to_owned(),
-                nick: target.to_owned(),
+            let is_chan = tiny.conns
+                .iter()
+                .find(|conn| conn.get_serv_name() == serv)
+                .map(|conn| conn.is_chan_name(target))
+                .unwrap_or(false);
+            if is_chan {
+                MsgSource::Chan {
+                    serv_name: serv.to_owned(),
+                    chan_name: target.to_owned(),
+                }
+            } else {
+                MsgSource::User {
+                    serv_name: serv.to_owned(),
+                    nick: target.to_owned(),
+                }
             }
         };
         tiny.send_msg(source, msg, false);
@@ -491,14 +824,40 @@ fn names(args: &str, _: &Poll, tiny: &mut Tiny, src: MsgSource) {
                 chan_name: chan_name,
             };
             if words.is_empty() {
+                let names: Vec<String> = nicks_vec
+                    .iter()
+                    .map(|nick| match tiny.tui.get_member(serv_name, chan_name, nick) {
+                        Some(member) =>
+                            format!(
+                                "{}{}{}",
+                                member.prefix,
+                                nick,
+                                if member.away { " (away)" } else { "" }
+                            ),
+                        None =>
+                            nick.clone(),
+                    })
+                    .collect();
                 tiny.tui.add_client_msg(
-                    &format!("{} users: {}", nicks_vec.len(), nicks_vec.join(", ")),
+                    &format!("{} users: {}", names.len(), names.join(", ")),
                     &target,
                 );
+                if let Some(modes) = tiny.tui.get_chan_modes(serv_name, chan_name) {
+                    if !modes.is_empty() {
+                        tiny.tui.add_client_msg(&format!("Channel modes: {}", modes), &target);
+                    }
+                }
             } else {
                 let nick = words[0];
                 if nicks_vec.iter().any(|v| v == nick) {
                     tiny.tui.add_client_msg(&format!("{} is online", nick), &target);
+                    let lines = tiny.tui
+                        .get_member(serv_name, chan_name, nick)
+                        .map(format_member)
+                        .unwrap_or_default();
+                    for line in lines {
+                        tiny.tui.add_client_msg(&line, &target);
+                    }
                 } else {
                     tiny.tui.add_client_msg(&format!("{} is not in the channel", nick), &target);
                 }
@@ -512,6 +871,28 @@ fn names(args: &str, _: &Poll, tiny: &mut Tiny, src: MsgSource) {
     }
 }
 
+/// Details of a channel member for `/names <nick>`. User information is only known after
+/// a WHO reply or, for users who joined after us, their JOIN.
+fn format_member(member: &Member) -> Vec<String> {
+    let mut lines = vec![];
+    if !member.prefix.is_empty() {
+        lines.push(format!("  prefix: {}", member.prefix));
+    }
+    if let Some(ref user) = member.user {
+        lines.push(format!("  user: {}", user));
+    }
+    if let Some(ref account) = member.account {
+        lines.push(format!("  account: {}", account));
+    }
+    if let Some(ref realname) = member.realname {
+        lines.push(format!("  realname: {}", realname));
+    }
+    if member.away {
+        lines.push("  is away".to_owned());
+    }
+    lines
+}
+
 ////////////////////////////////////////////////////////////////////////////////////////////////////
 
 static NICK_CMD: Cmd = Cmd {
@@ -537,6 +918,47 @@ fn nick(args: &str, _: &Poll, tiny: &mut Tiny, src: MsgSource) {
 
 ////////////////////////////////////////////////////////////////////////////////////////////////////
 
+static RECONNECT_CMD: Cmd = Cmd {
+    name: "reconnect",
+    cmd_fn: reconnect,
+};
+
+fn reconnect(args: &str, _: &Poll, tiny: &mut Tiny, src: MsgSource) {
+    match args.trim() {
+        "" =>
+            reconnect_(tiny, src),
+        "cancel" =>
+            match super::find_conn(&mut tiny.conns, src.serv_name()) {
+                Some(conn) =>
+                    if conn.cancel_reconnect() {
+                        tiny.tui.add_client_msg(
+                            "Reconnect cancelled. Use `/connect` to connect again.",
+                            &MsgTarget::AllServTabs {
+                                serv_name: conn.get_serv_name(),
+                            },
+                        );
+                    } else {
+                        tiny.tui.add_client_err_msg(
+                            "Not waiting to reconnect",
+                            &MsgTarget::CurrentTab,
+                        );
+                    },
+                None =>
+                    tiny.tui.add_client_err_msg(
+                        &format!("Not connected to server {}", src.serv_name()),
+                        &MsgTarget::CurrentTab,
+                    ),
+            },
+        _ =>
+            tiny.tui.add_client_err_msg(
+                "/reconnect usage: /reconnect (to reconnect now) or /reconnect cancel",
+                &MsgTarget::CurrentTab,
+            ),
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
 static RELOAD_CMD: Cmd = Cmd {
     name: "reload",
     cmd_fn: reload,
@@ -632,6 +1054,8 @@ fn notify(args: &str, _: &Poll, tiny: &mut Tiny, src: MsgSource) {
                     MsgTarget::Chan { serv_name, chan_name },
                 MsgSource::User { ref serv_name, ref nick } =>
                     MsgTarget::User { serv_name, nick },
+                MsgSource::Dcc { ref serv_name, ref nick } =>
+                    MsgTarget::Dcc { serv_name, nick },
             };
         tiny.tui.set_notifier(notifier, &tab_target);
     }
@@ -639,6 +1063,38 @@ fn notify(args: &str, _: &Poll, tiny: &mut Tiny, src: MsgSource) {
 
 ////////////////////////////////////////////////////////////////////////////////////////////////////
 
+static WHOIS_CMD: Cmd = Cmd {
+    name: "whois",
+    cmd_fn: whois,
+};
+
+fn whois(args: &str, _: &Poll, tiny: &mut Tiny, src: MsgSource) {
+    let words: Vec<&str> = args.split_whitespace().collect();
+    // in a privmsg tab the nick is optional
+    let nick = match (words.len(), &src) {
+        (1, _) =>
+            words[0],
+        (0, &MsgSource::User { ref nick, .. }) =>
+            nick,
+        _ =>
+            return tiny.tui.add_client_err_msg(
+                "/whois usage: /whois <nick>",
+                &MsgTarget::CurrentTab,
+            ),
+    };
+    match super::find_conn(&mut tiny.conns, src.serv_name()) {
+        Some(conn) =>
+            conn.whois(nick),
+        None =>
+            tiny.tui.add_client_err_msg(
+                &format!("Can't send WHOIS: Not connected to server {}", src.serv_name()),
+                &MsgTarget::CurrentTab,
+            ),
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
 #[cfg(test)]
 mod tests {
     use super::*;
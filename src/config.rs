@@ -22,6 +22,15 @@ pub struct Server {
     #[serde(default)]
     pub tls: bool,
 
+    /// Other addresses of the server (optional). When connecting to `addr` fails, these are tried
+    /// in order.
+    #[serde(default)]
+    pub alt_endpoints: Vec<Endpoint>,
+
+    /// TLS client certificate (optional). Used for CertFP and SASL EXTERNAL.
+    #[serde(default)]
+    pub tls_client_cert: Option<TlsClientCert>,
+
     /// Server password (optional)
     #[serde(default)]
     pub pass: Option<String>,
@@ -43,6 +52,195 @@ pub struct Server {
     /// Channels to automatically join. Any `/join` commands in `auto_cmds` will be moved here.
     #[serde(default)]
     pub join: Vec<String>,
+
+    /// IRCv3 capabilities to request during connection registration (e.g. `multi-prefix`).
+    /// Capabilities not supported by the server are ignored.
+    #[serde(default)]
+    pub caps: Vec<String>,
+
+    /// SASL authentication credentials (optional). `sasl` capability is requested automatically
+    /// when this is set.
+    #[serde(default)]
+    pub sasl: Option<SASLAuth>,
+
+    /// Rejoin a channel after being kicked. Disabled by default.
+    #[serde(default)]
+    pub auto_rejoin: bool,
+
+    /// How many seconds to wait before rejoining after a kick. Defaults to 5.
+    #[serde(default = "default_auto_rejoin_delay")]
+    pub auto_rejoin_delay: u8,
+
+    /// Nicks and channels to accept invites automatically from and to. Other invites can be
+    /// accepted with `/accept`.
+    #[serde(default)]
+    pub auto_accept_invites: Vec<String>,
+
+    /// Automatic replies to CTCP requests. All enabled by default.
+    #[serde(default)]
+    pub ctcp_replies: CtcpReplies,
+
+    /// Nicks to show online status of in the "friends" tab. Tracked with MONITOR when the server
+    /// supports it, with ISON otherwise.
+    #[serde(default)]
+    pub friends: Vec<String>,
+
+    /// Flood protection: how many lines can be sent at once. Defaults to 5.
+    #[serde(default = "default_send_burst")]
+    pub send_burst: u8,
+
+    /// Flood protection: milliseconds to wait between lines after a burst. Defaults to 2000, 0
+    /// disables flood protection.
+    #[serde(default = "default_send_interval_ms")]
+    pub send_interval_ms: u64,
+
+    /// Seconds to wait for a message from the server before sending a PING. Defaults to 60.
+    #[serde(default = "default_ping_interval")]
+    pub ping_interval: u16,
+
+    /// Seconds to wait for a reply to our PING before reconnecting. Defaults to 60.
+    #[serde(default = "default_pong_timeout")]
+    pub pong_timeout: u16,
+
+    /// Seconds to wait before reconnecting after a disconnect. Doubled (with some randomness)
+    /// after each failed attempt, up to `max_reconnect_delay`. Defaults to 30.
+    #[serde(default = "default_reconnect_delay")]
+    pub reconnect_delay: u16,
+
+    /// Defaults to 600.
+    #[serde(default = "default_max_reconnect_delay")]
+    pub max_reconnect_delay: u16,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct Endpoint {
+    pub addr: String,
+
+    pub port: u16,
+
+    #[serde(default)]
+    pub tls: bool,
+}
+
+pub fn default_auto_rejoin_delay() -> u8 {
+    5
+}
+
+pub fn default_send_burst() -> u8 {
+    5
+}
+
+pub fn default_send_interval_ms() -> u64 {
+    2000
+}
+
+pub fn default_ping_interval() -> u16 {
+    60
+}
+
+pub fn default_pong_timeout() -> u16 {
+    60
+}
+
+pub fn default_reconnect_delay() -> u16 {
+    30
+}
+
+pub fn default_max_reconnect_delay() -> u16 {
+    600
+}
+
+#[derive(Clone, Deserialize)]
+pub struct CtcpReplies {
+    #[serde(default = "default_true")]
+    pub version: bool,
+    #[serde(default = "default_true")]
+    pub ping: bool,
+    #[serde(default = "default_true")]
+    pub time: bool,
+    #[serde(default = "default_true")]
+    pub clientinfo: bool,
+    #[serde(default = "default_true")]
+    pub source: bool,
+}
+
+impl Default for CtcpReplies {
+    fn default() -> CtcpReplies {
+        CtcpReplies {
+            version: true,
+            ping: true,
+            time: true,
+            clientinfo: true,
+            source: true,
+        }
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Clone, Deserialize)]
+pub struct TlsClientCert {
+    /// Path to a PKCS#12 archive with the certificate and the private key, or to a PEM
+    /// certificate when `key` is set.
+    pub path: String,
+
+    /// Path to the private key of a PEM certificate (optional). PEM certificates are only
+    /// supported with OpenSSL, i.e. not on macOS.
+    #[serde(default)]
+    pub key: Option<String>,
+
+    /// Password of the PKCS#12 archive
+    #[serde(default)]
+    pub password: String,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct SASLAuth {
+    /// `PLAIN` (default) or `EXTERNAL`. `EXTERNAL` authenticates with the TLS client certificate.
+    #[serde(default)]
+    pub mechanism: SASLMechanism,
+
+    /// Account name. Required for `PLAIN`. Optional for `EXTERNAL`, where it's sent as the
+    /// authorization identity.
+    #[serde(default)]
+    pub username: String,
+
+    /// Account password. Only used by `PLAIN`.
+    #[serde(default)]
+    pub password: String,
+
+    /// Close the connection when SASL authentication fails (or the server doesn't support SASL)
+    /// instead of registering without authentication. Disabled by default.
+    #[serde(default)]
+    pub abort_on_fail: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+pub enum SASLMechanism {
+    #[serde(rename = "PLAIN")]
+    Plain,
+    #[serde(rename = "EXTERNAL")]
+    External,
+}
+
+impl Default for SASLMechanism {
+    fn default() -> SASLMechanism {
+        SASLMechanism::Plain
+    }
+}
+
+impl SASLMechanism {
+    /// Name of the mechanism as sent in `AUTHENTICATE`.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            SASLMechanism::Plain =>
+                "PLAIN",
+            SASLMechanism::External =>
+                "EXTERNAL",
+        }
+    }
 }
 
 /// Similar to `Server`, but used when connecting via the `/connect` command.
@@ -56,6 +254,8 @@ pub struct Defaults {
     pub join: Vec<String>,
     #[serde(default)]
     pub tls: bool,
+    #[serde(default)]
+    pub caps: Vec<String>,
 }
 
 #[derive(Deserialize)]
@@ -64,6 +264,15 @@ pub struct Config {
     pub defaults: Defaults,
     #[serde(default)] pub colors: Colors,
     pub log_dir: String,
+    /// Where to save files received via DCC. Defaults to `~/Downloads`.
+    #[serde(default)]
+    pub download_dir: Option<String>,
+}
+
+pub fn get_default_download_dir() -> PathBuf {
+    let mut download_dir = home_dir().unwrap();
+    download_dir.push("Downloads");
+    download_dir
 }
 
 pub fn get_default_config_path() -> PathBuf {
@@ -99,6 +308,16 @@ fn parse_config_str(contents: &str) -> Result<Config, serde_yaml::Error> {
 
     parse_join_cmds(&mut cfg.defaults.auto_cmds, &mut cfg.defaults.join);
 
+    for server in &cfg.servers {
+        let uses_tls = server.tls || server.alt_endpoints.iter().any(|endpoint| endpoint.tls);
+        if server.tls_client_cert.is_some() && !uses_tls {
+            return Err(<serde_yaml::Error as de::Error>::custom(format!(
+                "{}: tls_client_cert is set but tls is not enabled",
+                server.addr
+            )));
+        }
+    }
+
     Ok(cfg)
 }
 
@@ -434,4 +653,121 @@ log_dir: path";
             }
         }
     }
+
+    #[test]
+    fn parse_sasl_config() {
+        let config = "\
+servers:
+    - addr: irc.oftc.net
+      port: 6697
+      tls: true
+      tls_client_cert:
+          path: /home/tiny/oftc.p12
+      sasl:
+          mechanism: EXTERNAL
+      hostname: yourhost
+      realname: yourname
+      nicks: [tiny_user]
+      auto_cmds: []
+    - addr: irc.mozilla.org
+      port: 6697
+      tls: true
+      sasl:
+          username: tiny_user
+          password: hunter2
+          abort_on_fail: true
+      hostname: yourhost
+      realname: yourname
+      nicks: [tiny_user]
+      auto_cmds: []
+
+defaults:
+    nicks: [tiny_user]
+    hostname: yourhost
+    realname: yourname
+    auto_cmds: []
+
+log_dir: path";
+        let cfg = parse_config_str(config).unwrap();
+
+        let cert = cfg.servers[0].tls_client_cert.as_ref().unwrap();
+        assert_eq!(cert.path, "/home/tiny/oftc.p12");
+        assert_eq!(cert.password, "");
+        let sasl = cfg.servers[0].sasl.as_ref().unwrap();
+        assert_eq!(sasl.mechanism, SASLMechanism::External);
+        assert!(!sasl.abort_on_fail);
+
+        assert!(cfg.servers[1].tls_client_cert.is_none());
+        let sasl = cfg.servers[1].sasl.as_ref().unwrap();
+        assert_eq!(sasl.mechanism, SASLMechanism::Plain);
+        assert_eq!(sasl.username, "tiny_user");
+        assert_eq!(sasl.password, "hunter2");
+        assert!(sasl.abort_on_fail);
+    }
+
+    #[test]
+    fn parse_pem_client_cert() {
+        let config = "\
+servers:
+    - addr: irc.oftc.net
+      port: 6697
+      tls: true
+      tls_client_cert:
+          path: /home/tiny/oftc.pem
+          key: /home/tiny/oftc.key
+      hostname: yourhost
+      realname: yourname
+      nicks: [tiny_user]
+      auto_cmds: []
+
+defaults:
+    nicks: [tiny_user]
+    hostname: yourhost
+    realname: yourname
+    auto_cmds: []
+
+log_dir: path";
+        let cfg = parse_config_str(config).unwrap();
+        let cert = cfg.servers[0].tls_client_cert.as_ref().unwrap();
+        assert_eq!(cert.path, "/home/tiny/oftc.pem");
+        assert_eq!(cert.key, Some("/home/tiny/oftc.key".to_owned()));
+
+        // client certificate without TLS
+        let config = config.replace("tls: true", "tls: false");
+        assert!(parse_config_str(&config).is_err());
+    }
+
+    #[test]
+    fn parse_alt_endpoints() {
+        let config = "\
+servers:
+    - addr: irc.mozilla.org
+      port: 6697
+      tls: true
+      alt_endpoints:
+          - addr: irc-ipv6.mozilla.org
+            port: 6697
+            tls: true
+          - addr: 63.245.208.218
+            port: 6667
+      hostname: yourhost
+      realname: yourname
+      nicks: [tiny_user]
+      auto_cmds: []
+
+defaults:
+    nicks: [tiny_user]
+    hostname: yourhost
+    realname: yourname
+    auto_cmds: []
+
+log_dir: path";
+        let cfg = parse_config_str(config).unwrap();
+        let endpoints = &cfg.servers[0].alt_endpoints;
+        assert_eq!(endpoints.len(), 2);
+        assert_eq!(endpoints[0].addr, "irc-ipv6.mozilla.org");
+        assert!(endpoints[0].tls);
+        assert_eq!(endpoints[1].port, 6667);
+        assert!(!endpoints[1].tls);
+    }
 }
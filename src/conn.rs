@@ -1,21 +1,53 @@
 use mio::Poll;
 use mio::Token;
+use std::collections::VecDeque;
 use std::io::Write;
+use std::net::{IpAddr, SocketAddr};
 use std::result;
 use std::str;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
+use chans::JoinedChans;
+use config::SASLMechanism;
 use config;
+use ctcp::{self, RateLimiter};
+use friends::Friends;
+use isupport::{CaseMapping, ISupport};
 use logger::LogFile;
 use logger::Logger;
+use mode;
+use resolver::Resolver;
+use send_queue::SendQueue;
 use utils;
-use wire::{Cmd, Msg, Pfx};
+use whois::{Whois, WhoisCollector};
+use wire::{Cmd, Msg, Pfx, Reply};
 use wire;
-use stream::{Stream, StreamErr};
+use stream::{ClientCert, Stream, StreamErr};
 
 pub struct Conn<'poll> {
     serv_addr: String,
-    serv_port: u16,
-    tls: bool,
+
+    /// `addr`, `port` and `tls` of the server config, followed by `alt_endpoints`.
+    endpoints: Vec<config::Endpoint>,
+
+    /// Index of the endpoint we're connected (or connecting) to.
+    endpoint_idx: usize,
+
+    /// Index of the endpoint to resolve when `sock_addrs` runs out.
+    next_endpoint_idx: usize,
+
+    /// Endpoints not resolved yet in the current connection attempt. When resolving or
+    /// connecting fails, the next endpoint is tried right away until this is 0.
+    endpoints_left: usize,
+
+    /// Resolved addresses of the current endpoint that we haven't tried yet.
+    sock_addrs: VecDeque<SocketAddr>,
+
+    /// Address we're connected (or connecting) to. `None` until the first connection attempt.
+    sock_addr: Option<SocketAddr>,
+
+    /// TLS client certificate, loaded from the path in the config
+    client_cert: Option<ClientCert>,
     hostname: String,
     realname: String,
 
@@ -27,13 +59,49 @@ pub struct Conn<'poll> {
     /// Always in range of `nicks`
     current_nick_idx: usize,
 
-    /// Channels to auto-join. Every channel we join will be added here to be able to re-join
-    /// automatically on reconnect and channels we leave will be removed.
-    auto_join: Vec<String>,
+    /// Channels to join after registration: channels in the config, then channels we join. Channels
+    /// we leave are removed.
+    chans: JoinedChans,
+
+    /// Ticks to wait before rejoining a channel we were kicked from. `None` when auto-rejoin is
+    /// disabled.
+    auto_rejoin_delay: Option<u8>,
+
+    /// Channels to rejoin after a kick, with ticks left before sending the JOIN.
+    rejoin_queue: Vec<(String, u8)>,
+
+    /// Nicks and channels to accept invites automatically from and to.
+    auto_accept_invites: Vec<String>,
+
+    /// Channel of the most recent invite that wasn't accepted automatically. `/accept` joins it.
+    last_invite: Option<String>,
+
+    /// Which CTCP requests to reply to automatically.
+    ctcp_replies: config::CtcpReplies,
+
+    /// Limits automatic CTCP replies, to avoid getting disconnected for flooding.
+    ctcp_limiter: RateLimiter,
 
     /// Away reason if away mode is on. `None` otherwise.
     away_status: Option<String>,
 
+    /// IRCv3 capabilities to request from the server (when available).
+    wanted_caps: Vec<String>,
+
+    /// Capabilities advertised by the server in `CAP LS` and `CAP NEW`, without values.
+    available_caps: Vec<String>,
+
+    /// Capabilities acknowledged by the server.
+    caps: Vec<String>,
+
+    cap_status: CapStatus,
+
+    /// SASL credentials. `sasl` is added to `wanted_caps` when this is set.
+    sasl_auth: Option<config::SASLAuth>,
+
+    /// Server features advertised in 005 RPL_ISUPPORT.
+    isupport: ISupport,
+
     /// servername to be used in PING messages. Read from 002 RPL_YOURHOST.
     /// `None` until 002.
     servername: Option<String>,
@@ -45,6 +113,43 @@ pub struct Conn<'poll> {
     /// don't parse USERHOST responses to set this field.
     usermask: Option<String>,
 
+    /// Our user modes, e.g. `iw`. Updated on MODE messages targeting us and 221 RPL_UMODEIS.
+    user_modes: String,
+
+    /// WHOIS replies received so far. `ConnEv::Whois` is generated on RPL_ENDOFWHOIS.
+    whois: WhoisCollector,
+
+    /// Ticks passed since the last periodic WHO query.
+    who_refresh_ticks: u16,
+
+    /// Index of the `chans` channel to send the next periodic WHO query for.
+    who_refresh_idx: usize,
+
+    friends: Friends,
+
+    /// Ticks passed since the last ISON query. `None` when we don't poll with ISON: before
+    /// registration, when we don't have friends, or when the server supports MONITOR.
+    ison_ticks: Option<u8>,
+
+    /// Ticks to wait for a message from the server before sending a PING.
+    ping_ticks: u16,
+
+    /// Ticks to wait for a reply to our PING before reconnecting.
+    pong_ticks: u16,
+
+    /// Ticks to wait before the first reconnect attempt.
+    reconnect_delay: u16,
+
+    /// Max ticks to wait between reconnect attempts.
+    max_reconnect_delay: u16,
+
+    /// Failed reconnect attempts since we were last registered. Used for exponential backoff.
+    reconnect_attempts: u32,
+
+    /// Flood-protected queue for outgoing messages. Registration messages, PONG and QUIT are
+    /// written to the stream directly.
+    send_queue: SendQueue,
+
     poll: &'poll Poll,
 
     status: ConnStatus<'poll>,
@@ -55,37 +160,63 @@ pub struct Conn<'poll> {
 
 pub type ConnErr = StreamErr;
 
-/// How many ticks to wait before sending a ping to the server.
-const PING_TICKS: u8 = 60;
-/// How many ticks to wait after sending a ping to the server to consider a
-/// disconnect.
-const PONG_TICKS: u8 = 60;
-/// How many ticks to wait after a disconnect or a socket error.
-pub const RECONNECT_TICKS: u8 = 30;
+/// How many ticks to wait between periodic WHO queries. Channels are refreshed one at a time, so
+/// with N channels each channel is refreshed every N * WHO_REFRESH_TICKS ticks.
+const WHO_REFRESH_TICKS: u16 = 120;
+
+/// How many ticks to wait between ISON queries.
+const ISON_TICKS: u8 = 60;
+
+/// Max number of automatic CTCP replies in `CTCP_REPLY_WINDOW_SECS` seconds.
+const CTCP_REPLY_LIMIT: usize = 3;
+const CTCP_REPLY_WINDOW_SECS: u64 = 10;
 
 enum ConnStatus<'poll> {
     PingPong {
         /// Ticks passed since last time we've heard from the server. Reset on
-        /// each message. After `PING_TICKS` ticks we send a PING message and
+        /// each message. After `Conn::ping_ticks` ticks we send a PING message and
         /// move to `WaitPong` state.
-        ticks_passed: u8,
+        ticks_passed: u16,
         stream: Stream<'poll>,
     },
     WaitPong {
         /// Ticks passed since we sent a PING to the server. After a message
         /// move to `PingPong` state. On timeout we reset the connection.
-        ticks_passed: u8,
+        ticks_passed: u16,
         stream: Stream<'poll>,
     },
+    /// Resolving the address of the current endpoint in a worker thread. `Conn::read_ready`
+    /// connects when the resolver's token is ready.
+    Resolving { resolver: Resolver },
     Disconnected {
-        ticks_passed: u8,
+        /// Ticks left before reconnecting. `ConnEv::WantReconnect` is generated when this reaches
+        /// 0.
+        ticks_left: u16,
     },
+    /// Connection closed on purpose (e.g. SASL authentication failed and the server is configured
+    /// with `abort_on_fail`). We don't reconnect automatically in this state, `/connect` does.
+    Aborted,
+}
+
+/// State of IRCv3 capability negotiation. Registration is suspended by the server until we send
+/// `CAP END`, servers that don't know about `CAP` register us right away.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum CapStatus {
+    /// Sent `CAP LS 302`, waiting for the (possibly multi-line) list of capabilities.
+    Listing,
+    /// Sent `CAP REQ`, waiting for `ACK` or `NAK`.
+    Requesting,
+    /// Sent `AUTHENTICATE`, `CAP END` is sent after SASL authentication succeeds or fails.
+    Authenticating,
+    /// Sent `CAP END`, or the server doesn't support capability negotiation. `CAP NEW` and `CAP
+    /// DEL` are still handled in this state.
+    Done,
 }
 
 macro_rules! update_status {
     ($self:ident, $v:ident, $code:expr) => {{
         // temporarily putting `Disconnected` to `self.status`
-        let $v = ::std::mem::replace(&mut $self.status, ConnStatus::Disconnected { ticks_passed: 0 });
+        let $v = ::std::mem::replace(&mut $self.status, ConnStatus::Disconnected { ticks_left: 0 });
         let new_status = $code;
         $self.status = new_status;
     }}
@@ -98,7 +229,7 @@ impl<'poll> ConnStatus<'poll> {
             PingPong { ref stream, .. }
             | WaitPong { ref stream, .. } =>
                 Some(stream),
-            Disconnected { .. } =>
+            Resolving { .. } | Disconnected { .. } | Aborted =>
                 None,
         }
     }
@@ -109,7 +240,7 @@ impl<'poll> ConnStatus<'poll> {
             PingPong { ref mut stream, .. }
             | WaitPong { ref mut stream, .. } =>
                 Some(stream),
-            Disconnected { .. } =>
+            Resolving { .. } | Disconnected { .. } | Aborted =>
                 None,
         }
     }
@@ -131,9 +262,16 @@ pub enum ConnEv {
     Msg(Msg),
     /// Nick changed
     NickChange(String),
+    /// Connection closed and won't be re-established automatically. Argument is the reason.
+    Aborted(String),
+    /// All WHOIS replies for a nick received
+    Whois(Whois),
+    /// A friend came online (`true`) or went offline (`false`)
+    Friend { nick: String, online: bool },
 }
 
 fn introduce<W: Write>(stream: &mut W, pass: Option<&str>, hostname: &str, realname: &str, nick: &str) {
+    wire::cap_ls(stream).unwrap();
     if let Some(pass) = pass {
         wire::pass(stream, pass).unwrap();
     }
@@ -143,73 +281,230 @@ fn introduce<W: Write>(stream: &mut W, pass: Option<&str>, hostname: &str, realn
 
 impl<'poll> Conn<'poll> {
     pub fn new(server: config::Server, poll: &'poll Poll) -> Result<Conn<'poll>> {
-        let mut stream =
-            Stream::new(poll, &server.addr, server.port, server.tls).map_err(StreamErr::from)?;
-
-        introduce(
-            &mut stream,
-            server.pass.as_ref().map(String::as_str),
-            &server.hostname,
-            &server.realname,
-            &server.nicks[0],
-        );
+        let client_cert = match server.tls_client_cert {
+            None =>
+                None,
+            Some(config::TlsClientCert {
+                ref path,
+                key: Some(ref key),
+                ..
+            }) =>
+                Some(ClientCert::from_pem_files(path, key)?),
+            Some(ref cert) =>
+                Some(ClientCert::from_file(&cert.path, &cert.password)?),
+        };
+
+        let mut endpoints = vec![
+            config::Endpoint {
+                addr: server.addr.clone(),
+                port: server.port,
+                tls: server.tls,
+            },
+        ];
+        endpoints.extend(server.alt_endpoints);
+
+        let mut wanted_caps = server.caps;
+        // server-time is always requested, used for message timestamps
+        if !wanted_caps.iter().any(|cap| cap == "server-time") {
+            wanted_caps.push("server-time".to_owned());
+        }
+        if server.sasl.is_some() && !wanted_caps.iter().any(|cap| cap == "sasl") {
+            wanted_caps.push("sasl".to_owned());
+        }
 
-        Ok(Conn {
+        let mut conn = Conn {
             serv_addr: server.addr,
-            serv_port: server.port,
-            tls: server.tls,
+            endpoints,
+            endpoint_idx: 0,
+            next_endpoint_idx: 0,
+            endpoints_left: 0,
+            sock_addrs: VecDeque::new(),
+            sock_addr: None,
+            client_cert,
             hostname: server.hostname,
             realname: server.realname,
             pass: server.pass,
             nicks: server.nicks,
             current_nick_idx: 0,
-            auto_join: server.join,
+            chans: JoinedChans::new(server.join),
+            auto_rejoin_delay: if server.auto_rejoin {
+                Some(server.auto_rejoin_delay)
+            } else {
+                None
+            },
+            rejoin_queue: vec![],
+            auto_accept_invites: server.auto_accept_invites,
+            last_invite: None,
+            ctcp_replies: server.ctcp_replies,
+            ctcp_limiter: RateLimiter::new(
+                CTCP_REPLY_LIMIT,
+                Duration::from_secs(CTCP_REPLY_WINDOW_SECS),
+            ),
             away_status: None,
+            wanted_caps,
+            available_caps: vec![],
+            caps: vec![],
+            cap_status: CapStatus::Listing,
+            sasl_auth: server.sasl,
+            isupport: ISupport::default(),
             servername: None,
             usermask: None,
+            user_modes: String::new(),
+            whois: WhoisCollector::new(),
+            who_refresh_ticks: 0,
+            who_refresh_idx: 0,
+            friends: Friends::new(server.friends),
+            ison_ticks: None,
+            ping_ticks: server.ping_interval,
+            pong_ticks: server.pong_timeout,
+            reconnect_delay: server.reconnect_delay,
+            max_reconnect_delay: server.max_reconnect_delay,
+            reconnect_attempts: 0,
+            send_queue: SendQueue::new(
+                server.send_burst,
+                Duration::from_millis(server.send_interval_ms),
+                Instant::now(),
+            ),
             poll,
-            status: ConnStatus::PingPong {
-                ticks_passed: 0,
-                stream: stream,
-            },
+            status: ConnStatus::Disconnected { ticks_left: 0 },
             in_buf: vec![],
-        })
+        };
+
+        conn.endpoints_left = conn.endpoints.len();
+        conn.connect()?;
+        Ok(conn)
+    }
+
+    /// Connect to the next resolved address of the current endpoint. When there are none left,
+    /// start resolving the next endpoint; `read_ready` connects when the addresses are ready.
+    /// Returns the last error if all endpoints of the attempt failed.
+    fn connect(&mut self) -> Result<()> {
+        let mut last_err = None;
+        while let Some(addr) = self.sock_addrs.pop_front() {
+            let endpoint = &self.endpoints[self.endpoint_idx];
+            match Stream::new(
+                self.poll,
+                &endpoint.addr,
+                addr,
+                endpoint.tls,
+                self.client_cert.as_ref(),
+            ) {
+                Ok(mut stream) => {
+                    introduce(
+                        &mut stream,
+                        self.pass.as_ref().map(String::as_str),
+                        &self.hostname,
+                        &self.realname,
+                        self.get_nick(),
+                    );
+                    self.sock_addr = Some(addr);
+                    self.status = ConnStatus::PingPong {
+                        ticks_passed: 0,
+                        stream: stream,
+                    };
+                    return Ok(());
+                }
+                Err(err) => {
+                    last_err = Some(err);
+                }
+            }
+        }
+        if self.endpoints_left == 0 {
+            return Err(last_err.unwrap_or(StreamErr::CantResolveAddr));
+        }
+        self.resolve_next_endpoint();
+        Ok(())
+    }
+
+    fn resolve_next_endpoint(&mut self) {
+        self.endpoints_left -= 1;
+        self.endpoint_idx = self.next_endpoint_idx;
+        self.next_endpoint_idx = (self.endpoint_idx + 1) % self.endpoints.len();
+        let resolver = {
+            let endpoint = &self.endpoints[self.endpoint_idx];
+            Resolver::new(self.poll, &endpoint.addr, endpoint.port)
+        };
+        self.status = ConnStatus::Resolving { resolver };
+    }
+
+    /// Connect to the resolved addresses of the current endpoint, if they're ready.
+    fn resolve_ready(&mut self, evs: &mut Vec<ConnEv>) {
+        let result = match self.status {
+            ConnStatus::Resolving { ref resolver } =>
+                resolver.get_result(),
+            _ =>
+                None,
+        };
+        match result {
+            None =>
+                {}
+            Some(Ok(addrs)) => {
+                self.sock_addrs.extend(addrs);
+                if let Err(err) = self.connect() {
+                    evs.push(ConnEv::Err(err));
+                }
+            }
+            Some(Err(err)) =>
+                if self.endpoints_left == 0 {
+                    evs.push(ConnEv::Err(err));
+                } else {
+                    self.resolve_next_endpoint();
+                },
+        }
     }
 
     pub fn reconnect(&mut self, new_serv: Option<(&str, u16)>) -> Result<()> {
         // drop existing connection first
         let old_stream = ::std::mem::replace(
             &mut self.status,
-            ConnStatus::Disconnected { ticks_passed: 0 },
+            ConnStatus::Disconnected { ticks_left: 0 },
         );
         drop(old_stream);
 
         if let Some((new_name, new_port)) = new_serv {
+            let tls = self.endpoints[self.endpoint_idx].tls;
             self.serv_addr = new_name.to_owned();
-            self.serv_port = new_port;
-        }
-        match Stream::new(self.poll, &self.serv_addr, self.serv_port, self.tls) {
-            Err(err) =>
-                Err(StreamErr::from(err)),
-            Ok(mut stream) => {
-                introduce(
-                    &mut stream,
-                    self.pass.as_ref().map(String::as_str),
-                    &self.hostname,
-                    &self.realname,
-                    self.get_nick()
-                );
-                self.status = ConnStatus::PingPong {
-                    ticks_passed: 0,
-                    stream: stream,
-                };
-                self.current_nick_idx = 0;
-                Ok(())
+            self.endpoints = vec![
+                config::Endpoint {
+                    addr: new_name.to_owned(),
+                    port: new_port,
+                    tls,
+                },
+            ];
+            self.endpoint_idx = 0;
+            self.next_endpoint_idx = 0;
+            self.sock_addrs.clear();
+        }
+        self.current_nick_idx = 0;
+        self.available_caps.clear();
+        self.caps.clear();
+        self.cap_status = CapStatus::Listing;
+        self.isupport = ISupport::default();
+        self.user_modes.clear();
+        // channels are rejoined via `chans`
+        self.rejoin_queue.clear();
+        self.last_invite = None;
+        self.whois.clear();
+        self.who_refresh_ticks = 0;
+        self.who_refresh_idx = 0;
+        self.friends.reconnect();
+        self.ison_ticks = None;
+        self.send_queue.clear(Instant::now());
+        self.endpoints_left = self.endpoints.len();
+        match self.connect() {
+            Err(err) => {
+                self.enter_disconnect_state();
+                Err(err)
             }
+            Ok(()) =>
+                Ok(()),
         }
     }
 
     pub fn get_conn_tok(&self) -> Option<Token> {
+        if let ConnStatus::Resolving { ref resolver } = self.status {
+            return Some(resolver.get_tok());
+        }
         self.status.get_stream().map(|s| s.get_tok())
     }
 
@@ -217,10 +512,75 @@ impl<'poll> Conn<'poll> {
         &self.serv_addr
     }
 
+    /// The endpoint we're connected (or connecting) to, e.g. `irc.a.net:6697 (1.2.3.4:6697,
+    /// TLS)`.
+    pub fn get_endpoint(&self) -> String {
+        let endpoint = &self.endpoints[self.endpoint_idx];
+        let mut ret = format!("{}:{}", endpoint.addr, endpoint.port);
+        if let Some(addr) = self.sock_addr {
+            ret.push_str(&format!(" ({}{})", addr, if endpoint.tls { ", TLS" } else { "" }));
+        }
+        ret
+    }
+
     pub fn get_nick(&self) -> &str {
         &self.nicks[self.current_nick_idx]
     }
 
+    /// Server features advertised in RPL_ISUPPORT. Defaults until the server sends 005.
+    pub fn get_isupport(&self) -> &ISupport {
+        &self.isupport
+    }
+
+    /// Seconds to wait before rejoining a channel after a kick, if auto-rejoin is enabled.
+    pub fn get_auto_rejoin_delay(&self) -> Option<u8> {
+        self.auto_rejoin_delay
+    }
+
+    /// Are invites from this nick or to this channel accepted automatically?
+    pub fn is_invite_trusted(&self, nick: &str, chan: &str) -> bool {
+        let casemapping = self.isupport.casemapping;
+        self.auto_accept_invites
+            .iter()
+            .any(|trusted| casemapping.eq(trusted, nick) || casemapping.eq(trusted, chan))
+    }
+
+    /// Join the channel of the most recent invite. Returns the channel, or `None` if there are
+    /// no pending invites.
+    pub fn accept_invite(&mut self) -> Option<String> {
+        let chan = self.last_invite.take()?;
+        self.join(&[&chan], &[]);
+        Some(chan)
+    }
+
+    /// Our user modes, without the `+`.
+    pub fn get_user_modes(&self) -> &str {
+        &self.user_modes
+    }
+
+    pub fn get_friends(&self) -> &Friends {
+        &self.friends
+    }
+
+    pub fn get_casemapping(&self) -> CaseMapping {
+        self.isupport.casemapping
+    }
+
+    /// Is this our current nick? Compared using the server's casemapping.
+    pub fn is_our_nick(&self, nick: &str) -> bool {
+        self.isupport.casemapping.eq(nick, self.get_nick())
+    }
+
+    /// Is the name a channel name on this server?
+    pub fn is_chan_name(&self, name: &str) -> bool {
+        self.isupport.is_chan_name(name)
+    }
+
+    /// Is the capability enabled by the server?
+    pub fn has_cap(&self, cap: &str) -> bool {
+        self.caps.iter().any(|cap_| cap_ == cap)
+    }
+
     pub fn set_nick(&mut self, nick: &str) {
         if let Some(nick_idx) = self.nicks.iter().position(|n| n == nick) {
             self.current_nick_idx = nick_idx;
@@ -242,14 +602,76 @@ impl<'poll> Conn<'poll> {
 }
 
 impl<'poll> Conn<'poll> {
+    /// Close the connection and schedule a reconnect. The delay is doubled after each failed
+    /// attempt. Does nothing if a reconnect is already scheduled (a failed attempt may report
+    /// several errors) or the connection was aborted.
     pub fn enter_disconnect_state(&mut self) {
-        self.status = ConnStatus::Disconnected { ticks_passed: 0 };
+        match self.status {
+            ConnStatus::Disconnected { ticks_left } if ticks_left != 0 =>
+                return,
+            ConnStatus::Aborted =>
+                return,
+            _ =>
+                {}
+        }
+        let ticks_left = self.next_attempt_delay();
+        self.status = ConnStatus::Disconnected { ticks_left };
+    }
+
+    /// Other addresses of the host (e.g. IPv4 after IPv6) are tried right away, other endpoints
+    /// after the usual reconnect delay.
+    fn next_attempt_delay(&mut self) -> u16 {
+        if self.sock_addrs.is_empty() {
+            self.next_reconnect_delay()
+        } else {
+            1
+        }
+    }
+
+    fn next_reconnect_delay(&mut self) -> u16 {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.subsec_nanos())
+            .unwrap_or(0);
+        let delay = backoff_delay(
+            self.reconnect_delay,
+            self.max_reconnect_delay,
+            self.reconnect_attempts,
+            nanos,
+        );
+        self.reconnect_attempts = self.reconnect_attempts.saturating_add(1);
+        delay
+    }
+
+    /// Seconds left before reconnecting. `None` if we're not waiting to reconnect.
+    pub fn get_reconnect_secs(&self) -> Option<u16> {
+        match self.status {
+            ConnStatus::Disconnected { ticks_left } if ticks_left != 0 =>
+                Some(ticks_left),
+            _ =>
+                None,
+        }
+    }
+
+    /// Stop waiting to reconnect. `/connect` can be used to connect again. Returns `false` if
+    /// we're not waiting to reconnect.
+    pub fn cancel_reconnect(&mut self) -> bool {
+        if self.get_reconnect_secs().is_some() {
+            self.status = ConnStatus::Aborted;
+            true
+        } else {
+            false
+        }
     }
 
     ////////////////////////////////////////////////////////////////////////////
     // Tick handling
 
     pub fn tick(&mut self, evs: &mut Vec<ConnEv>, mut debug_out: LogFile) {
+        self.tick_rejoin();
+        self.tick_who_refresh();
+        self.tick_ison();
+        self.flush_send_queue();
         update_status!(
             self,
             status,
@@ -259,7 +681,7 @@ impl<'poll> Conn<'poll> {
                     ticks_passed,
                 } => {
                     let ticks = ticks_passed + 1;
-                    if ticks == PING_TICKS {
+                    if ticks >= self.ping_ticks {
                         match self.servername {
                             None => {
                                 debug_out.write_line(format_args!(
@@ -291,9 +713,11 @@ impl<'poll> Conn<'poll> {
                     ticks_passed,
                 } => {
                     let ticks = ticks_passed + 1;
-                    if ticks == PONG_TICKS {
+                    if ticks >= self.pong_ticks {
                         evs.push(ConnEv::Disconnected);
-                        ConnStatus::Disconnected { ticks_passed: 0 }
+                        ConnStatus::Disconnected {
+                            ticks_left: self.next_attempt_delay(),
+                        }
                     } else {
                         ConnStatus::WaitPong {
                             stream,
@@ -301,22 +725,41 @@ impl<'poll> Conn<'poll> {
                         }
                     }
                 }
-                ConnStatus::Disconnected { ticks_passed } => {
-                    let ticks = ticks_passed + 1;
-                    if ticks_passed + 1 == RECONNECT_TICKS {
+                ConnStatus::Resolving { resolver } =>
+                    ConnStatus::Resolving { resolver },
+                ConnStatus::Aborted =>
+                    ConnStatus::Aborted,
+                ConnStatus::Disconnected { ticks_left } => {
+                    if ticks_left == 1 {
                         // *sigh* it's slightly annoying that we can't reconnect here, we need to
                         // update the event loop
                         evs.push(ConnEv::WantReconnect);
                         self.current_nick_idx = 0;
                     }
                     ConnStatus::Disconnected {
-                        ticks_passed: ticks,
+                        ticks_left: ticks_left.saturating_sub(1),
                     }
                 }
             }
         );
     }
 
+    fn tick_rejoin(&mut self) {
+        if self.rejoin_queue.is_empty() {
+            return;
+        }
+        for &mut (_, ref mut ticks) in &mut self.rejoin_queue {
+            *ticks = ticks.saturating_sub(1);
+        }
+        let chans: Vec<String> = self.rejoin_queue
+            .drain_filter(|&mut (_, ticks)| ticks == 0)
+            .map(|(chan, _)| chan)
+            .collect();
+        if !chans.is_empty() {
+            self.rejoin(&chans);
+        }
+    }
+
     fn reset_ticks(&mut self) {
         update_status!(
             self,
@@ -328,7 +771,9 @@ impl<'poll> Conn<'poll> {
                     // no bug: we heard something from the server, whether it was a pong or not
                     // doesn't matter that much, connectivity is fine.
                     ConnStatus::PingPong { ticks_passed: 0, stream },
-                ConnStatus::Disconnected { .. } =>
+                ConnStatus::Resolving { .. }
+                | ConnStatus::Disconnected { .. }
+                | ConnStatus::Aborted =>
                     status,
             }
         );
@@ -337,6 +782,39 @@ impl<'poll> Conn<'poll> {
     ////////////////////////////////////////////////////////////////////////////
     // Sending messages
 
+    /// Add a message to the send queue. `write` writes the message to the given buffer. Messages
+    /// are dropped when we're disconnected.
+    fn send<F>(&mut self, write: F)
+    where
+        F: FnOnce(&mut Vec<u8>) -> ::std::io::Result<()>,
+    {
+        if self.status.get_stream().is_none() {
+            return;
+        }
+        let mut line = vec![];
+        write(&mut line).unwrap();
+        self.send_queue.push(line);
+        self.flush_send_queue();
+    }
+
+    /// Write messages allowed by flood protection to the stream.
+    fn flush_send_queue(&mut self) {
+        if self.send_queue.len() == 0 {
+            return;
+        }
+        let lines = self.send_queue.pop_ready(Instant::now());
+        self.status.get_stream_mut().map(|stream| {
+            for line in lines {
+                stream.write_all(&line).unwrap();
+            }
+        });
+    }
+
+    /// Number of messages waiting in the send queue.
+    pub fn get_queued_lines(&self) -> usize {
+        self.send_queue.len()
+    }
+
     fn send_nick(&mut self) {
         let nick = &self.nicks[self.current_nick_idx];
         self.status.get_stream_mut().map(|stream| {
@@ -375,50 +853,141 @@ impl<'poll> Conn<'poll> {
     // to fit into 512 bytes. Need to make sure `split_privmsg` is called before
     // this.
     pub fn privmsg(&mut self, target: &str, msg: &str) {
-        self.status.get_stream_mut().map(|stream| {
-            wire::privmsg(stream, target, msg).unwrap();
-        });
+        self.send(|buf| wire::privmsg(buf, target, msg));
     }
 
     pub fn ctcp_action(&mut self, target: &str, msg: &str) {
-        self.status.get_stream_mut().map(|stream| {
-            wire::ctcp_action(stream, target, msg).unwrap();
-        });
+        self.send(|buf| wire::ctcp_action(buf, target, msg));
     }
 
-    pub fn join(&mut self, chans: &[&str]) {
-        self.status.get_stream_mut().map(|stream| {
-            wire::join(stream, chans).unwrap();
-        });
-        // the channel will be added to auto-join list on successful join (i.e.
-        // after RPL_TOPIC)
+    /// Refresh member information of one channel (away status changes are not sent to us).
+    fn tick_who_refresh(&mut self) {
+        self.who_refresh_ticks += 1;
+        if self.who_refresh_ticks < WHO_REFRESH_TICKS {
+            return;
+        }
+        self.who_refresh_ticks = 0;
+        if self.chans.is_empty() {
+            return;
+        }
+        self.who_refresh_idx = (self.who_refresh_idx + 1) % self.chans.len();
+        let chan = self.chans.get_name(self.who_refresh_idx).to_owned();
+        self.who_chan(&chan);
+    }
+
+    fn tick_ison(&mut self) {
+        let ticks = match self.ison_ticks {
+            None =>
+                return,
+            Some(ticks) =>
+                ticks + 1,
+        };
+        if ticks < ISON_TICKS {
+            self.ison_ticks = Some(ticks);
+        } else {
+            self.ison_ticks = Some(0);
+            self.send_ison();
+        }
+    }
+
+    fn send_ison(&mut self) {
+        if self.status.get_stream().is_none() {
+            return;
+        }
+        let nicks = self.friends.get_nicks().to_vec();
+        self.send(|buf| wire::ison(buf, &nicks.iter().map(String::as_str).collect::<Vec<_>>()));
+        self.friends.ison_sent();
+    }
+
+    /// Start tracking friends after registration.
+    fn track_friends(&mut self) {
+        if self.friends.get_nicks().is_empty() {
+            return;
+        }
+        if self.isupport.get_other("MONITOR").is_some() {
+            let nicks = self.friends.get_nicks().to_vec();
+            self.send(|buf| {
+                wire::monitor_add(buf, &nicks.iter().map(String::as_str).collect::<Vec<_>>())
+            });
+        } else {
+            self.ison_ticks = Some(0);
+            self.send_ison();
+        }
+    }
+
+    /// `keys[i]` is the key of `chans[i]`. Channels are added to `chans` (with the keys) when we
+    /// receive our JOIN.
+    pub fn join(&mut self, chans: &[&str], keys: &[&str]) {
+        let casemapping = self.isupport.casemapping;
+        for (chan, key) in chans.iter().zip(keys.iter()) {
+            self.chans.set_pending_key(casemapping, chan, key);
+        }
+        self.send(|buf| wire::join(buf, chans, keys));
     }
 
     pub fn part(&mut self, chan: &str) {
-        self.status.get_stream_mut().map(|stream| {
-            wire::part(stream, chan).unwrap();
+        self.send(|buf| wire::part(buf, chan));
+        // removed here too as the PART is lost if we're disconnected
+        let casemapping = self.isupport.casemapping;
+        self.chans.remove(casemapping, chan);
+        self.rejoin_queue.drain_filter(|&mut (ref chan_, _)| casemapping.eq(chan_, chan));
+    }
+
+    /// Join `chans`, with their keys.
+    fn rejoin(&mut self, chans: &[String]) {
+        let (chans, keys) = self.chans.join_params(self.isupport.casemapping, chans);
+        self.send(|buf| {
+            wire::join(
+                buf,
+                &chans.iter().map(String::as_str).collect::<Vec<&str>>(),
+                &keys.iter().map(String::as_str).collect::<Vec<&str>>(),
+            )
         });
-        self.auto_join.drain_filter(|chan_| chan_ == chan);
+    }
+
+    /// Our IP address on the server connection. `None` when disconnected.
+    pub fn get_local_ip(&self) -> Option<IpAddr> {
+        self.status
+            .get_stream()
+            .and_then(|stream| stream.local_addr().ok())
+            .map(|addr| addr.ip())
+    }
+
+    pub fn ctcp_request(&mut self, target: &str, cmd: &str, args: &str) {
+        self.send(|buf| wire::ctcp_request(buf, target, cmd, args));
+    }
+
+    pub fn whois(&mut self, nick: &str) {
+        self.send(|buf| wire::whois(buf, nick));
+    }
+
+    /// Request information of channel members. WHOX is used when available to get accounts.
+    pub fn who_chan(&mut self, chan: &str) {
+        let whox = self.isupport.get_other("WHOX").is_some();
+        self.send(|buf| wire::who(buf, chan, whox));
     }
 
     pub fn away(&mut self, msg: Option<&str>) {
         self.away_status = msg.map(|s| s.to_string());
-        self.status.get_stream_mut().map(|stream| {
-            wire::away(stream, msg).unwrap();
-        });
+        self.send(|buf| wire::away(buf, msg));
     }
 
     pub fn raw_msg(&mut self, msg: &str) {
-        self.status.get_stream_mut().map(|stream| {
-            use std::io::Write;
-            write!(stream, "{}\r\n", msg).unwrap();
-        });
+        // QUIT bypasses the queue, we don't want to wait for queued messages to quit
+        if is_quit(msg) {
+            self.status.get_stream_mut().map(|stream| {
+                write!(stream, "{}\r\n", msg).unwrap();
+            });
+        } else {
+            self.send(|buf| write!(buf, "{}\r\n", msg));
+        }
     }
 
     ////////////////////////////////////////////////////////////////////////////
     // Sending messages
 
     pub fn write_ready(&mut self, evs: &mut Vec<ConnEv>) {
+        self.flush_send_queue();
         if let Some(stream) = self.status.get_stream_mut() {
             match stream.write_ready() {
                 Err(err) =>
@@ -435,6 +1004,11 @@ impl<'poll> Conn<'poll> {
     // Receiving messages
 
     pub fn read_ready(&mut self, evs: &mut Vec<ConnEv>, logger: &mut Logger) {
+        if let ConnStatus::Resolving { .. } = self.status {
+            self.resolve_ready(evs);
+            return;
+        }
+
         let mut read_buf: [u8; 512] = [0; 512];
 
         if let Some(stream) = self.status.get_stream_mut() {
@@ -462,7 +1036,7 @@ impl<'poll> Conn<'poll> {
         }
     }
 
-    fn handle_msg(&mut self, msg: Msg, evs: &mut Vec<ConnEv>, logger: &mut Logger) {
+    fn handle_msg(&mut self, mut msg: Msg, evs: &mut Vec<ConnEv>, logger: &mut Logger) {
         if let Msg {
             cmd: Cmd::PING { ref server },
             ..
@@ -476,9 +1050,22 @@ impl<'poll> Conn<'poll> {
         if let Msg {
             cmd: Cmd::JOIN { .. },
             pfx: Some(Pfx::User { ref nick, ref user }),
+            ..
         } = msg
         {
-            if nick == self.get_nick() {
+            if self.is_our_nick(nick) {
+                if let Cmd::JOIN { ref chans, .. } = msg.cmd {
+                    // joined before the rejoin timer expired
+                    let casemapping = self.isupport.casemapping;
+                    self.rejoin_queue.drain_filter(|&mut (ref chan, _)| {
+                        chans.iter().any(|chan_| casemapping.eq(chan, chan_))
+                    });
+                    // NAMES only gives us nicks and prefixes
+                    for chan in chans {
+                        self.chans.add(casemapping, chan);
+                        self.who_chan(chan);
+                    }
+                }
                 let usermask = format!("{}!{}", nick, user);
                 logger
                     .get_debug_logs()
@@ -488,31 +1075,170 @@ impl<'poll> Conn<'poll> {
         }
 
         if let Msg {
-            cmd: Cmd::Reply {
-                num: 396,
-                ref params,
+            cmd: Cmd::PART { ref chans, .. },
+            pfx: Some(Pfx::User { ref nick, .. }),
+            ..
+        } = msg
+        {
+            if self.is_our_nick(nick) {
+                let casemapping = self.isupport.casemapping;
+                for chan in chans {
+                    self.chans.remove(casemapping, chan);
+                }
+            }
+        }
+
+        if let Msg {
+            cmd: Cmd::KICK { ref chan, ref nick, .. },
+            ..
+        } = msg
+        {
+            if self.is_our_nick(nick) {
+                let casemapping = self.isupport.casemapping;
+                if let Some(delay) = self.auto_rejoin_delay {
+                    let queued = self.rejoin_queue
+                        .iter()
+                        .any(|&(ref chan_, _)| casemapping.eq(chan, chan_));
+                    if !queued {
+                        self.rejoin_queue.push((chan.to_owned(), delay));
+                    }
+                } else {
+                    // not in the channel anymore, don't join it on reconnect
+                    self.chans.remove(casemapping, chan);
+                }
+            }
+        }
+
+        if let Msg {
+            cmd: Cmd::PRIVMSG {
+                msg: ref text,
+                is_notice: false,
+                ..
             },
+            pfx: Some(Pfx::User { ref nick, .. }),
             ..
         } = msg
         {
-            // :hobana.freenode.net 396 osa1 haskell/developer/osa1
-            // :is now your hidden host (set by services.)
-            if params.len() == 3 {
-                let usermask = format!("{}!~{}@{}", self.get_nick(), self.hostname, params[1]);
-                logger
-                    .get_debug_logs()
-                    .write_line(format_args!("usermask set: {}", usermask));
-                self.usermask = Some(usermask);
+            if let Some(ctcp) = ctcp::parse_ctcp(text, false) {
+                if let Some(args) = ctcp::reply_args(&self.ctcp_replies, &ctcp.cmd, ctcp.args) {
+                    if self.ctcp_limiter.allow(Instant::now()) {
+                        self.send(|buf| wire::ctcp_reply(buf, nick, ctcp.cmd.name(), &args));
+                    } else {
+                        logger.get_debug_logs().write_line(format_args!(
+                            "Not replying to CTCP {} from {}: rate limited",
+                            ctcp.cmd.name(),
+                            nick
+                        ));
+                    }
+                }
+            }
+        }
+
+        if let Msg {
+            cmd: Cmd::INVITE { ref nick, ref chan },
+            pfx: Some(Pfx::User { nick: ref inviter, .. }),
+            ..
+        } = msg
+        {
+            if self.is_our_nick(nick) {
+                if self.is_invite_trusted(inviter, chan) {
+                    self.join(&[chan], &[]);
+                } else {
+                    self.last_invite = Some(chan.to_owned());
+                }
             }
         }
 
         if let Msg {
-            cmd: Cmd::Reply {
-                num: 302,
-                ref params,
+            cmd: Cmd::NICK { ref nick },
+            pfx: Some(Pfx::User { nick: ref old_nick, .. }),
+            ..
+        } = msg
+        {
+            // Our nick can change without a `/nick` (e.g. by services), or only in case
+            if self.is_our_nick(old_nick) && nick != self.get_nick() {
+                match self.nicks.iter().position(|n| n == nick) {
+                    Some(nick_idx) =>
+                        self.current_nick_idx = nick_idx,
+                    None => {
+                        self.nicks.push(nick.to_owned());
+                        self.current_nick_idx = self.nicks.len() - 1;
+                    }
+                }
+                evs.push(ConnEv::NickChange(self.get_nick().to_owned()));
+            }
+        }
+
+        if let Msg {
+            cmd: Cmd::CAP {
+                ref subcmd,
+                ref caps,
+                more,
+                ..
             },
             ..
         } = msg
+        {
+            self.handle_cap(subcmd, caps, more, evs, logger);
+        }
+
+        if let Msg {
+            cmd: Cmd::AUTHENTICATE { ref param },
+            ..
+        } = msg
+        {
+            self.handle_authenticate(param);
+        }
+
+        if let Msg {
+            cmd: Cmd::Reply(ref reply),
+            ..
+        } = msg
+        {
+            if self.cap_status == CapStatus::Authenticating {
+                match *reply {
+                    Reply::SaslSuccess { .. } | Reply::ErrSaslAlready { .. } =>
+                        self.end_cap_negotiation(),
+                    Reply::ErrNickLocked { .. }
+                    | Reply::ErrSaslFail { .. }
+                    | Reply::ErrSaslTooLong { .. }
+                    | Reply::ErrSaslAborted { .. } =>
+                        self.sasl_failed(evs),
+                    _ =>
+                        {}
+                }
+            }
+        }
+
+        if let Msg {
+            cmd: Cmd::Reply(ref reply),
+            ..
+        } = msg
+        {
+            let casemapping = self.get_casemapping();
+            if let Some(whois) = self.whois.add(casemapping, reply) {
+                evs.push(ConnEv::Whois(whois));
+            }
+        }
+
+        if let Msg {
+            cmd: Cmd::Reply(Reply::HostHidden { ref host, .. }),
+            ..
+        } = msg
+        {
+            // :hobana.freenode.net 396 osa1 haskell/developer/osa1
+            // :is now your hidden host (set by services.)
+            let usermask = format!("{}!~{}@{}", self.get_nick(), self.hostname, host);
+            logger
+                .get_debug_logs()
+                .write_line(format_args!("usermask set: {}", usermask));
+            self.usermask = Some(usermask);
+        }
+
+        if let Msg {
+            cmd: Cmd::Reply(Reply::UserHost { ref replies, .. }),
+            ..
+        } = msg
         {
             // 302 RPL_USERHOST
             // :ircd.stealth.net 302 yournick :syrk=+syrk@millennium.stealth.net
@@ -520,14 +1246,14 @@ impl<'poll> Conn<'poll> {
             // We know there will be only one nick because /userhost cmd sends
             // one parameter (our nick)
             //
-            // Example args: ["osa1", "osa1=+omer@moz-s8a.9ac.93.91.IP "]
+            // Example replies: "osa1=+omer@moz-s8a.9ac.93.91.IP "
 
-            let param = &params[1];
+            let param = replies;
             match wire::find_byte(param.as_bytes(), b'=') {
                 None => {
                     logger
                         .get_debug_logs()
-                        .write_line(format_args!("can't parse RPL_USERHOST: {}", params[1]));
+                        .write_line(format_args!("can't parse RPL_USERHOST: {}", param));
                 }
                 Some(mut i) => {
                     if param.as_bytes().get(i + 1) == Some(&b'+')
@@ -545,20 +1271,79 @@ impl<'poll> Conn<'poll> {
         }
 
         if let Msg {
-            cmd: Cmd::Reply { num: 001, .. },
+            cmd: Cmd::Reply(Reply::ISupport { ref tokens, .. }),
+            ..
+        } = msg
+        {
+            self.isupport.parse_tokens(tokens);
+        }
+
+        if let Msg {
+            cmd: Cmd::MODE {
+                ref target,
+                ref modes,
+                ..
+            },
+            ..
+        } = msg
+        {
+            if self.is_our_nick(target) {
+                mode::apply_modes(&mut self.user_modes, &mode::parse_user_modes(modes));
+            }
+        }
+
+        if let Msg {
+            cmd: Cmd::Reply(Reply::UModeIs { ref modes, .. }),
+            ..
+        } = msg
+        {
+            self.user_modes.clear();
+            mode::apply_modes(&mut self.user_modes, &mode::parse_user_modes(modes));
+        }
+
+        if let Msg {
+            cmd: Cmd::PRIVMSG { ref mut targets, .. },
+            ..
+        } = msg
+        {
+            // wire only knows about `#` channels, fix the targets according to CHANTYPES.
+            // Messages to a status prefix of a channel (e.g. `@#tiny`) are shown in the channel
+            // tab.
+            for target in targets.iter_mut() {
+                let target_name = match *target {
+                    wire::MsgTarget::Chan(ref name) | wire::MsgTarget::User(ref name) =>
+                        self.isupport.drop_statusmsg_prefix(name).to_owned(),
+                };
+                *target = if self.isupport.is_chan_name(&target_name) {
+                    wire::MsgTarget::Chan(target_name)
+                } else {
+                    wire::MsgTarget::User(target_name)
+                };
+            }
+        }
+
+        if let Msg {
+            cmd: Cmd::Reply(Reply::Welcome { .. }),
             ..
         } = msg
         {
             // 001 RPL_WELCOME is how we understand that the registration was successful
+            // (servers without CAP support never reply to our CAP LS)
+            self.cap_status = CapStatus::Done;
+            self.reconnect_attempts = 0;
+            // start with the same endpoint on reconnect
+            self.sock_addrs.clear();
+            self.next_endpoint_idx = self.endpoint_idx;
+            if !self.chans.is_empty() {
+                let chans = self.chans.get_names();
+                self.rejoin(&chans);
+            }
             evs.push(ConnEv::Connected);
             evs.push(ConnEv::NickChange(self.get_nick().to_owned()));
         }
 
         if let Msg {
-            cmd: Cmd::Reply {
-                num: 002,
-                ref params,
-            },
+            cmd: Cmd::Reply(Reply::YourHost { msg: ref host_msg, .. }),
             ..
         } = msg
         {
@@ -567,12 +1352,12 @@ impl<'poll> Conn<'poll> {
 
             // An example <servername>: cherryh.freenode.net[149.56.134.238/8001]
 
-            match parse_servername(params) {
+            match parse_servername(host_msg) {
                 None => {
                     logger.get_debug_logs().write_line(format_args!(
-                        "{} Can't parse hostname from params: {:?}",
+                        "{} Can't parse hostname from msg: {:?}",
                         self.serv_addr,
-                        params
+                        host_msg
                     ));
                 }
                 Some(servername) => {
@@ -585,7 +1370,7 @@ impl<'poll> Conn<'poll> {
         }
 
         if let Msg {
-            cmd: Cmd::Reply { num: 433, .. },
+            cmd: Cmd::Reply(Reply::ErrNicknameInUse { .. }),
             ..
         } = msg
         {
@@ -595,70 +1380,55 @@ impl<'poll> Conn<'poll> {
             evs.push(ConnEv::NickChange(self.get_nick().to_owned()));
         }
 
-        // Not in any of the RFCs. Also known as ERR_BANONCHAN on the internets.
         // Sent by freenode when nick change failed. See issue #29.
         if let Msg {
-            cmd: Cmd::Reply {
-                num: 435,
-                ref params,
-            },
+            cmd: Cmd::Reply(Reply::ErrBanOnChan {
+                client: ref old_nick,
+                ..
+            }),
             ..
         } = msg
         {
-            if params.len() == 4 {
-                // args: [old_nick, new_nick, chan, msg]
-                let old_nick = &params[0];
-                // make current nick 'old_nick'
-                for (nick_idx, nick) in self.nicks.iter().enumerate() {
-                    if nick == old_nick {
-                        self.current_nick_idx = nick_idx;
-                        evs.push(ConnEv::NickChange(self.get_nick().to_owned()));
-                        break;
-                    }
+            // make current nick 'old_nick'
+            for (nick_idx, nick) in self.nicks.iter().enumerate() {
+                if self.isupport.casemapping.eq(nick, old_nick) {
+                    self.current_nick_idx = nick_idx;
+                    evs.push(ConnEv::NickChange(self.get_nick().to_owned()));
+                    break;
                 }
             }
         }
 
-        if let Msg {
-            cmd: Cmd::Reply { num: 376, .. },
-            ..
-        } = msg
-        {
-            if let Some(mut stream) = self.status.get_stream_mut() {
-                // RPL_ENDOFMOTD. Join auto-join channels.
-                if !self.auto_join.is_empty() {
-                    wire::join(
-                        &mut stream,
-                        self.auto_join
-                            .iter()
-                            .map(String::as_str)
-                            .collect::<Vec<&str>>()
-                            .as_slice(),
-                    ).unwrap();
-                }
-
-                // Set away mode
-                if let Some(ref reason) = self.away_status {
-                    wire::away(stream, Some(reason)).unwrap();
+        match msg.cmd {
+            Cmd::Reply(Reply::EndOfMotd { .. }) | Cmd::Reply(Reply::ErrNoMotd { .. }) => {
+                // RPL_ENDOFMOTD or ERR_NOMOTD, registration is done. Set away mode
+                if let Some(reason) = self.away_status.clone() {
+                    self.send(|buf| wire::away(buf, Some(&reason)));
                 }
+                self.track_friends();
             }
+            _ =>
+                {}
         }
 
         if let Msg {
-            cmd: Cmd::Reply {
-                num: 332,
-                ref params,
-            },
+            cmd: Cmd::Reply(ref reply),
             ..
         } = msg
         {
-            if params.len() == 2 || params.len() == 3 {
-                // RPL_TOPIC. We've successfully joined a channel, add the channel to
-                // self.auto_join to be able to auto-join next time we connect
-                let chan = &params[params.len() - 2];
-                if !self.auto_join.contains(chan) {
-                    self.auto_join.push(chan.to_owned());
-                }
+            // replies to our ISON queries are only shown as `ConnEv::Friend`s
+            let our_ison = match *reply {
+                Reply::IsOn { .. } =>
+                    self.friends.is_ison_pending(),
+                _ =>
+                    false,
+            };
+            let casemapping = self.get_casemapping();
+            for (nick, online) in self.friends.add(casemapping, reply) {
+                evs.push(ConnEv::Friend { nick, online });
+            }
+            if our_ison {
+                return;
             }
         }
 
@@ -666,45 +1436,307 @@ impl<'poll> Conn<'poll> {
     }
 }
 
-/// Try to parse servername in a 002 RPL_YOURHOST reply
-fn parse_servername(params: &[String]) -> Option<String> {
-    let msg = params.get(1).or_else(|| params.get(0))?;
-    let slice1 = &msg[13..];
+impl<'poll> Conn<'poll> {
+    ////////////////////////////////////////////////////////////////////////////
+    // Capability negotiation
+
+    fn handle_cap(
+        &mut self,
+        subcmd: &str,
+        caps: &[String],
+        more: bool,
+        evs: &mut Vec<ConnEv>,
+        logger: &mut Logger,
+    ) {
+        match subcmd {
+            "LS" => {
+                self.available_caps
+                    .extend(caps.iter().map(|cap| cap_name(cap).to_owned()));
+                if !more && self.cap_status == CapStatus::Listing {
+                    let to_req = caps_to_request(&self.wanted_caps, &self.available_caps, &self.caps);
+                    if to_req.is_empty() {
+                        self.caps_negotiated(evs);
+                    } else {
+                        if let Some(stream) = self.status.get_stream_mut() {
+                            wire::cap_req(stream, &to_req).unwrap();
+                        }
+                        self.cap_status = CapStatus::Requesting;
+                    }
+                }
+            }
+            "ACK" => {
+                for cap in caps {
+                    if cap.starts_with('-') {
+                        let cap = &cap[1..];
+                        self.caps.retain(|cap_| cap_ != cap);
+                    } else if !self.has_cap(cap) {
+                        self.caps.push(cap.to_owned());
+                    }
+                }
+                logger
+                    .get_debug_logs()
+                    .write_line(format_args!("{} caps: {:?}", self.serv_addr, self.caps));
+                if self.cap_status == CapStatus::Requesting {
+                    self.caps_negotiated(evs);
+                }
+            }
+            "NAK" => {
+                logger.get_debug_logs().write_line(format_args!(
+                    "{} rejected caps: {:?}",
+                    self.serv_addr,
+                    caps
+                ));
+                if self.cap_status == CapStatus::Requesting {
+                    self.caps_negotiated(evs);
+                }
+            }
+            "NEW" => {
+                let new_caps: Vec<String> = caps.iter().map(|cap| cap_name(cap).to_owned()).collect();
+                if self.cap_status == CapStatus::Done {
+                    let to_req = caps_to_request(&self.wanted_caps, &new_caps, &self.caps);
+                    if !to_req.is_empty() {
+                        if let Some(stream) = self.status.get_stream_mut() {
+                            wire::cap_req(stream, &to_req).unwrap();
+                        }
+                    }
+                }
+                self.available_caps.extend(new_caps);
+            }
+            "DEL" => {
+                for cap in caps {
+                    let cap = cap_name(cap);
+                    self.available_caps.retain(|cap_| cap_ != cap);
+                    self.caps.retain(|cap_| cap_ != cap);
+                }
+            }
+            _ =>
+                {}
+        }
+    }
+
+    /// Requested capabilities are acknowledged or rejected. Authenticate if SASL is configured,
+    /// otherwise finish capability negotiation.
+    fn caps_negotiated(&mut self, evs: &mut Vec<ConnEv>) {
+        let abort_on_fail = match self.sasl_auth {
+            None => {
+                self.end_cap_negotiation();
+                return;
+            }
+            Some(ref auth) =>
+                auth.abort_on_fail,
+        };
+        if self.has_cap("sasl") {
+            let mechanism = self.sasl_auth.as_ref().unwrap().mechanism;
+            self.status.get_stream_mut().map(|stream| {
+                wire::authenticate(stream, mechanism.name()).unwrap();
+            });
+            self.cap_status = CapStatus::Authenticating;
+        } else if abort_on_fail {
+            self.abort(evs, "Server doesn't support SASL authentication");
+        } else {
+            self.end_cap_negotiation();
+        }
+    }
+
+    fn handle_authenticate(&mut self, param: &str) {
+        if self.cap_status != CapStatus::Authenticating || param != "+" {
+            return;
+        }
+        if let Some(ref auth) = self.sasl_auth {
+            let payload = match auth.mechanism {
+                // authzid \0 authcid \0 passwd. Empty authzid means "same as authcid".
+                SASLMechanism::Plain =>
+                    format!("\0{}\0{}", auth.username, auth.password),
+                // Just the authzid, which can be empty. The account is found using the
+                // certificate.
+                SASLMechanism::External =>
+                    auth.username.clone(),
+            };
+            self.status.get_stream_mut().map(|stream| {
+                wire::authenticate_payload(stream, payload.as_bytes()).unwrap();
+            });
+        }
+    }
+
+    /// SASL authentication failed. Continue registration unless configured otherwise.
+    fn sasl_failed(&mut self, evs: &mut Vec<ConnEv>) {
+        if self.sasl_auth.as_ref().map(|auth| auth.abort_on_fail).unwrap_or(false) {
+            self.abort(evs, "SASL authentication failed");
+        } else {
+            self.end_cap_negotiation();
+        }
+    }
+
+    /// Close the connection without scheduling a reconnect.
+    fn abort(&mut self, evs: &mut Vec<ConnEv>, reason: &str) {
+        // dropping the stream closes the socket
+        self.status = ConnStatus::Aborted;
+        self.cap_status = CapStatus::Done;
+        evs.push(ConnEv::Aborted(reason.to_owned()));
+    }
+
+    fn end_cap_negotiation(&mut self) {
+        self.status.get_stream_mut().map(|stream| {
+            wire::cap_end(stream).unwrap();
+        });
+        self.cap_status = CapStatus::Done;
+    }
+}
+
+/// Delay before reconnect attempt number `attempts` (starting from 0): `delay` doubled after each
+/// attempt, plus up to 25% jitter (using `random`) to avoid all clients of a restarted server
+/// reconnecting at once. At most `max_delay`.
+fn backoff_delay(delay: u16, max_delay: u16, attempts: u32, random: u32) -> u16 {
+    // u16 << 16 fits into u32
+    let delay = (u32::from(delay.max(1)) << attempts.min(16)).min(u32::from(max_delay));
+    let jitter = random % (delay / 4 + 1);
+    (delay + jitter).min(u32::from(max_delay)).max(1) as u16
+}
+
+/// Drop the value part of a capability in `CAP LS 302` and `CAP NEW` replies (e.g.
+/// `sasl=PLAIN,EXTERNAL`).
+fn cap_name(cap: &str) -> &str {
+    match wire::find_byte(cap.as_bytes(), b'=') {
+        None =>
+            cap,
+        Some(idx) =>
+            &cap[..idx],
+    }
+}
+
+/// Capabilities in `wanted` that are in `available` but not enabled yet.
+fn caps_to_request<'a>(wanted: &'a [String], available: &[String], enabled: &[String]) -> Vec<&'a str> {
+    wanted
+        .iter()
+        .filter(|cap| available.contains(cap) && !enabled.contains(cap))
+        .map(String::as_str)
+        .collect()
+}
+
+/// Try to parse servername from the message of a 002 RPL_YOURHOST reply.
+fn parse_servername(msg: &str) -> Option<String> {
+    let slice1 = msg.get(13..)?;
     let servername_ends =
         wire::find_byte(slice1.as_bytes(), b'[')
             .or_else(|| wire::find_byte(slice1.as_bytes(), b','))?;
     Some((&slice1[..servername_ends]).to_owned())
 }
 
+/// Is the raw message a QUIT?
+fn is_quit(msg: &str) -> bool {
+    msg.split_whitespace()
+        .next()
+        .map(|cmd| cmd.eq_ignore_ascii_case("QUIT"))
+        .unwrap_or(false)
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use serde_yaml;
+
+    #[test]
+    fn test_is_quit() {
+        assert!(is_quit("QUIT"));
+        assert!(is_quit("quit :bye"));
+        assert!(!is_quit("QUITS"));
+        assert!(!is_quit("PRIVMSG #tiny :QUIT"));
+        assert!(!is_quit(""));
+        assert!(!is_quit("café"));
+        assert!(!is_quit("quité"));
+    }
 
     #[test]
     fn test_parse_servername_1() {
-        let args = vec![
-            "tiny_test".to_owned(),
-            "Your host is adams.freenode.net[94.125.182.252/8001], \
-             running version ircd-seven-1.1.4"
-                .to_owned(),
-        ];
+        let msg = "Your host is adams.freenode.net[94.125.182.252/8001], \
+                   running version ircd-seven-1.1.4";
         assert_eq!(
-            parse_servername(&args),
+            parse_servername(msg),
             Some("adams.freenode.net".to_owned())
         );
     }
 
     #[test]
     fn test_parse_servername_2() {
-        let args = vec![
-            "tiny_test".to_owned(),
-            "Your host is belew.mozilla.org, running version InspIRCd-2.0".to_owned(),
-        ];
+        let msg = "Your host is belew.mozilla.org, running version InspIRCd-2.0";
         assert_eq!(
-            parse_servername(&args),
+            parse_servername(msg),
             Some("belew.mozilla.org".to_owned())
         );
     }
+
+    fn test_server() -> config::Server {
+        serde_yaml::from_str(
+            "\
+addr: localhost
+port: 6667
+hostname: yourhost
+realname: yourname
+nicks: [tiny_user]
+auto_cmds: []",
+        ).unwrap()
+    }
+
+    #[test]
+    fn test_disconnect_counts_once() {
+        let poll = Poll::new().unwrap();
+        let mut conn = Conn::new(test_server(), &poll).unwrap();
+        // e.g. HUP, then errors from both read_ready and write_ready
+        conn.enter_disconnect_state();
+        let secs = conn.get_reconnect_secs();
+        conn.enter_disconnect_state();
+        conn.enter_disconnect_state();
+        assert_eq!(conn.reconnect_attempts, 1);
+        assert_eq!(conn.get_reconnect_secs(), secs);
+
+        assert!(conn.cancel_reconnect());
+        conn.enter_disconnect_state();
+        assert_eq!(conn.get_reconnect_secs(), None);
+    }
+
+    #[test]
+    fn test_part_cancels_rejoin() {
+        let poll = Poll::new().unwrap();
+        let mut conn = Conn::new(test_server(), &poll).unwrap();
+        // kicked from #Tiny, waiting to rejoin
+        conn.rejoin_queue.push(("#Tiny".to_owned(), 3));
+        conn.rejoin_queue.push(("#rust".to_owned(), 3));
+        conn.part("#tiny");
+        assert_eq!(conn.rejoin_queue, vec![("#rust".to_owned(), 3)]);
+    }
+
+    #[test]
+    fn test_backoff_delay() {
+        assert_eq!(backoff_delay(30, 600, 0, 0), 30);
+        assert_eq!(backoff_delay(30, 600, 1, 0), 60);
+        assert_eq!(backoff_delay(30, 600, 2, 0), 120);
+        assert_eq!(backoff_delay(30, 600, 5, 0), 600);
+        assert_eq!(backoff_delay(30, 600, 1000, 0), 600);
+        // jitter is at most 25%
+        assert_eq!(backoff_delay(40, 600, 0, 7), 47);
+        assert_eq!(backoff_delay(40, 600, 0, 10), 50);
+        assert_eq!(backoff_delay(40, 600, 0, 11), 40);
+        // but doesn't exceed the max
+        assert_eq!(backoff_delay(40, 45, 0, 10), 45);
+        assert_eq!(backoff_delay(0, 0, 0, 0), 1);
+    }
+
+    #[test]
+    fn test_cap_name() {
+        assert_eq!(cap_name("server-time"), "server-time");
+        assert_eq!(cap_name("sasl=PLAIN,EXTERNAL"), "sasl");
+        assert_eq!(cap_name("draft/foo="), "draft/foo");
+    }
+
+    #[test]
+    fn test_caps_to_request() {
+        let wanted = vec!["multi-prefix".to_owned(), "sasl".to_owned(), "server-time".to_owned()];
+        let available = vec!["sasl".to_owned(), "server-time".to_owned(), "chghost".to_owned()];
+        let enabled = vec!["sasl".to_owned()];
+        assert_eq!(caps_to_request(&wanted, &available, &[]), vec!["sasl", "server-time"]);
+        assert_eq!(caps_to_request(&wanted, &available, &enabled), vec!["server-time"]);
+        assert!(caps_to_request(&[], &available, &[]).is_empty());
+    }
 }
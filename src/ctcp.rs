@@ -0,0 +1,278 @@
+//! Client-to-client protocol. CTCP messages are PRIVMSGs (requests) and NOTICEs (replies) with
+//! contents wrapped in `\x01`, e.g. `\x01VERSION\x01`.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use time;
+
+use config::CtcpReplies;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum CtcpCmd {
+    Action,
+    Version,
+    Ping,
+    Time,
+    ClientInfo,
+    Source,
+    Other(String),
+}
+
+impl CtcpCmd {
+    /// Command names are case-insensitive, `Other` commands are kept upper case.
+    pub fn from_name(name: &str) -> CtcpCmd {
+        let name = name.to_ascii_uppercase();
+        match name.as_str() {
+            "ACTION" =>
+                CtcpCmd::Action,
+            "VERSION" =>
+                CtcpCmd::Version,
+            "PING" =>
+                CtcpCmd::Ping,
+            "TIME" =>
+                CtcpCmd::Time,
+            "CLIENTINFO" =>
+                CtcpCmd::ClientInfo,
+            "SOURCE" =>
+                CtcpCmd::Source,
+            _ =>
+                CtcpCmd::Other(name),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match *self {
+            CtcpCmd::Action =>
+                "ACTION",
+            CtcpCmd::Version =>
+                "VERSION",
+            CtcpCmd::Ping =>
+                "PING",
+            CtcpCmd::Time =>
+                "TIME",
+            CtcpCmd::ClientInfo =>
+                "CLIENTINFO",
+            CtcpCmd::Source =>
+                "SOURCE",
+            CtcpCmd::Other(ref name) =>
+                name,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum CtcpKind {
+    /// Sent in a PRIVMSG
+    Request,
+    /// Sent in a NOTICE
+    Reply,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Ctcp<'a> {
+    pub kind: CtcpKind,
+    pub cmd: CtcpCmd,
+    /// Everything after the command name. Empty if the message has no arguments.
+    pub args: &'a str,
+}
+
+/// Parse contents of a PRIVMSG or NOTICE as a CTCP message. The closing `\x01` is optional, as
+/// some clients omit it.
+pub fn parse_ctcp(msg: &str, is_notice: bool) -> Option<Ctcp> {
+    if !msg.starts_with('\x01') {
+        return None;
+    }
+    let msg = &msg[1..];
+    let msg = if msg.ends_with('\x01') {
+        &msg[..msg.len() - 1]
+    } else {
+        msg
+    };
+    let (name, args) = match msg.find(' ') {
+        None =>
+            (msg, ""),
+        Some(idx) =>
+            (&msg[..idx], &msg[idx + 1..]),
+    };
+    if name.is_empty() {
+        return None;
+    }
+    Some(Ctcp {
+        kind: if is_notice {
+            CtcpKind::Reply
+        } else {
+            CtcpKind::Request
+        },
+        cmd: CtcpCmd::from_name(name),
+        args,
+    })
+}
+
+/// Contents of a CTCP PRIVMSG or NOTICE.
+pub fn format_ctcp(cmd: &str, args: &str) -> String {
+    if args.is_empty() {
+        format!("\x01{}\x01", cmd)
+    } else {
+        format!("\x01{} {}\x01", cmd, args)
+    }
+}
+
+/// Arguments of our reply to a CTCP request. `None` when we don't reply to the request (unknown
+/// command, or replies to the command are disabled in the config).
+pub fn reply_args(config: &CtcpReplies, cmd: &CtcpCmd, args: &str) -> Option<String> {
+    match *cmd {
+        CtcpCmd::Version if config.version =>
+            Some(format!("tiny {}", env!("CARGO_PKG_VERSION"))),
+        CtcpCmd::Ping if config.ping =>
+            Some(args.to_owned()),
+        CtcpCmd::Time if config.time =>
+            Some(format!("{}", time::now().rfc822())),
+        CtcpCmd::ClientInfo if config.clientinfo =>
+            Some("ACTION CLIENTINFO PING SOURCE TIME VERSION".to_owned()),
+        CtcpCmd::Source if config.source =>
+            Some("https://github.com/osa1/tiny".to_owned()),
+        _ =>
+            None,
+    }
+}
+
+/// Milliseconds since the epoch. Sent in CTCP PING requests to calculate round-trip time when the
+/// reply arrives.
+pub fn now_millis() -> i64 {
+    let now = time::get_time();
+    now.sec * 1000 + i64::from(now.nsec / 1_000_000)
+}
+
+/// Round-trip time of a CTCP PING, if the reply has a timestamp we sent.
+pub fn ping_rtt(reply_args: &str) -> Option<i64> {
+    reply_args
+        .trim()
+        .parse::<i64>()
+        .ok()
+        .map(|sent| now_millis() - sent)
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Allows at most `max` events in any `window`. Used to avoid flooding the server (and getting
+/// disconnected) when someone sends us lots of CTCP requests.
+pub struct RateLimiter {
+    max: usize,
+    window: Duration,
+    events: VecDeque<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(max: usize, window: Duration) -> RateLimiter {
+        RateLimiter {
+            max,
+            window,
+            events: VecDeque::with_capacity(max),
+        }
+    }
+
+    /// Record an event at `now` if the limit is not reached. Returns whether the event is allowed.
+    pub fn allow(&mut self, now: Instant) -> bool {
+        while let Some(&event) = self.events.front() {
+            if now.duration_since(event) >= self.window {
+                self.events.pop_front();
+            } else {
+                break;
+            }
+        }
+        if self.events.len() < self.max {
+            self.events.push_back(now);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ctcp() {
+        assert_eq!(
+            parse_ctcp("\x01VERSION\x01", false),
+            Some(Ctcp {
+                kind: CtcpKind::Request,
+                cmd: CtcpCmd::Version,
+                args: "",
+            })
+        );
+        assert_eq!(
+            parse_ctcp("\x01PING 1234\x01", true),
+            Some(Ctcp {
+                kind: CtcpKind::Reply,
+                cmd: CtcpCmd::Ping,
+                args: "1234",
+            })
+        );
+        // closing \x01 is optional, commands are case insensitive
+        assert_eq!(
+            parse_ctcp("\x01action waves", false),
+            Some(Ctcp {
+                kind: CtcpKind::Request,
+                cmd: CtcpCmd::Action,
+                args: "waves",
+            })
+        );
+        assert_eq!(
+            parse_ctcp("\x01finger\x01", false),
+            Some(Ctcp {
+                kind: CtcpKind::Request,
+                cmd: CtcpCmd::Other("FINGER".to_owned()),
+                args: "",
+            })
+        );
+        assert_eq!(parse_ctcp("VERSION", false), None);
+        assert_eq!(parse_ctcp("\x01\x01", false), None);
+    }
+
+    #[test]
+    fn test_format_ctcp() {
+        assert_eq!(format_ctcp("VERSION", ""), "\x01VERSION\x01");
+        assert_eq!(format_ctcp("PING", "1234"), "\x01PING 1234\x01");
+        let msg = format_ctcp("TIME", "now");
+        let ctcp = parse_ctcp(&msg, true).unwrap();
+        assert_eq!(ctcp.cmd, CtcpCmd::Time);
+        assert_eq!(ctcp.args, "now");
+    }
+
+    #[test]
+    fn test_reply_args() {
+        let mut config = CtcpReplies::default();
+        assert_eq!(reply_args(&config, &CtcpCmd::Ping, "1234"), Some("1234".to_owned()));
+        assert!(reply_args(&config, &CtcpCmd::Version, "").unwrap().starts_with("tiny "));
+        assert_eq!(reply_args(&config, &CtcpCmd::Action, "waves"), None);
+        assert_eq!(reply_args(&config, &CtcpCmd::Other("FINGER".to_owned()), ""), None);
+        config.version = false;
+        assert_eq!(reply_args(&config, &CtcpCmd::Version, ""), None);
+    }
+
+    #[test]
+    fn test_ping_rtt() {
+        let sent = now_millis() - 100;
+        let rtt = ping_rtt(&sent.to_string()).unwrap();
+        assert!(rtt >= 100 && rtt < 10000);
+        assert_eq!(ping_rtt("foo"), None);
+    }
+
+    #[test]
+    fn test_rate_limiter() {
+        let mut limiter = RateLimiter::new(2, Duration::from_secs(10));
+        let t0 = Instant::now();
+        assert!(limiter.allow(t0));
+        assert!(limiter.allow(t0 + Duration::from_secs(1)));
+        assert!(!limiter.allow(t0 + Duration::from_secs(2)));
+        // first event drops out of the window
+        assert!(limiter.allow(t0 + Duration::from_secs(10)));
+        assert!(!limiter.allow(t0 + Duration::from_secs(10)));
+        assert!(limiter.allow(t0 + Duration::from_secs(11)));
+    }
+}
@@ -0,0 +1,1143 @@
+//! DCC SEND file transfers and DCC CHAT. Offers are sent as CTCP requests (e.g. `\x01DCC SEND
+//! <file> <ip> <port> <size>\x01`), files and chat lines are transferred over direct TCP
+//! connections registered to the same `Poll` as server connections.
+
+use mio::Poll;
+use mio::Ready;
+use mio::Token;
+use net2::TcpBuilder;
+use net2::TcpStreamExt;
+use std::fs::File;
+use std::fs;
+use std::io::{Read, Write};
+use std::io;
+use std::mem;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
+
+use isupport::CaseMapping;
+use stream::utils::{deregister, register_for_r, reregister_for_r, reregister_for_rw};
+
+/// A DCC SEND offer: `SEND <filename> <ip> <port> [<size>]`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct DccSend {
+    pub filename: String,
+    pub addr: SocketAddr,
+    /// Not all clients send the file size.
+    pub size: Option<u64>,
+}
+
+/// Parse arguments of a `DCC` CTCP request, e.g. `SEND "my file.txt" 2130706433 5000 1234`.
+/// Returns `None` for other DCC requests, malformed offers and passive (port 0) offers.
+pub fn parse_dcc_send(args: &str) -> Option<DccSend> {
+    let args = args.trim();
+    match args.get(..5) {
+        Some(send) if send.eq_ignore_ascii_case("SEND ") =>
+            {}
+        _ =>
+            return None,
+    }
+    let args = args[5..].trim_left();
+    let (filename, rest) = if args.starts_with('"') {
+        let close = args[1..].find('"')? + 1;
+        (&args[1..close], &args[close + 1..])
+    } else {
+        let space = args.find(' ')?;
+        (&args[..space], &args[space..])
+    };
+    let mut words = rest.split_whitespace();
+    let ip = parse_dcc_ip(words.next()?)?;
+    let port = words.next()?.parse::<u16>().ok()?;
+    let size = words.next().and_then(|size| size.parse::<u64>().ok());
+    if filename.is_empty() || port == 0 {
+        return None;
+    }
+    Some(DccSend {
+        filename: filename.to_owned(),
+        addr: SocketAddr::new(ip, port),
+        size,
+    })
+}
+
+/// IPv4 addresses are sent as a 32-bit integer, IPv6 addresses in the usual notation.
+fn parse_dcc_ip(s: &str) -> Option<IpAddr> {
+    match s.parse::<u32>() {
+        Ok(ip) =>
+            Some(IpAddr::V4(Ipv4Addr::from(ip))),
+        Err(_) =>
+            s.parse::<IpAddr>().ok(),
+    }
+}
+
+fn format_dcc_ip(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) =>
+            u32::from(ip).to_string(),
+        IpAddr::V6(ip) =>
+            ip.to_string(),
+    }
+}
+
+/// Arguments of a `DCC` CTCP request offering a file.
+pub fn format_dcc_send(filename: &str, addr: SocketAddr, size: u64) -> String {
+    let ip = format_dcc_ip(addr.ip());
+    if filename.contains(' ') {
+        format!("SEND \"{}\" {} {} {}", filename, ip, addr.port(), size)
+    } else {
+        format!("SEND {} {} {} {}", filename, ip, addr.port(), size)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+pub struct Transfer {
+    pub id: usize,
+    pub serv_name: String,
+    pub nick: String,
+    pub filename: String,
+    pub size: Option<u64>,
+    /// Bytes received or sent so far.
+    pub transferred: u64,
+    state: State,
+    /// Progress reported so far, in tens of percents.
+    reported: u64,
+}
+
+enum State {
+    /// Incoming offer, waiting for `/dcc accept`.
+    Offered { addr: SocketAddr },
+    /// Outgoing offer, waiting for the peer to connect.
+    Listening { listener: TcpListener, file: File },
+    Receiving { stream: TcpStream, file: File },
+    Sending {
+        stream: TcpStream,
+        file: File,
+        /// Read from the file but not sent yet.
+        out_buf: Vec<u8>,
+        /// Acknowledgements are 32-bit big-endian byte counts, possibly split across reads.
+        ack_buf: Vec<u8>,
+        acked: u32,
+    },
+    /// Temporarily used while moving sockets between states.
+    Closed,
+}
+
+impl State {
+    fn fd(&self) -> Option<RawFd> {
+        match *self {
+            State::Listening { ref listener, .. } =>
+                Some(listener.as_raw_fd()),
+            State::Receiving { ref stream, .. } | State::Sending { ref stream, .. } =>
+                Some(stream.as_raw_fd()),
+            State::Offered { .. } | State::Closed =>
+                None,
+        }
+    }
+}
+
+impl Transfer {
+    fn is_incoming(&self) -> bool {
+        match self.state {
+            State::Offered { .. } | State::Receiving { .. } =>
+                true,
+            State::Listening { .. } | State::Sending { .. } | State::Closed =>
+                false,
+        }
+    }
+
+    fn progress(&self) -> String {
+        match self.size {
+            Some(size) if size != 0 =>
+                format!(
+                    "{}% ({}/{} bytes)",
+                    self.transferred * 100 / size,
+                    self.transferred,
+                    size
+                ),
+            _ =>
+                format!("{} bytes", self.transferred),
+        }
+    }
+
+    /// Name of the transfer in messages, e.g. `#1 notes.txt from osa1`.
+    pub fn name(&self) -> String {
+        format!(
+            "#{} {} {} {}",
+            self.id,
+            self.filename,
+            if self.is_incoming() { "from" } else { "to" },
+            self.nick
+        )
+    }
+
+    /// A line for the transfer list.
+    pub fn describe(&self) -> String {
+        let status = match self.state {
+            State::Offered { .. } =>
+                format!("waiting for `/dcc accept {}`", self.id),
+            State::Listening { .. } =>
+                "waiting for the peer to connect".to_owned(),
+            State::Receiving { .. } | State::Sending { .. } | State::Closed =>
+                self.progress(),
+        };
+        format!("{} ({}): {}", self.name(), self.serv_name, status)
+    }
+
+    /// Handle a readiness event. Returns `true` when the transfer is complete.
+    fn ready(&mut self, poll: &Poll, readiness: Ready) -> io::Result<bool> {
+        let state = mem::replace(&mut self.state, State::Closed);
+        let (state, done) = match state {
+            State::Listening { listener, file } => {
+                let (stream, _) = match listener.accept() {
+                    Ok(ret) =>
+                        ret,
+                    Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => {
+                        self.state = State::Listening { listener, file };
+                        return Ok(false);
+                    }
+                    Err(err) =>
+                        return Err(err),
+                };
+                // one connection per offer
+                deregister(poll, listener.as_raw_fd());
+                stream.set_nonblocking(true)?;
+                register_for_r(poll, stream.as_raw_fd());
+                reregister_for_rw(poll, stream.as_raw_fd());
+                let state = State::Sending {
+                    stream,
+                    file,
+                    out_buf: vec![],
+                    ack_buf: vec![],
+                    acked: 0,
+                };
+                (state, false)
+            }
+            State::Receiving {
+                mut stream,
+                mut file,
+            } => {
+                let done = self.receive(&mut stream, &mut file)?;
+                (State::Receiving { stream, file }, done)
+            }
+            State::Sending {
+                mut stream,
+                mut file,
+                mut out_buf,
+                mut ack_buf,
+                mut acked,
+            } => {
+                let size = self.size.unwrap_or(0);
+                let mut closed = false;
+                if readiness.is_readable() {
+                    closed = read_acks(&mut stream, &mut ack_buf, &mut acked)?;
+                }
+                if readiness.is_writable() && self.transferred < size {
+                    self.send(&mut stream, &mut file, &mut out_buf)?;
+                    if self.transferred == size {
+                        // only waiting for acknowledgements now
+                        reregister_for_r(poll, stream.as_raw_fd());
+                    }
+                }
+                // some clients don't send acknowledgements and close the connection instead
+                let sent_all = self.transferred == size;
+                if closed && !sent_all {
+                    return Err(connection_closed());
+                }
+                let done = sent_all && (closed || acked == size as u32);
+                let state = State::Sending {
+                    stream,
+                    file,
+                    out_buf,
+                    ack_buf,
+                    acked,
+                };
+                (state, done)
+            }
+            State::Offered { .. } | State::Closed =>
+                (state, false),
+        };
+        self.state = state;
+        Ok(done)
+    }
+
+    fn receive(&mut self, stream: &mut TcpStream, file: &mut File) -> io::Result<bool> {
+        let mut buf = [0u8; 4096];
+        loop {
+            match stream.read(&mut buf) {
+                Ok(0) =>
+                    return match self.size {
+                        Some(size) if self.transferred < size =>
+                            Err(connection_closed()),
+                        _ =>
+                            Ok(true),
+                    },
+                Ok(n) => {
+                    file.write_all(&buf[..n])?;
+                    self.transferred += n as u64;
+                }
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock =>
+                    break,
+                Err(err) =>
+                    return Err(err),
+            }
+        }
+        // acknowledge the bytes received so far
+        let ack = self.transferred as u32;
+        let _ = stream.write(&[(ack >> 24) as u8, (ack >> 16) as u8, (ack >> 8) as u8, ack as u8]);
+        Ok(match self.size {
+            Some(size) =>
+                self.transferred >= size,
+            None =>
+                false,
+        })
+    }
+
+    fn send(
+        &mut self,
+        stream: &mut TcpStream,
+        file: &mut File,
+        out_buf: &mut Vec<u8>,
+    ) -> io::Result<()> {
+        loop {
+            if out_buf.is_empty() {
+                out_buf.resize(4096, 0);
+                let n = file.read(out_buf)?;
+                out_buf.truncate(n);
+                if n == 0 {
+                    return Ok(());
+                }
+            }
+            match stream.write(out_buf) {
+                Ok(n) => {
+                    out_buf.drain(0..n);
+                    self.transferred += n as u64;
+                }
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock =>
+                    return Ok(()),
+                Err(err) =>
+                    return Err(err),
+            }
+        }
+    }
+}
+
+/// Read acknowledgements sent by the receiver. Returns `true` when the connection is closed.
+fn read_acks(stream: &mut TcpStream, ack_buf: &mut Vec<u8>, acked: &mut u32) -> io::Result<bool> {
+    let mut buf = [0u8; 64];
+    loop {
+        match stream.read(&mut buf) {
+            Ok(0) =>
+                return Ok(true),
+            Ok(n) => {
+                ack_buf.extend_from_slice(&buf[..n]);
+                while ack_buf.len() >= 4 {
+                    *acked = (u32::from(ack_buf[0]) << 24) | (u32::from(ack_buf[1]) << 16)
+                        | (u32::from(ack_buf[2]) << 8)
+                        | u32::from(ack_buf[3]);
+                    ack_buf.drain(0..4);
+                }
+            }
+            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock =>
+                return Ok(false),
+            Err(err) =>
+                return Err(err),
+        }
+    }
+}
+
+fn connection_closed() -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, "Connection closed")
+}
+
+/// Path to save a received file. Only the file name part of the offered name is used, and a
+/// number is appended when the file already exists.
+fn download_path(download_dir: &Path, filename: &str) -> PathBuf {
+    let filename = Path::new(filename)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "dcc_file".to_owned());
+    let mut path = download_dir.join(&filename);
+    let mut n = 1;
+    while path.exists() {
+        path = download_dir.join(format!("{}.{}", filename, n));
+        n += 1;
+    }
+    path
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+pub enum TransferEv {
+    /// Shown every 10%.
+    Progress(String),
+    Done(String),
+    Failed(String),
+}
+
+/// All pending and active transfers. Finished transfers are removed.
+pub struct Transfers<'poll> {
+    poll: &'poll Poll,
+    transfers: Vec<Transfer>,
+    next_id: usize,
+}
+
+impl<'poll> Transfers<'poll> {
+    pub fn new(poll: &'poll Poll) -> Transfers<'poll> {
+        Transfers {
+            poll,
+            transfers: vec![],
+            next_id: 1,
+        }
+    }
+
+    pub fn iter(&self) -> ::std::slice::Iter<Transfer> {
+        self.transfers.iter()
+    }
+
+    pub fn get(&self, id: usize) -> Option<&Transfer> {
+        self.transfers.iter().find(|transfer| transfer.id == id)
+    }
+
+    /// Id of the most recent offer that's not accepted yet.
+    pub fn last_offer(&self) -> Option<usize> {
+        self.transfers
+            .iter()
+            .rev()
+            .find(|transfer| match transfer.state {
+                State::Offered { .. } =>
+                    true,
+                _ =>
+                    false,
+            })
+            .map(|transfer| transfer.id)
+    }
+
+    fn new_transfer(
+        &mut self,
+        serv_name: &str,
+        nick: &str,
+        filename: &str,
+        size: Option<u64>,
+        state: State,
+    ) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.transfers.push(Transfer {
+            id,
+            serv_name: serv_name.to_owned(),
+            nick: nick.to_owned(),
+            filename: filename.to_owned(),
+            size,
+            transferred: 0,
+            state,
+            reported: 0,
+        });
+        id
+    }
+
+    /// Add an incoming offer. Returns id of the transfer.
+    pub fn offer(&mut self, serv_name: &str, nick: &str, offer: DccSend) -> usize {
+        self.new_transfer(
+            serv_name,
+            nick,
+            &offer.filename,
+            offer.size,
+            State::Offered { addr: offer.addr },
+        )
+    }
+
+    /// Accept an incoming offer. Returns the path the file is saved to.
+    pub fn accept(&mut self, id: usize, download_dir: &Path) -> io::Result<PathBuf> {
+        let idx = self.find_idx(id)?;
+        let addr = match self.transfers[idx].state {
+            State::Offered { addr } =>
+                addr,
+            _ =>
+                return Err(io::Error::new(io::ErrorKind::Other, "Transfer already accepted")),
+        };
+        fs::create_dir_all(download_dir)?;
+        let path = download_path(download_dir, &self.transfers[idx].filename);
+        let file = File::create(&path)?;
+        let builder = match addr {
+            SocketAddr::V4(_) =>
+                TcpBuilder::new_v4()?,
+            SocketAddr::V6(_) =>
+                TcpBuilder::new_v6()?,
+        };
+        let stream = builder.to_tcp_stream()?;
+        stream.set_nonblocking(true)?;
+        // This will fail with EINPROGRESS, errors are reported when the socket becomes readable
+        let _ = stream.connect(addr);
+        register_for_r(self.poll, stream.as_raw_fd());
+        self.transfers[idx].state = State::Receiving { stream, file };
+        Ok(path)
+    }
+
+    /// Reject an offer or cancel a transfer. Returns the removed transfer.
+    pub fn cancel(&mut self, id: usize) -> Option<Transfer> {
+        self.find_idx(id).ok().map(|idx| self.remove(idx))
+    }
+
+    /// Offer a file. Listens on `ip` (our address on the server connection) and returns id of the
+    /// transfer and arguments of the `DCC` CTCP request to send to the peer.
+    pub fn send(
+        &mut self,
+        serv_name: &str,
+        nick: &str,
+        path: &Path,
+        ip: IpAddr,
+    ) -> io::Result<(usize, String)> {
+        let file = File::open(path)?;
+        let size = file.metadata()?.len();
+        let filename = path.file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Not a file"))?;
+        let listener = TcpListener::bind(SocketAddr::new(ip, 0))?;
+        listener.set_nonblocking(true)?;
+        let addr = listener.local_addr()?;
+        register_for_r(self.poll, listener.as_raw_fd());
+        let id = self.new_transfer(
+            serv_name,
+            nick,
+            &filename,
+            Some(size),
+            State::Listening { listener, file },
+        );
+        Ok((id, format_dcc_send(&filename, addr, size)))
+    }
+
+    /// Is this token of a transfer socket?
+    pub fn has_token(&self, token: Token) -> bool {
+        self.transfers
+            .iter()
+            .any(|transfer| transfer.state.fd().map(|fd| Token(fd as usize)) == Some(token))
+    }
+
+    /// Handle a readiness event of a transfer socket.
+    pub fn ready(&mut self, token: Token, readiness: Ready, evs: &mut Vec<TransferEv>) {
+        let idx = match self.transfers
+            .iter()
+            .position(|transfer| transfer.state.fd().map(|fd| Token(fd as usize)) == Some(token))
+        {
+            None =>
+                return,
+            Some(idx) =>
+                idx,
+        };
+        let ret = self.transfers[idx].ready(self.poll, readiness);
+        match ret {
+            Ok(true) => {
+                let transfer = self.remove(idx);
+                evs.push(TransferEv::Done(format!(
+                    "{}: done, {} bytes",
+                    transfer.name(),
+                    transfer.transferred
+                )));
+            }
+            Ok(false) => {
+                let transfer = &mut self.transfers[idx];
+                if let Some(size) = transfer.size {
+                    let tens = if size == 0 { 10 } else { transfer.transferred * 10 / size };
+                    if tens > transfer.reported && tens < 10 {
+                        transfer.reported = tens;
+                        evs.push(TransferEv::Progress(format!(
+                            "{}: {}",
+                            transfer.name(),
+                            transfer.progress()
+                        )));
+                    }
+                }
+            }
+            Err(err) => {
+                let transfer = self.remove(idx);
+                evs.push(TransferEv::Failed(format!("{}: {}", transfer.name(), err)));
+            }
+        }
+    }
+
+    fn find_idx(&self, id: usize) -> io::Result<usize> {
+        self.transfers
+            .iter()
+            .position(|transfer| transfer.id == id)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No such transfer"))
+    }
+
+    fn remove(&mut self, idx: usize) -> Transfer {
+        let transfer = self.transfers.remove(idx);
+        if let Some(fd) = transfer.state.fd() {
+            deregister(self.poll, fd);
+        }
+        transfer
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// DCC CHAT
+
+/// Parse arguments of a `DCC` CTCP request offering a chat: `CHAT chat <ip> <port>`.
+pub fn parse_dcc_chat(args: &str) -> Option<SocketAddr> {
+    let mut words = args.split_whitespace();
+    match (words.next(), words.next()) {
+        (Some(chat), Some(_)) if chat.eq_ignore_ascii_case("CHAT") =>
+            {}
+        _ =>
+            return None,
+    }
+    let ip = parse_dcc_ip(words.next()?)?;
+    let port = words.next()?.parse::<u16>().ok()?;
+    if port == 0 {
+        return None;
+    }
+    Some(SocketAddr::new(ip, port))
+}
+
+/// Arguments of a `DCC` CTCP request offering a chat.
+pub fn format_dcc_chat(addr: SocketAddr) -> String {
+    format!("CHAT chat {} {}", format_dcc_ip(addr.ip()), addr.port())
+}
+
+pub struct Chat {
+    pub serv_name: String,
+    pub nick: String,
+    /// Casemapping of the server, for matching the nick.
+    casemapping: CaseMapping,
+    state: ChatState,
+    /// Incomplete line received.
+    in_buf: Vec<u8>,
+    /// Lines not sent yet.
+    out_buf: Vec<u8>,
+}
+
+enum ChatState {
+    /// Incoming offer, waiting for `/dcc chat <nick>`.
+    Offered { addr: SocketAddr },
+    /// Outgoing offer, waiting for the peer to connect.
+    Listening { listener: TcpListener },
+    /// Connecting to the peer after accepting an offer.
+    Connecting { stream: TcpStream },
+    Connected { stream: TcpStream },
+    /// Temporarily used while moving sockets between states.
+    Closed,
+}
+
+impl ChatState {
+    fn fd(&self) -> Option<RawFd> {
+        match *self {
+            ChatState::Listening { ref listener } =>
+                Some(listener.as_raw_fd()),
+            ChatState::Connecting { ref stream } | ChatState::Connected { ref stream } =>
+                Some(stream.as_raw_fd()),
+            ChatState::Offered { .. } | ChatState::Closed =>
+                None,
+        }
+    }
+}
+
+impl Chat {
+    fn is(&self, serv_name: &str, nick: &str) -> bool {
+        self.serv_name == serv_name && self.casemapping.eq(&self.nick, nick)
+    }
+
+    /// Handle a readiness event. Received lines are pushed to `evs`.
+    fn ready(&mut self, poll: &Poll, readiness: Ready, evs: &mut Vec<ChatEv>) -> io::Result<()> {
+        let state = mem::replace(&mut self.state, ChatState::Closed);
+        self.state = match state {
+            ChatState::Listening { listener } => {
+                let stream = match listener.accept() {
+                    Ok((stream, _)) =>
+                        stream,
+                    Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => {
+                        self.state = ChatState::Listening { listener };
+                        return Ok(());
+                    }
+                    Err(err) =>
+                        return Err(err),
+                };
+                deregister(poll, listener.as_raw_fd());
+                stream.set_nonblocking(true)?;
+                register_for_r(poll, stream.as_raw_fd());
+                evs.push(self.ev(ChatEvKind::Connected));
+                ChatState::Connected { stream }
+            }
+            ChatState::Connecting { stream } => {
+                // connection is established (or failed) when the socket becomes writable
+                if let Some(err) = stream.take_error()? {
+                    return Err(err);
+                }
+                evs.push(self.ev(ChatEvKind::Connected));
+                let mut state = ChatState::Connected { stream };
+                self.flush(poll, &mut state)?;
+                state
+            }
+            ChatState::Connected { mut stream } => {
+                if readiness.is_readable() {
+                    self.receive(&mut stream, evs)?;
+                }
+                let mut state = ChatState::Connected { stream };
+                if readiness.is_writable() {
+                    self.flush(poll, &mut state)?;
+                }
+                state
+            }
+            ChatState::Offered { .. } | ChatState::Closed =>
+                state,
+        };
+        Ok(())
+    }
+
+    fn receive(&mut self, stream: &mut TcpStream, evs: &mut Vec<ChatEv>) -> io::Result<()> {
+        let mut buf = [0u8; 1024];
+        let mut closed = false;
+        loop {
+            match stream.read(&mut buf) {
+                Ok(0) => {
+                    closed = true;
+                    break;
+                }
+                Ok(n) =>
+                    self.in_buf.extend_from_slice(&buf[..n]),
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock =>
+                    break,
+                Err(err) =>
+                    return Err(err),
+            }
+        }
+        while let Some(idx) = self.in_buf.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.in_buf.drain(0..idx + 1).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_right_matches(|c| c == '\r' || c == '\n');
+            if !line.is_empty() {
+                evs.push(self.ev(ChatEvKind::Msg(line.to_owned())));
+            }
+        }
+        if closed {
+            Err(connection_closed())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Write buffered lines. Socket is registered for writing until the buffer is empty.
+    fn flush(&mut self, poll: &Poll, state: &mut ChatState) -> io::Result<()> {
+        if let ChatState::Connected { ref mut stream } = *state {
+            while !self.out_buf.is_empty() {
+                match stream.write(&self.out_buf) {
+                    Ok(n) => {
+                        self.out_buf.drain(0..n);
+                    }
+                    Err(ref err) if err.kind() == io::ErrorKind::WouldBlock =>
+                        break,
+                    Err(err) =>
+                        return Err(err),
+                }
+            }
+            if self.out_buf.is_empty() {
+                reregister_for_r(poll, stream.as_raw_fd());
+            } else {
+                reregister_for_rw(poll, stream.as_raw_fd());
+            }
+        }
+        Ok(())
+    }
+
+    fn ev(&self, kind: ChatEvKind) -> ChatEv {
+        ChatEv {
+            serv_name: self.serv_name.clone(),
+            nick: self.nick.clone(),
+            kind,
+        }
+    }
+}
+
+pub struct ChatEv {
+    pub serv_name: String,
+    pub nick: String,
+    pub kind: ChatEvKind,
+}
+
+pub enum ChatEvKind {
+    Connected,
+    /// A line received from the peer. Actions are sent as CTCP ACTION messages.
+    Msg(String),
+    Closed(String),
+}
+
+/// DCC CHAT connections, one per server and nick.
+pub struct Chats<'poll> {
+    poll: &'poll Poll,
+    chats: Vec<Chat>,
+}
+
+impl<'poll> Chats<'poll> {
+    pub fn new(poll: &'poll Poll) -> Chats<'poll> {
+        Chats {
+            poll,
+            chats: vec![],
+        }
+    }
+
+    /// Add an incoming offer. An existing offer from the nick is replaced. Returns `false` if we
+    /// already have a chat with the nick.
+    pub fn offer(
+        &mut self,
+        casemapping: CaseMapping,
+        serv_name: &str,
+        nick: &str,
+        addr: SocketAddr,
+    ) -> bool {
+        match self.find_idx(serv_name, nick) {
+            Some(idx) =>
+                match self.chats[idx].state {
+                    ChatState::Offered { .. } => {
+                        self.chats[idx].state = ChatState::Offered { addr };
+                        true
+                    }
+                    _ =>
+                        false,
+                },
+            None => {
+                self.new_chat(casemapping, serv_name, nick, ChatState::Offered { addr });
+                true
+            }
+        }
+    }
+
+    pub fn has_offer(&self, serv_name: &str, nick: &str) -> bool {
+        self.find_idx(serv_name, nick)
+            .map(|idx| match self.chats[idx].state {
+                ChatState::Offered { .. } =>
+                    true,
+                _ =>
+                    false,
+            })
+            .unwrap_or(false)
+    }
+
+    /// Accept an offer from the nick. `ChatEvKind::Connected` is generated when the connection is
+    /// established.
+    pub fn accept(&mut self, serv_name: &str, nick: &str) -> io::Result<()> {
+        let idx = self.find_idx(serv_name, nick)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No chat offers"))?;
+        let addr = match self.chats[idx].state {
+            ChatState::Offered { addr } =>
+                addr,
+            _ =>
+                return Err(io::Error::new(io::ErrorKind::Other, "Chat already accepted")),
+        };
+        let builder = match addr {
+            SocketAddr::V4(_) =>
+                TcpBuilder::new_v4()?,
+            SocketAddr::V6(_) =>
+                TcpBuilder::new_v6()?,
+        };
+        let stream = builder.to_tcp_stream()?;
+        stream.set_nonblocking(true)?;
+        // This will fail with EINPROGRESS, errors are reported when the socket becomes writable
+        let _ = stream.connect(addr);
+        register_for_r(self.poll, stream.as_raw_fd());
+        reregister_for_rw(self.poll, stream.as_raw_fd());
+        self.chats[idx].state = ChatState::Connecting { stream };
+        Ok(())
+    }
+
+    /// Offer a chat. Listens on `ip` (our address on the server connection) and returns arguments
+    /// of the `DCC` CTCP request to send to the peer.
+    pub fn listen(
+        &mut self,
+        casemapping: CaseMapping,
+        serv_name: &str,
+        nick: &str,
+        ip: IpAddr,
+    ) -> io::Result<String> {
+        if self.find_idx(serv_name, nick).is_some() {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, "Chat already exists"));
+        }
+        let listener = TcpListener::bind(SocketAddr::new(ip, 0))?;
+        listener.set_nonblocking(true)?;
+        let addr = listener.local_addr()?;
+        register_for_r(self.poll, listener.as_raw_fd());
+        self.new_chat(casemapping, serv_name, nick, ChatState::Listening { listener });
+        Ok(format_dcc_chat(addr))
+    }
+
+    /// Send a line to the peer. Lines are buffered until the socket is ready for writing.
+    pub fn send(&mut self, serv_name: &str, nick: &str, msg: &str) -> io::Result<()> {
+        let idx = self.find_idx(serv_name, nick)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Not connected"))?;
+        let chat = &mut self.chats[idx];
+        match chat.state {
+            ChatState::Connected { ref stream } => {
+                chat.out_buf.extend_from_slice(msg.as_bytes());
+                chat.out_buf.push(b'\n');
+                reregister_for_rw(self.poll, stream.as_raw_fd());
+                Ok(())
+            }
+            _ =>
+                Err(io::Error::new(io::ErrorKind::NotConnected, "Not connected")),
+        }
+    }
+
+    /// Close a chat or reject an offer. Returns `false` if there's no chat with the nick.
+    pub fn close(&mut self, serv_name: &str, nick: &str) -> bool {
+        match self.find_idx(serv_name, nick) {
+            Some(idx) => {
+                self.remove(idx);
+                true
+            }
+            None =>
+                false,
+        }
+    }
+
+    /// Close all chats and offers of a server.
+    pub fn close_serv(&mut self, serv_name: &str) {
+        while let Some(idx) = self.chats.iter().position(|chat| chat.serv_name == serv_name) {
+            self.remove(idx);
+        }
+    }
+
+    /// Is this token of a chat socket?
+    pub fn has_token(&self, token: Token) -> bool {
+        self.find_token_idx(token).is_some()
+    }
+
+    /// Handle a readiness event of a chat socket.
+    pub fn ready(&mut self, token: Token, readiness: Ready, evs: &mut Vec<ChatEv>) {
+        if let Some(idx) = self.find_token_idx(token) {
+            if let Err(err) = self.chats[idx].ready(self.poll, readiness, evs) {
+                let chat = self.remove(idx);
+                evs.push(chat.ev(ChatEvKind::Closed(err.to_string())));
+            }
+        }
+    }
+
+    fn new_chat(
+        &mut self,
+        casemapping: CaseMapping,
+        serv_name: &str,
+        nick: &str,
+        state: ChatState,
+    ) {
+        self.chats.push(Chat {
+            serv_name: serv_name.to_owned(),
+            nick: nick.to_owned(),
+            casemapping,
+            state,
+            in_buf: vec![],
+            out_buf: vec![],
+        });
+    }
+
+    fn find_idx(&self, serv_name: &str, nick: &str) -> Option<usize> {
+        self.chats.iter().position(|chat| chat.is(serv_name, nick))
+    }
+
+    fn find_token_idx(&self, token: Token) -> Option<usize> {
+        self.chats
+            .iter()
+            .position(|chat| chat.state.fd().map(|fd| Token(fd as usize)) == Some(token))
+    }
+
+    fn remove(&mut self, idx: usize) -> Chat {
+        let chat = self.chats.remove(idx);
+        if let Some(fd) = chat.state.fd() {
+            deregister(self.poll, fd);
+        }
+        chat
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mio::Events;
+    use std::env;
+    use std::process;
+    use std::time::Duration;
+
+    #[test]
+    fn test_parse_dcc_send() {
+        assert_eq!(
+            parse_dcc_send("SEND notes.txt 2130706433 5000 1234"),
+            Some(DccSend {
+                filename: "notes.txt".to_owned(),
+                addr: "127.0.0.1:5000".parse().unwrap(),
+                size: Some(1234),
+            })
+        );
+        assert_eq!(
+            parse_dcc_send("SEND \"my notes.txt\" ::1 5000"),
+            Some(DccSend {
+                filename: "my notes.txt".to_owned(),
+                addr: "[::1]:5000".parse().unwrap(),
+                size: None,
+            })
+        );
+        // passive DCC
+        assert_eq!(parse_dcc_send("SEND notes.txt 2130706433 0 1234 42"), None);
+        assert_eq!(parse_dcc_send("CHAT chat 2130706433 5000"), None);
+        assert_eq!(parse_dcc_send("SEND notes.txt"), None);
+    }
+
+    #[test]
+    fn test_format_dcc_send() {
+        let addr = "127.0.0.1:5000".parse().unwrap();
+        assert_eq!(
+            format_dcc_send("notes.txt", addr, 1234),
+            "SEND notes.txt 2130706433 5000 1234"
+        );
+        let offer = parse_dcc_send(&format_dcc_send("my notes.txt", addr, 1234)).unwrap();
+        assert_eq!(offer.filename, "my notes.txt");
+        assert_eq!(offer.addr, addr);
+        assert_eq!(offer.size, Some(1234));
+    }
+
+    #[test]
+    fn test_parse_dcc_chat() {
+        let addr = "127.0.0.1:5000".parse().unwrap();
+        assert_eq!(parse_dcc_chat("CHAT chat 2130706433 5000"), Some(addr));
+        assert_eq!(parse_dcc_chat(&format_dcc_chat(addr)), Some(addr));
+        assert_eq!(parse_dcc_chat("CHAT chat 2130706433 0"), None);
+        assert_eq!(parse_dcc_chat("SEND chat 2130706433 5000"), None);
+    }
+
+    #[test]
+    fn test_download_path() {
+        let dir = Path::new("/nonexistent");
+        assert_eq!(download_path(dir, "notes.txt"), dir.join("notes.txt"));
+        assert_eq!(download_path(dir, "../../.bashrc"), dir.join(".bashrc"));
+        assert_eq!(download_path(dir, ".."), dir.join("dcc_file"));
+    }
+
+    #[test]
+    fn test_loopback_transfer() {
+        let dir = env::temp_dir().join(format!("tiny_dcc_test_{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let contents: Vec<u8> = (0..100_000).map(|i| (i % 251) as u8).collect();
+        let src_path = dir.join("test file.bin");
+        File::create(&src_path).unwrap().write_all(&contents).unwrap();
+
+        let poll = Poll::new().unwrap();
+        let mut transfers = Transfers::new(&poll);
+        let localhost = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1));
+        let (_, args) = transfers.send("serv", "osa1", &src_path, localhost).unwrap();
+
+        let offer = parse_dcc_send(&args).unwrap();
+        assert_eq!(offer.filename, "test file.bin");
+        assert_eq!(offer.size, Some(contents.len() as u64));
+        let recv_id = transfers.offer("serv", "tiny", offer);
+        assert_eq!(transfers.last_offer(), Some(recv_id));
+        let download_dir = dir.join("downloads");
+        let path = transfers.accept(recv_id, &download_dir).unwrap();
+        assert_eq!(path, download_dir.join("test file.bin"));
+
+        let mut events = Events::with_capacity(10);
+        let mut evs = vec![];
+        for _ in 0..1000 {
+            if transfers.iter().next().is_none() {
+                break;
+            }
+            poll.poll(&mut events, Some(Duration::from_millis(100))).unwrap();
+            for event in events.iter() {
+                transfers.ready(event.token(), event.readiness(), &mut evs);
+            }
+        }
+
+        let n_done = evs.iter()
+            .filter(|ev| match **ev {
+                TransferEv::Done(_) =>
+                    true,
+                TransferEv::Progress(_) =>
+                    false,
+                TransferEv::Failed(ref err) =>
+                    panic!("{}", err),
+            })
+            .count();
+        assert_eq!(n_done, 2);
+
+        let mut received = vec![];
+        File::open(&path).unwrap().read_to_end(&mut received).unwrap();
+        assert!(received == contents);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn poll_chats(poll: &Poll, chats: &mut Chats, evs: &mut Vec<ChatEv>, n_evs: usize) {
+        let mut events = Events::with_capacity(10);
+        for _ in 0..100 {
+            if evs.len() >= n_evs {
+                return;
+            }
+            poll.poll(&mut events, Some(Duration::from_millis(100))).unwrap();
+            for event in events.iter() {
+                chats.ready(event.token(), event.readiness(), evs);
+            }
+        }
+        panic!("Expected {} events, got {}", n_evs, evs.len());
+    }
+
+    #[test]
+    fn test_loopback_chat() {
+        let poll = Poll::new().unwrap();
+        let mut chats = Chats::new(&poll);
+        let localhost = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1));
+        let cm = CaseMapping::Rfc1459;
+
+        // both sides of the chat in the same `Chats`, on different servers
+        let args = chats.listen(cm, "serv1", "osa1", localhost).unwrap();
+        assert!(chats.listen(cm, "serv1", "OSA1", localhost).is_err());
+        let addr = parse_dcc_chat(&args).unwrap();
+        assert!(chats.offer(cm, "serv2", "tiny", addr));
+        assert!(chats.offer(cm, "serv2", "tiny[m]", addr));
+        assert!(chats.close("serv2", "TINY{M}"));
+        assert!(chats.has_offer("serv2", "tiny"));
+        chats.accept("serv2", "tiny").unwrap();
+
+        let mut evs = vec![];
+        poll_chats(&poll, &mut chats, &mut evs, 2);
+        assert!(evs.iter().all(|ev| match ev.kind {
+            ChatEvKind::Connected =>
+                true,
+            _ =>
+                false,
+        }));
+        evs.clear();
+
+        chats.send("serv1", "osa1", "hello").unwrap();
+        chats.send("serv1", "osa1", "\x01ACTION waves\x01").unwrap();
+        chats.send("serv2", "tiny", "hi").unwrap();
+        poll_chats(&poll, &mut chats, &mut evs, 3);
+        let mut msgs: Vec<(String, String)> = evs.drain(..)
+            .map(|ev| match ev.kind {
+                ChatEvKind::Msg(msg) =>
+                    (ev.serv_name, msg),
+                _ =>
+                    panic!("Unexpected chat event"),
+            })
+            .collect();
+        msgs.sort();
+        assert_eq!(
+            msgs,
+            vec![
+                ("serv1".to_owned(), "hi".to_owned()),
+                ("serv2".to_owned(), "\x01ACTION waves\x01".to_owned()),
+                ("serv2".to_owned(), "hello".to_owned()),
+            ]
+        );
+
+        assert!(chats.close("serv1", "osa1"));
+        assert!(!chats.close("serv1", "osa1"));
+        poll_chats(&poll, &mut chats, &mut evs, 1);
+        assert_eq!(evs[0].serv_name, "serv2");
+        match evs[0].kind {
+            ChatEvKind::Closed(_) =>
+                {}
+            _ =>
+                panic!("Unexpected chat event"),
+        }
+        assert!(chats.send("serv2", "tiny", "hello?").is_err());
+    }
+}
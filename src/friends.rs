@@ -0,0 +1,177 @@
+//! Online status of friends, from MONITOR (730 RPL_MONONLINE, 731 RPL_MONOFFLINE) or, for servers
+//! without MONITOR, from periodic ISON queries (303 RPL_ISON).
+
+use isupport::CaseMapping;
+use wire::Reply;
+
+pub struct Friends {
+    nicks: Vec<String>,
+    /// Friends currently online, as reported by the server.
+    online: Vec<String>,
+    /// An ISON query was sent, the next 303 is the reply.
+    ison_pending: bool,
+}
+
+impl Friends {
+    pub fn new(nicks: Vec<String>) -> Friends {
+        Friends {
+            nicks,
+            online: vec![],
+            ison_pending: false,
+        }
+    }
+
+    pub fn get_nicks(&self) -> &[String] {
+        &self.nicks
+    }
+
+    /// Friends currently online. Kept over reconnects until the server reports them again.
+    pub fn get_online(&self) -> &[String] {
+        &self.online
+    }
+
+    /// Prepare for a new connection. Online friends are kept and updated with the first replies
+    /// after the reconnect, so friends who stayed online are not reported as coming online again.
+    pub fn reconnect(&mut self) {
+        self.ison_pending = false;
+    }
+
+    pub fn ison_sent(&mut self) {
+        self.ison_pending = true;
+    }
+
+    pub fn is_ison_pending(&self) -> bool {
+        self.ison_pending
+    }
+
+    /// Update online friends with a reply. Returns friends that came online (`true`) or went
+    /// offline (`false`).
+    pub fn add(&mut self, casemapping: CaseMapping, reply: &Reply) -> Vec<(String, bool)> {
+        let mut changes = vec![];
+        match *reply {
+            Reply::MonOnline { ref targets, .. } =>
+                for target in targets.split(',') {
+                    let nick = target.split('!').next().unwrap();
+                    if self.set_online(casemapping, nick) {
+                        changes.push((nick.to_owned(), true));
+                    }
+                },
+            Reply::MonOffline { ref targets, .. } =>
+                for nick in targets.split(',') {
+                    if self.set_offline(casemapping, nick) {
+                        changes.push((nick.to_owned(), false));
+                    }
+                },
+            // RPL_ISON is also sent for ISON queries sent with `/quote`
+            Reply::IsOn { ref nicks, .. } if self.ison_pending => {
+                self.ison_pending = false;
+                let online: Vec<&str> = nicks.split_whitespace().collect();
+                let gone: Vec<String> = self.online
+                    .iter()
+                    .filter(|nick| !online.iter().any(|nick_| casemapping.eq(nick, nick_)))
+                    .cloned()
+                    .collect();
+                for nick in gone {
+                    self.set_offline(casemapping, &nick);
+                    changes.push((nick, false));
+                }
+                for nick in online {
+                    if self.set_online(casemapping, nick) {
+                        changes.push((nick.to_owned(), true));
+                    }
+                }
+            }
+            _ =>
+                {}
+        }
+        changes
+    }
+
+    /// Returns whether the friend was offline.
+    fn set_online(&mut self, casemapping: CaseMapping, nick: &str) -> bool {
+        let is_friend = self.nicks.iter().any(|nick_| casemapping.eq(nick, nick_));
+        let is_online = self.online.iter().any(|nick_| casemapping.eq(nick, nick_));
+        if is_friend && !is_online {
+            self.online.push(nick.to_owned());
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Returns whether the friend was online.
+    fn set_offline(&mut self, casemapping: CaseMapping, nick: &str) -> bool {
+        match self.online
+            .iter()
+            .position(|nick_| casemapping.eq(nick, nick_))
+        {
+            Some(idx) => {
+                self.online.remove(idx);
+                true
+            }
+            None =>
+                false,
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wire::reply;
+
+    #[test]
+    fn test_monitor() {
+        let cm = CaseMapping::Rfc1459;
+        let mut friends = Friends::new(vec!["osa1".to_owned(), "tiny".to_owned()]);
+        assert_eq!(
+            friends.add(cm, &reply(730, &["me", "OSA1!~osa1@host.com,foo!~foo@host.com"])),
+            vec![("OSA1".to_owned(), true)]
+        );
+        // already online
+        assert_eq!(friends.add(cm, &reply(730, &["me", "osa1!~osa1@host.com"])), vec![]);
+        assert_eq!(
+            friends.add(cm, &reply(731, &["me", "osa1,tiny"])),
+            vec![("osa1".to_owned(), false)]
+        );
+    }
+
+    #[test]
+    fn test_ison() {
+        let cm = CaseMapping::Rfc1459;
+        let mut friends = Friends::new(vec!["osa1".to_owned(), "tiny".to_owned()]);
+        // not our query
+        assert_eq!(friends.add(cm, &reply(303, &["me", "osa1"])), vec![]);
+
+        friends.ison_sent();
+        assert_eq!(
+            friends.add(cm, &reply(303, &["me", "osa1"])),
+            vec![("osa1".to_owned(), true)]
+        );
+        friends.ison_sent();
+        assert_eq!(
+            friends.add(cm, &reply(303, &["me", "tiny"])),
+            vec![("osa1".to_owned(), false), ("tiny".to_owned(), true)]
+        );
+        friends.ison_sent();
+        assert_eq!(friends.add(cm, &reply(303, &["me", "tiny"])), vec![]);
+    }
+
+    #[test]
+    fn test_reconnect() {
+        let cm = CaseMapping::Rfc1459;
+        let mut friends = Friends::new(vec!["osa1".to_owned(), "tiny".to_owned()]);
+        friends.add(cm, &reply(730, &["me", "osa1!~osa1@host.com,tiny!~tiny@host.com"]));
+        friends.reconnect();
+        assert_eq!(friends.get_online(), &["osa1".to_owned(), "tiny".to_owned()]);
+        // replies to `MONITOR +` after the reconnect
+        assert_eq!(friends.add(cm, &reply(730, &["me", "osa1!~osa1@host.com"])), vec![]);
+        assert_eq!(
+            friends.add(cm, &reply(731, &["me", "tiny"])),
+            vec![("tiny".to_owned(), false)]
+        );
+        assert_eq!(friends.get_online(), &["osa1".to_owned()]);
+    }
+}
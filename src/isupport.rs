@@ -0,0 +1,497 @@
+//! RPL_ISUPPORT (005) parsing. See http://modern.ircdocs.horse/#rplisupport-005 for the tokens.
+
+use std::collections::BTreeMap;
+
+/// Features advertised by a server in RPL_ISUPPORT. Fields are initialized with the defaults
+/// suggested by the spec, and updated as 005 replies come in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ISupport {
+    /// CHANTYPES: Channel name prefixes.
+    pub chantypes: String,
+
+    /// PREFIX: Channel membership modes and their nick prefixes, in decreasing rank (e.g.
+    /// `[('o', '@'), ('v', '+')]`).
+    pub prefix: Vec<(char, char)>,
+
+    /// CHANMODES: Channel modes, in four groups.
+    pub chanmodes: ChanModes,
+
+    /// CASEMAPPING: How nicks and channel names are compared.
+    pub casemapping: CaseMapping,
+
+    /// NICKLEN: Max nick length.
+    pub nicklen: Option<usize>,
+
+    /// CHANNELLEN: Max channel name length.
+    pub channellen: Option<usize>,
+
+    /// TOPICLEN: Max topic length.
+    pub topiclen: Option<usize>,
+
+    /// NETWORK: Name of the IRC network.
+    pub network: Option<String>,
+
+    /// MAXTARGETS: Max number of targets in a PRIVMSG or NOTICE.
+    pub maxtargets: Option<usize>,
+
+    /// MODES: Max number of modes with parameters in a single MODE command.
+    pub modes: Option<usize>,
+
+    /// STATUSMSG: Prefixes that can be added to a channel name to only message users with that
+    /// membership prefix (e.g. `@#tiny`).
+    pub statusmsg: String,
+
+    /// Tokens not listed above, with (unescaped) values. Tokens without values map to an empty
+    /// string.
+    pub other: BTreeMap<String, String>,
+}
+
+/// Channel modes, grouped by how they take parameters.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChanModes {
+    /// Type A: Modes that add or remove an address to or from a list (e.g. `b` for bans). Always
+    /// take a parameter.
+    pub a: String,
+    /// Type B: Modes that always take a parameter (e.g. `k` for channel key).
+    pub b: String,
+    /// Type C: Modes that take a parameter only when set (e.g. `l` for user limit).
+    pub c: String,
+    /// Type D: Modes that never take a parameter (e.g. `m` for moderated).
+    pub d: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaseMapping {
+    /// Only `A-Z` and `a-z` are equivalent.
+    Ascii,
+    /// Like `Ascii`, but also `[]\~` and `{}|^` are equivalent.
+    Rfc1459,
+    /// Like `Rfc1459`, but `~` and `^` are not equivalent.
+    StrictRfc1459,
+}
+
+impl CaseMapping {
+    /// Map a character to its lowercase equivalent.
+    pub fn to_lower_char(self, c: char) -> char {
+        if c.is_ascii_uppercase() {
+            return c.to_ascii_lowercase();
+        }
+        match (self, c) {
+            (CaseMapping::Ascii, _) =>
+                c,
+            (_, '[') =>
+                '{',
+            (_, ']') =>
+                '}',
+            (_, '\\') =>
+                '|',
+            (CaseMapping::Rfc1459, '~') =>
+                '^',
+            _ =>
+                c,
+        }
+    }
+
+    pub fn to_lower(self, s: &str) -> String {
+        s.chars().map(|c| self.to_lower_char(c)).collect()
+    }
+
+    /// Are the nicks or channel names equal under this casemapping?
+    pub fn eq(self, s1: &str, s2: &str) -> bool {
+        s1.len() == s2.len()
+            && s1.chars()
+                .zip(s2.chars())
+                .all(|(c1, c2)| self.to_lower_char(c1) == self.to_lower_char(c2))
+    }
+
+    /// Does `haystack` contain `needle` under this casemapping? Used for finding mentions of a
+    /// nick in a message.
+    pub fn contains(self, haystack: &str, needle: &str) -> bool {
+        // only ASCII characters are mapped so byte lengths don't change
+        self.to_lower(haystack).contains(&self.to_lower(needle))
+    }
+}
+
+impl Default for ChanModes {
+    fn default() -> ChanModes {
+        ChanModes {
+            a: "b".to_owned(),
+            b: "k".to_owned(),
+            c: "l".to_owned(),
+            d: "imnpst".to_owned(),
+        }
+    }
+}
+
+impl Default for ISupport {
+    fn default() -> ISupport {
+        ISupport {
+            chantypes: "#&".to_owned(),
+            prefix: vec![('o', '@'), ('v', '+')],
+            chanmodes: ChanModes::default(),
+            casemapping: CaseMapping::Rfc1459,
+            nicklen: None,
+            channellen: None,
+            topiclen: None,
+            network: None,
+            maxtargets: None,
+            modes: None,
+            statusmsg: String::new(),
+            other: BTreeMap::new(),
+        }
+    }
+}
+
+impl ISupport {
+    /// Update the model with the tokens of a 005 reply, e.g. `["CHANTYPES=#",
+    /// "NETWORK=freenode"]`.
+    pub fn parse_tokens(&mut self, tokens: &[String]) {
+        for token in tokens {
+            self.parse_token(token);
+        }
+    }
+
+    fn parse_token(&mut self, token: &str) {
+        if token.starts_with('-') {
+            self.reset_token(&token[1..]);
+            return;
+        }
+
+        let (name, value) = match token.find('=') {
+            None =>
+                (token, String::new()),
+            Some(idx) =>
+                (&token[..idx], unescape_value(&token[idx + 1..])),
+        };
+
+        match name {
+            "CHANTYPES" =>
+                self.chantypes = value,
+            "PREFIX" =>
+                match parse_prefix(&value) {
+                    Some(prefix) =>
+                        self.prefix = prefix,
+                    None =>
+                        {}
+                },
+            "CHANMODES" => {
+                let mut groups = value.split(',').map(str::to_owned);
+                self.chanmodes = ChanModes {
+                    a: groups.next().unwrap_or_default(),
+                    b: groups.next().unwrap_or_default(),
+                    c: groups.next().unwrap_or_default(),
+                    d: groups.next().unwrap_or_default(),
+                };
+            }
+            "CASEMAPPING" =>
+                // Unknown casemappings (e.g. rfc7613) are not supported, the current one is kept
+                match value.as_str() {
+                    "ascii" =>
+                        self.casemapping = CaseMapping::Ascii,
+                    "rfc1459" =>
+                        self.casemapping = CaseMapping::Rfc1459,
+                    "strict-rfc1459" =>
+                        self.casemapping = CaseMapping::StrictRfc1459,
+                    _ =>
+                        {}
+                },
+            "NICKLEN" =>
+                self.nicklen = value.parse().ok(),
+            "CHANNELLEN" =>
+                self.channellen = value.parse().ok(),
+            "TOPICLEN" =>
+                self.topiclen = value.parse().ok(),
+            "NETWORK" =>
+                self.network = if value.is_empty() { None } else { Some(value) },
+            "MAXTARGETS" =>
+                self.maxtargets = value.parse().ok(),
+            "MODES" =>
+                self.modes = value.parse().ok(),
+            "STATUSMSG" =>
+                self.statusmsg = value,
+            _ => {
+                self.other.insert(name.to_owned(), value);
+            }
+        }
+    }
+
+    /// Handle a `-TOKEN` parameter: the server no longer advertises the token, go back to the
+    /// default.
+    fn reset_token(&mut self, name: &str) {
+        let default = ISupport::default();
+        match name {
+            "CHANTYPES" =>
+                self.chantypes = default.chantypes,
+            "PREFIX" =>
+                self.prefix = default.prefix,
+            "CHANMODES" =>
+                self.chanmodes = default.chanmodes,
+            "CASEMAPPING" =>
+                self.casemapping = default.casemapping,
+            "NICKLEN" =>
+                self.nicklen = default.nicklen,
+            "CHANNELLEN" =>
+                self.channellen = default.channellen,
+            "TOPICLEN" =>
+                self.topiclen = default.topiclen,
+            "NETWORK" =>
+                self.network = default.network,
+            "MAXTARGETS" =>
+                self.maxtargets = default.maxtargets,
+            "MODES" =>
+                self.modes = default.modes,
+            "STATUSMSG" =>
+                self.statusmsg = default.statusmsg,
+            _ => {
+                self.other.remove(name);
+            }
+        }
+    }
+
+    /// Value of a token not modelled by a field of `ISupport`.
+    pub fn get_other(&self, name: &str) -> Option<&str> {
+        self.other.get(name).map(String::as_str)
+    }
+
+    /// Is this a channel name? Checks the first character against CHANTYPES.
+    pub fn is_chan_name(&self, name: &str) -> bool {
+        match name.chars().next() {
+            None =>
+                false,
+            Some(c) =>
+                self.chantypes.contains(c),
+        }
+    }
+
+    /// Drop a STATUSMSG prefix (e.g. the `@` in `@#tiny`) from a message target, if the rest of the
+    /// target is a channel name.
+    pub fn drop_statusmsg_prefix<'a>(&self, target: &'a str) -> &'a str {
+        match target.chars().next() {
+            Some(c) if self.statusmsg.contains(c) && self.is_chan_name(&target[c.len_utf8()..]) =>
+                &target[c.len_utf8()..],
+            _ =>
+                target,
+        }
+    }
+
+    /// Drop membership prefixes (e.g. `@` or `+`) from a nick in a NAMES reply. There may be
+    /// more than one prefix when `multi-prefix` capability is enabled.
+    pub fn drop_nick_prefix<'a>(&self, nick: &'a str) -> &'a str {
+        self.split_nick_prefix(nick).1
+    }
+
+    /// Split membership prefixes of a nick in a NAMES reply, e.g. `@+osa1` becomes
+    /// `("@+", "osa1")`.
+    pub fn split_nick_prefix<'a>(&self, nick: &'a str) -> (&'a str, &'a str) {
+        let mut idx = 0;
+        for c in nick.chars() {
+            if self.prefix.iter().any(|&(_, prefix)| prefix == c) {
+                idx += c.len_utf8();
+            } else {
+                break;
+            }
+        }
+        (&nick[..idx], &nick[idx..])
+    }
+
+    /// Membership prefix of a channel mode, e.g. `@` for `o`. `None` if the mode is not a
+    /// membership mode.
+    pub fn mode_prefix(&self, mode: char) -> Option<char> {
+        self.prefix
+            .iter()
+            .find(|&&(m, _)| m == mode)
+            .map(|&(_, prefix)| prefix)
+    }
+}
+
+/// Parse `PREFIX` value, e.g. `(ov)@+`. Empty value means the server has no membership prefixes.
+fn parse_prefix(value: &str) -> Option<Vec<(char, char)>> {
+    if value.is_empty() {
+        return Some(vec![]);
+    }
+    if !value.starts_with('(') {
+        return None;
+    }
+    let close = value.find(')')?;
+    let modes = value[1..close].chars();
+    let prefixes = value[close + 1..].chars();
+    if modes.clone().count() != prefixes.clone().count() {
+        return None;
+    }
+    Some(modes.zip(prefixes).collect())
+}
+
+/// Token values escape some characters as `\xHH`.
+fn unescape_value(value: &str) -> String {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut rest = value;
+    while !rest.is_empty() {
+        if rest.starts_with("\\x") && rest.len() >= 4 {
+            if let Ok(byte) = u8::from_str_radix(&rest[2..4], 16) {
+                bytes.push(byte);
+                rest = &rest[4..];
+                continue;
+            }
+        }
+        let c = rest.chars().next().unwrap();
+        let mut buf = [0; 4];
+        bytes.extend(c.encode_utf8(&mut buf).as_bytes());
+        rest = &rest[c.len_utf8()..];
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(tokens: &[&str]) -> Vec<String> {
+        tokens.iter().map(|s| (*s).to_owned()).collect()
+    }
+
+    #[test]
+    fn test_parse_tokens() {
+        let mut isupport = ISupport::default();
+        isupport.parse_tokens(&tokens(&[
+            "CHANTYPES=#",
+            "EXCEPTS",
+            "INVEX",
+            "CHANMODES=eIbq,k,flj,CFLMPQScgimnprstz",
+            "CHANLIMIT=#:120",
+            "PREFIX=(ov)@+",
+            "MAXLIST=bqeI:100",
+            "MODES=4",
+            "NETWORK=freenode",
+            "STATUSMSG=@+",
+            "CALLERID=g",
+            "CASEMAPPING=rfc1459",
+        ]));
+        isupport.parse_tokens(&tokens(&[
+            "CHARSET=ascii",
+            "NICKLEN=16",
+            "CHANNELLEN=50",
+            "TOPICLEN=390",
+            "DEAF=D",
+            "FNC",
+            "TARGMAX=NAMES:1,LIST:1,KICK:1,WHOIS:1,PRIVMSG:4,NOTICE:4,ACCEPT:,MONITOR:",
+        ]));
+
+        assert_eq!(isupport.chantypes, "#");
+        assert_eq!(isupport.prefix, vec![('o', '@'), ('v', '+')]);
+        assert_eq!(
+            isupport.chanmodes,
+            ChanModes {
+                a: "eIbq".to_owned(),
+                b: "k".to_owned(),
+                c: "flj".to_owned(),
+                d: "CFLMPQScgimnprstz".to_owned(),
+            }
+        );
+        assert_eq!(isupport.casemapping, CaseMapping::Rfc1459);
+        assert_eq!(isupport.nicklen, Some(16));
+        assert_eq!(isupport.channellen, Some(50));
+        assert_eq!(isupport.topiclen, Some(390));
+        assert_eq!(isupport.network, Some("freenode".to_owned()));
+        assert_eq!(isupport.maxtargets, None);
+        assert_eq!(isupport.modes, Some(4));
+        assert_eq!(isupport.statusmsg, "@+");
+        assert_eq!(isupport.get_other("EXCEPTS"), Some(""));
+        assert_eq!(isupport.get_other("CHANLIMIT"), Some("#:120"));
+        assert_eq!(isupport.get_other("FOO"), None);
+    }
+
+    #[test]
+    fn test_reset_token() {
+        let mut isupport = ISupport::default();
+        isupport.parse_tokens(&tokens(&["CHANTYPES=#", "NETWORK=Rizon", "SAFELIST"]));
+        isupport.parse_tokens(&tokens(&["-CHANTYPES", "-NETWORK", "-SAFELIST"]));
+        assert_eq!(isupport, ISupport::default());
+    }
+
+    #[test]
+    fn test_parse_prefix() {
+        assert_eq!(
+            parse_prefix("(qaohv)~&@%+"),
+            Some(vec![('q', '~'), ('a', '&'), ('o', '@'), ('h', '%'), ('v', '+')])
+        );
+        assert_eq!(parse_prefix(""), Some(vec![]));
+        assert_eq!(parse_prefix("(ov)@"), None);
+        assert_eq!(parse_prefix("ov@+"), None);
+    }
+
+    #[test]
+    fn test_unescape_value() {
+        assert_eq!(unescape_value("Example\\x20Network"), "Example Network");
+        assert_eq!(unescape_value("a\\x3Db"), "a=b");
+        assert_eq!(unescape_value("\\x5C"), "\\");
+        assert_eq!(unescape_value("\\xZZ"), "\\xZZ");
+        assert_eq!(unescape_value("\\x2"), "\\x2");
+        assert_eq!(unescape_value("ağ"), "ağ");
+    }
+
+    #[test]
+    fn test_chan_names() {
+        let mut isupport = ISupport::default();
+        assert!(isupport.is_chan_name("#tiny"));
+        assert!(isupport.is_chan_name("&tiny"));
+        assert!(!isupport.is_chan_name("tiny"));
+        assert!(!isupport.is_chan_name(""));
+
+        isupport.parse_tokens(&tokens(&["CHANTYPES=#!", "STATUSMSG=@+"]));
+        assert!(isupport.is_chan_name("!tiny"));
+        assert!(!isupport.is_chan_name("&tiny"));
+        assert_eq!(isupport.drop_statusmsg_prefix("@#tiny"), "#tiny");
+        assert_eq!(isupport.drop_statusmsg_prefix("+#tiny"), "#tiny");
+        assert_eq!(isupport.drop_statusmsg_prefix("#tiny"), "#tiny");
+        assert_eq!(isupport.drop_statusmsg_prefix("@tiny"), "@tiny");
+    }
+
+    #[test]
+    fn test_casemapping() {
+        let rfc1459 = CaseMapping::Rfc1459;
+        let strict = CaseMapping::StrictRfc1459;
+        let ascii = CaseMapping::Ascii;
+
+        assert!(rfc1459.eq("#Rust", "#rust"));
+        assert!(strict.eq("#Rust", "#rust"));
+        assert!(ascii.eq("#Rust", "#rust"));
+
+        assert!(rfc1459.eq("[osa1]\\~", "{OSA1}|^"));
+        assert!(!strict.eq("[osa1]\\~", "{OSA1}|^"));
+        assert!(strict.eq("[osa1]\\", "{OSA1}|"));
+        assert!(!ascii.eq("[osa1]", "{osa1}"));
+
+        assert!(!rfc1459.eq("osa1", "osa1_"));
+        assert!(!rfc1459.eq("ÖSA", "ösa"));
+
+        assert_eq!(rfc1459.to_lower("Tiny[m]"), "tiny{m}");
+        assert!(rfc1459.contains("hey Tiny[m]: hi", "tiny{m}"));
+        assert!(!ascii.contains("hey Tiny[m]: hi", "tiny{m}"));
+    }
+
+    #[test]
+    fn test_drop_nick_prefix() {
+        let mut isupport = ISupport::default();
+        assert_eq!(isupport.drop_nick_prefix("@osa1"), "osa1");
+        assert_eq!(isupport.drop_nick_prefix("+osa1"), "osa1");
+        assert_eq!(isupport.drop_nick_prefix("~osa1"), "~osa1");
+        assert_eq!(isupport.drop_nick_prefix("@+osa1"), "osa1");
+        isupport.parse_tokens(&tokens(&["PREFIX=(qov)~@+"]));
+        assert_eq!(isupport.drop_nick_prefix("~osa1"), "osa1");
+        assert_eq!(isupport.drop_nick_prefix("osa1"), "osa1");
+    }
+
+    #[test]
+    fn test_mode_prefix() {
+        let mut isupport = ISupport::default();
+        assert_eq!(isupport.split_nick_prefix("@+osa1"), ("@+", "osa1"));
+        assert_eq!(isupport.split_nick_prefix("osa1"), ("", "osa1"));
+        assert_eq!(isupport.mode_prefix('o'), Some('@'));
+        assert_eq!(isupport.mode_prefix('q'), None);
+        isupport.parse_tokens(&tokens(&["PREFIX=(qov)~@+"]));
+        assert_eq!(isupport.mode_prefix('q'), Some('~'));
+        assert_eq!(isupport.mode_prefix('b'), None);
+    }
+}
@@ -22,6 +22,8 @@ extern crate libc;
 extern crate mio;
 extern crate native_tls;
 extern crate net2;
+#[cfg(not(any(target_os = "macos", target_os = "ios", target_os = "windows")))]
+extern crate openssl;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
@@ -36,12 +38,23 @@ extern crate take_mut;
 #[macro_use]
 mod utils;
 
+mod chans;
 mod cmd;
 mod cmd_line_args;
 mod conn;
+mod ctcp;
+mod dcc;
+mod friends;
+mod isupport;
 mod logger;
+mod mode;
+mod netsplit;
 mod notifier;
+mod resolver;
+mod send_queue;
 mod stream;
+mod who;
+mod whois;
 mod wire;
 pub mod config;
 pub mod trie;
@@ -57,16 +70,21 @@ use mio::unix::UnixReady;
 use std::path::PathBuf;
 use std::time::Duration;
 use std::time::Instant;
+use time::Tm;
 
 use conn::{Conn, ConnErr, ConnEv};
+use ctcp::{Ctcp, CtcpCmd, CtcpKind};
+use dcc::{ChatEv, ChatEvKind, Chats, TransferEv, Transfers};
 use cmd_line_args::{CmdLineArgs, parse_cmd_line_args};
 use logger::Logger;
+use mode::ModeChange;
+use netsplit::Netsplits;
 use term_input::{Event, Input};
 use tui::tabbed::MsgSource;
 use cmd::{parse_cmd, ParseCmdResult};
 use tui::tabbed::TabStyle;
 use tui::{MsgTarget, TUIRet, Timestamp, TUI};
-use wire::{Cmd, Msg, Pfx};
+use wire::{Cmd, Msg, Pfx, Reply};
 
 ////////////////////////////////////////////////////////////////////////////////////////////////////
 
@@ -91,6 +109,7 @@ pub fn run() {
                 defaults,
                 colors,
                 log_dir,
+                download_dir,
             }) => {
                 let servers = if !server_args.is_empty() {
                     // connect only to servers that match at least one of
@@ -109,7 +128,10 @@ pub fn run() {
                 } else {
                     servers
                 };
-                Tiny::run(servers, defaults, log_dir, colors, config_path)
+                let download_dir = download_dir
+                    .map(PathBuf::from)
+                    .unwrap_or_else(config::get_default_download_dir);
+                Tiny::run(servers, defaults, log_dir, download_dir, colors, config_path)
             }
         }
     }
@@ -125,6 +147,10 @@ pub struct Tiny<'poll> {
     input_ev_handler: Input,
     logger: Logger,
     config_path: PathBuf,
+    download_dir: PathBuf,
+    transfers: Transfers<'poll>,
+    chats: Chats<'poll>,
+    netsplits: Netsplits,
 }
 
 const STDIN_TOKEN: Token = Token(libc::STDIN_FILENO as usize);
@@ -134,6 +160,7 @@ impl<'poll> Tiny<'poll> {
         servers: Vec<config::Server>,
         defaults: config::Defaults,
         log_dir: String,
+        download_dir: PathBuf,
         colors: config::Colors,
         config_path: PathBuf,
     ) {
@@ -159,6 +186,18 @@ impl<'poll> Tiny<'poll> {
             },
         );
 
+        // init "friends" tab
+        if servers.iter().any(|server| !server.friends.is_empty()) {
+            tui.new_server_tab("friends");
+            tui.add_client_msg(
+                "Friends coming online and going offline will be listed here. \
+                 Use `/friends` to list friends online.",
+                &MsgTarget::Server {
+                    serv_name: "friends",
+                },
+            );
+        }
+
         tui.draw();
 
         for server in servers.iter().cloned() {
@@ -183,6 +222,10 @@ impl<'poll> Tiny<'poll> {
             input_ev_handler: Input::new(),
             logger: Logger::new(PathBuf::from(log_dir)),
             config_path: config_path.to_owned(),
+            download_dir,
+            transfers: Transfers::new(&poll),
+            chats: Chats::new(&poll),
+            netsplits: Netsplits::new(),
         };
 
         tiny.tui.draw();
@@ -191,6 +234,8 @@ impl<'poll> Tiny<'poll> {
         let mut poll_evs = Events::with_capacity(10);
         let mut conn_evs = Vec::with_capacity(10);
         let mut input_evs = Vec::with_capacity(10);
+        let mut transfer_evs = Vec::with_capacity(10);
+        let mut chat_evs = Vec::with_capacity(10);
         'mainloop: loop {
             // FIXME this will sometimes miss the tick deadline
             match poll.poll(&mut poll_evs, Some(Duration::from_secs(1))) {
@@ -209,6 +254,15 @@ impl<'poll> Tiny<'poll> {
                             }
                         } else {
                             match find_token_conn_idx(&tiny.conns, token) {
+                                None if tiny.transfers.has_token(token) => {
+                                    let readiness = event.readiness();
+                                    tiny.transfers.ready(token, readiness, &mut transfer_evs);
+                                    tiny.handle_transfer_evs(&mut transfer_evs);
+                                }
+                                None if tiny.chats.has_token(token) => {
+                                    tiny.chats.ready(token, event.readiness(), &mut chat_evs);
+                                    tiny.handle_chat_evs(&mut chat_evs);
+                                }
                                 None => {
                                     tiny.logger.get_debug_logs().write_line(format_args!(
                                         "BUG: Can't find Token in conns: {:?}",
@@ -230,15 +284,56 @@ impl<'poll> Tiny<'poll> {
                             }
                             tiny.handle_conn_evs(&poll, conn_idx, &mut conn_evs);
                         }
+                        tiny.show_reconnect_countdowns();
+                        tiny.tick_netsplits();
                         last_tick = Instant::now();
                     }
                 }
             }
 
+            tiny.show_send_queues();
             tiny.tui.draw();
         }
     }
 
+    fn show_reconnect_countdowns(&mut self) {
+        for conn in &self.conns {
+            self.tui.show_reconnect_countdown(
+                conn.get_reconnect_secs(),
+                &MsgTarget::Server {
+                    serv_name: conn.get_serv_name(),
+                },
+            );
+        }
+    }
+
+    fn show_send_queues(&mut self) {
+        for conn in &self.conns {
+            self.tui.set_queued_lines(
+                conn.get_queued_lines(),
+                &MsgTarget::AllServTabs {
+                    serv_name: conn.get_serv_name(),
+                },
+            );
+        }
+    }
+
+    fn tick_netsplits(&mut self) {
+        for line in self.netsplits.tick() {
+            // the channel may be closed since the split
+            if self.tui.get_nicks(&line.serv_name, &line.chan).is_some() {
+                self.tui.add_msg(
+                    &line.msg,
+                    Timestamp::now(),
+                    &MsgTarget::Chan {
+                        serv_name: &line.serv_name,
+                        chan_name: &line.chan,
+                    },
+                );
+            }
+        }
+    }
+
     fn handle_stdin(&mut self, poll: &'poll Poll, evs: &mut Vec<Event>) -> bool {
         let mut abort = false;
         self.input_ev_handler.read_input_events(evs);
@@ -300,12 +395,54 @@ impl<'poll> Tiny<'poll> {
         }
     }
 
+    /// Send a message to a DCC CHAT. Unlike PRIVMSGs, the message is not split.
+    fn send_dcc_chat_msg(&mut self, serv_name: &str, nick: &str, msg: &str, ctcp_action: bool) {
+        let line = if ctcp_action {
+            ctcp::format_ctcp("ACTION", msg)
+        } else {
+            msg.to_owned()
+        };
+        if let Err(err) = self.chats.send(serv_name, nick, &line) {
+            self.tui.add_client_err_msg(
+                &format!("Can't send message: {}", err),
+                &MsgTarget::CurrentTab,
+            );
+            return;
+        }
+        // chats are closed with the server tab, so the connection exists
+        let our_nick = find_conn(&mut self.conns, serv_name).unwrap().get_nick().to_owned();
+        self.logger
+            .get_dcc_chat_logs(serv_name, nick)
+            .write_line(format_args!("{}: {}", our_nick, msg));
+        self.tui.add_privmsg(
+            &our_nick,
+            msg,
+            Timestamp::now(),
+            &MsgTarget::Dcc { serv_name, nick },
+            ctcp_action,
+        );
+    }
+
     fn part(&mut self, serv_name: &str, chan: &str) {
         let conn = find_conn(&mut self.conns, serv_name).unwrap();
         conn.part(chan);
     }
 
     fn send_msg(&mut self, from: MsgSource, msg: &str, ctcp_action: bool) {
+        if from.serv_name() == "transfers" {
+            self.tui.add_client_err_msg(
+                "Use `/dcc` to manage file transfers",
+                &MsgTarget::CurrentTab,
+            );
+            return;
+        }
+        if from.serv_name() == "friends" {
+            self.tui.add_client_err_msg(
+                "Friends are configured in the `friends` server config field",
+                &MsgTarget::CurrentTab,
+            );
+            return;
+        }
         if from.serv_name() == "mentions" {
             self.tui.add_client_err_msg(
                 "Use `/connect <server>` to connect to a server",
@@ -366,6 +503,14 @@ impl<'poll> Tiny<'poll> {
                     };
                     (msg_target, nick, serv_name)
                 }
+
+                MsgSource::Dcc {
+                    ref serv_name,
+                    ref nick,
+                } => {
+                    self.send_dcc_chat_msg(serv_name, nick, msg, ctcp_action);
+                    return;
+                }
             }
         };
 
@@ -405,7 +550,7 @@ impl<'poll> Tiny<'poll> {
                     &format!(
                         "Connection error (HUP). \
                          Will try to reconnect in {} seconds.",
-                        conn::RECONNECT_TICKS
+                        conn.get_reconnect_secs().unwrap_or(0)
                     ),
                     Timestamp::now(),
                     &MsgTarget::AllServTabs {
@@ -417,6 +562,61 @@ impl<'poll> Tiny<'poll> {
         self.handle_conn_evs(poll, conn_idx, evs);
     }
 
+    fn handle_transfer_evs(&mut self, evs: &mut Vec<TransferEv>) {
+        let target = MsgTarget::Server {
+            serv_name: "transfers",
+        };
+        for ev in evs.drain(..) {
+            match ev {
+                TransferEv::Progress(msg) =>
+                    show_transfer_msg(&mut self.tui, &msg, false),
+                TransferEv::Done(msg) => {
+                    show_transfer_msg(&mut self.tui, &msg, false);
+                    self.tui.set_tab_style(TabStyle::Highlight, &target);
+                }
+                TransferEv::Failed(msg) => {
+                    show_transfer_msg(&mut self.tui, &msg, true);
+                    self.tui.set_tab_style(TabStyle::Highlight, &target);
+                }
+            }
+        }
+    }
+
+    fn handle_chat_evs(&mut self, evs: &mut Vec<ChatEv>) {
+        for ev in evs.drain(..) {
+            let target = MsgTarget::Dcc {
+                serv_name: &ev.serv_name,
+                nick: &ev.nick,
+            };
+            let ts = Timestamp::now();
+            let mut logs = self.logger.get_dcc_chat_logs(&ev.serv_name, &ev.nick);
+            match ev.kind {
+                ChatEvKind::Connected => {
+                    logs.write_line(format_args!("DCC CHAT connected"));
+                    self.tui.new_dcc_tab(&ev.serv_name, &ev.nick);
+                    self.tui.add_msg(
+                        &format!("DCC CHAT with {} connected.", ev.nick),
+                        ts,
+                        &target,
+                    );
+                    self.tui.set_tab_style(TabStyle::Highlight, &target);
+                }
+                ChatEvKind::Msg(msg) => {
+                    let (msg, is_ctcp_action) = wire::check_ctcp_action_msg(&msg);
+                    logs.write_line(format_args!("{}: {}", ev.nick, msg));
+                    self.tui
+                        .add_privmsg(&ev.nick, msg, ts, &target, is_ctcp_action);
+                    self.tui.set_tab_style(TabStyle::Highlight, &target);
+                }
+                ChatEvKind::Closed(err) => {
+                    logs.write_line(format_args!("DCC CHAT closed: {}", err));
+                    self.tui.add_err_msg(&format!("DCC CHAT closed: {}", err), ts, &target);
+                    self.tui.set_tab_style(TabStyle::Highlight, &target);
+                }
+            }
+        }
+    }
+
     fn handle_conn_evs(&mut self, poll: &'poll Poll, conn_idx: usize, evs: &mut Vec<ConnEv>) {
         for ev in evs.drain(..) {
             self.handle_conn_ev(poll, conn_idx, ev);
@@ -426,11 +626,12 @@ impl<'poll> Tiny<'poll> {
     fn handle_conn_ev(&mut self, poll: &'poll Poll, conn_idx: usize, ev: ConnEv) {
         match ev {
             ConnEv::Connected => {
+                let conn = &self.conns[conn_idx];
                 self.tui.add_msg(
-                    "Connected.",
+                    &format!("Connected to {}.", conn.get_endpoint()),
                     Timestamp::now(),
                     &MsgTarget::AllServTabs {
-                        serv_name: self.conns[conn_idx].get_serv_name(),
+                        serv_name: conn.get_serv_name(),
                     },
                 );
                 let mut serv_auto_cmds = None;
@@ -465,7 +666,7 @@ impl<'poll> Tiny<'poll> {
                 self.tui.add_err_msg(
                     &format!(
                         "Disconnected. Will try to reconnect in {} seconds.",
-                        conn::RECONNECT_TICKS
+                        conn.get_reconnect_secs().unwrap_or(0)
                     ),
                     Timestamp::now(),
                     &target,
@@ -485,7 +686,7 @@ impl<'poll> Tiny<'poll> {
                         {}
                     Err(err) => {
                         self.tui.add_err_msg(
-                            &reconnect_err_msg(&err),
+                            &reconnect_err_msg(&err, conn),
                             Timestamp::now(),
                             &MsgTarget::AllServTabs {
                                 serv_name: conn.get_serv_name(),
@@ -498,7 +699,7 @@ impl<'poll> Tiny<'poll> {
                 let conn = &mut self.conns[conn_idx];
                 conn.enter_disconnect_state();
                 self.tui.add_err_msg(
-                    &reconnect_err_msg(&err),
+                    &reconnect_err_msg(&err, conn),
                     Timestamp::now(),
                     &MsgTarget::AllServTabs {
                         serv_name: conn.get_serv_name(),
@@ -506,20 +707,62 @@ impl<'poll> Tiny<'poll> {
                 );
             }
             ConnEv::Msg(msg) => {
-                self.handle_msg(conn_idx, msg, Timestamp::now());
+                let tm = msg.server_time().unwrap_or_else(time::now);
+                self.handle_msg(conn_idx, msg, tm);
             }
             ConnEv::NickChange(new_nick) => {
                 let conn = &self.conns[conn_idx];
                 self.tui.set_nick(conn.get_serv_name(), &new_nick);
             }
+            ConnEv::Aborted(reason) => {
+                let conn = &self.conns[conn_idx];
+                let target = MsgTarget::AllServTabs {
+                    serv_name: conn.get_serv_name(),
+                };
+                self.tui.add_err_msg(
+                    &format!("{}. Disconnected, use /connect to reconnect.", reason),
+                    Timestamp::now(),
+                    &target,
+                );
+                self.tui.clear_nicks(&target);
+            }
+            ConnEv::Whois(whois) => {
+                let conn = &self.conns[conn_idx];
+                let serv_name = conn.get_serv_name();
+                let target = if self.tui.does_user_tab_exist(serv_name, &whois.nick) {
+                    MsgTarget::User {
+                        serv_name: serv_name,
+                        nick: &whois.nick,
+                    }
+                } else {
+                    MsgTarget::Server {
+                        serv_name: serv_name,
+                    }
+                };
+                let ts = Timestamp::now();
+                for line in whois.format() {
+                    self.tui.add_msg(&line, ts, &target);
+                }
+            }
+            ConnEv::Friend { nick, online } => {
+                let serv_name = self.conns[conn_idx].get_serv_name();
+                show_friend(&mut self.tui, serv_name, &nick, online);
+            }
         }
     }
 
-    fn handle_msg(&mut self, conn_idx: usize, msg: Msg, ts: Timestamp) {
+    /// `tm`: Time of the message. Sent by the server (IRCv3 `server-time`) or the time we
+    /// received the message.
+    fn handle_msg(&mut self, conn_idx: usize, msg: Msg, tm: Tm) {
+        let ts = Timestamp::from(tm);
         let conn = &self.conns[conn_idx];
         let pfx = msg.pfx;
         match msg.cmd {
-            Cmd::PRIVMSG { target, msg, is_notice } => {
+            Cmd::PRIVMSG {
+                targets,
+                msg,
+                is_notice,
+            } => {
                 let pfx = match pfx {
                     Some(pfx) =>
                         pfx,
@@ -527,7 +770,7 @@ impl<'poll> Tiny<'poll> {
                         self.logger
                             .get_debug_logs()
                             .write_line(format_args!("PRIVMSG or NOTICE without prefix \
-                                                     target: {:?} msg: {:?}", target, msg));
+                                                     targets: {:?} msg: {:?}", targets, msg));
                         return;
                     }
                 };
@@ -540,96 +783,162 @@ impl<'poll> Tiny<'poll> {
                         nick,
                 };
 
+                // ACTIONs are shown as messages, other CTCP messages are replied by `Conn`
+                if let Some(ctcp) = ctcp::parse_ctcp(&msg, is_notice) {
+                    let dcc_args = match (ctcp.kind, &ctcp.cmd) {
+                        (CtcpKind::Request, &CtcpCmd::Other(ref cmd)) if cmd == "DCC" =>
+                            Some(ctcp.args),
+                        _ =>
+                            None,
+                    };
+                    if let Some(addr) = dcc_args.and_then(dcc::parse_dcc_chat) {
+                        let casemapping = conn.get_casemapping();
+                        if self.chats.offer(casemapping, conn.get_serv_name(), origin, addr) {
+                            show_dcc_chat_offer(&mut self.tui, conn, origin, ts);
+                        }
+                        return;
+                    }
+                    let offer = dcc_args.and_then(dcc::parse_dcc_send);
+                    if let Some(offer) = offer {
+                        let size = match offer.size {
+                            Some(size) =>
+                                format!("{} bytes", size),
+                            None =>
+                                "unknown size".to_owned(),
+                        };
+                        let filename = offer.filename.clone();
+                        let id = self.transfers.offer(conn.get_serv_name(), origin, offer);
+                        let msg = format!(
+                            "{} offers {} ({}). Use `/dcc accept {id}` or `/dcc reject {id}`.",
+                            origin,
+                            filename,
+                            size,
+                            id = id
+                        );
+                        show_transfer_msg(&mut self.tui, &msg, false);
+                        self.tui.set_tab_style(
+                            TabStyle::Highlight,
+                            &MsgTarget::Server {
+                                serv_name: "transfers",
+                            },
+                        );
+                        return;
+                    }
+                    if ctcp.cmd != CtcpCmd::Action {
+                        show_ctcp(&mut self.tui, conn, origin, &ctcp, ts);
+                        return;
+                    }
+                }
+
                 let (msg, is_ctcp_action) = wire::check_ctcp_action_msg(&msg);
 
-                match target {
-                    wire::MsgTarget::Chan(chan) => {
-                        self.logger
-                            .get_chan_logs(conn.get_serv_name(), &chan)
-                            .write_line(format_args!("PRIVMSG: {}", msg));
-                        let msg_target = MsgTarget::Chan {
-                            serv_name: conn.get_serv_name(),
-                            chan_name: &chan,
-                        };
-                        // highlight the message if it mentions us
-                        if msg.find(conn.get_nick()).is_some() {
-                            self.tui.add_privmsg_highlight(
-                                origin,
-                                msg,
-                                ts,
-                                &msg_target,
-                                is_ctcp_action,
-                            );
-                            self.tui.set_tab_style(TabStyle::Highlight, &msg_target);
-                            let mentions_target = MsgTarget::Server {
-                                serv_name: "mentions",
+                // a message can have multiple targets, show it in each of the tabs
+                for target in targets {
+                    match target {
+                        wire::MsgTarget::Chan(chan) => {
+                            self.logger
+                                .get_chan_logs(conn.get_serv_name(), &chan)
+                                .write_line_at(tm, format_args!("PRIVMSG: {}", msg));
+                            let msg_target = MsgTarget::Chan {
+                                serv_name: conn.get_serv_name(),
+                                chan_name: &chan,
                             };
-                            self.tui.add_msg(
-                                &format!(
-                                    "{} in {}:{}: {}",
+                            // highlight the message if it mentions us
+                            if conn.get_casemapping().contains(msg, conn.get_nick()) {
+                                self.tui.add_privmsg_highlight(
                                     origin,
-                                    conn.get_serv_name(),
-                                    chan,
-                                    msg
-                                ),
-                                ts,
-                                &mentions_target,
-                            );
-                            self.tui
-                                .set_tab_style(TabStyle::Highlight, &mentions_target);
-                        } else {
-                            self.tui
-                                .add_privmsg(origin, msg, ts, &msg_target, is_ctcp_action);
-                            self.tui.set_tab_style(TabStyle::NewMsg, &msg_target);
+                                    msg,
+                                    ts,
+                                    &msg_target,
+                                    is_ctcp_action,
+                                );
+                                self.tui.set_tab_style(TabStyle::Highlight, &msg_target);
+                                let mentions_target = MsgTarget::Server {
+                                    serv_name: "mentions",
+                                };
+                                self.tui.add_msg(
+                                    &format!(
+                                        "{} in {}:{}: {}",
+                                        origin,
+                                        conn.get_serv_name(),
+                                        chan,
+                                        msg
+                                    ),
+                                    ts,
+                                    &mentions_target,
+                                );
+                                self.tui
+                                    .set_tab_style(TabStyle::Highlight, &mentions_target);
+                            } else {
+                                self.tui
+                                    .add_privmsg(origin, msg, ts, &msg_target, is_ctcp_action);
+                                self.tui.set_tab_style(TabStyle::NewMsg, &msg_target);
+                            }
                         }
-                    }
-                    wire::MsgTarget::User(target) => {
-                        let serv_name = conn.get_serv_name();
-                        let msg_target = {
-                            match pfx {
-                                Pfx::Server(_) =>
-                                    MsgTarget::Server { serv_name },
-                                Pfx::User { ref nick, .. } => {
-                                    // show NOTICE messages in server tabs if we don't have a tab
-                                    // for the sender already (see #21)
-                                    if is_notice && !self.tui.does_user_tab_exist(serv_name, nick) {
-                                        MsgTarget::Server { serv_name }
-                                    } else {
-                                        MsgTarget::User { serv_name, nick }
+                        wire::MsgTarget::User(target) => {
+                            let serv_name = conn.get_serv_name();
+                            let msg_target = {
+                                match pfx {
+                                    Pfx::Server(_) =>
+                                        MsgTarget::Server { serv_name },
+                                    Pfx::User { ref nick, .. } => {
+                                        // show NOTICE messages in server tabs if we don't have a
+                                        // tab for the sender already (see #21)
+                                        if is_notice
+                                            && !self.tui.does_user_tab_exist(serv_name, nick)
+                                        {
+                                            MsgTarget::Server { serv_name }
+                                        } else {
+                                            MsgTarget::User { serv_name, nick }
+                                        }
                                     }
                                 }
+                            };
+                            self.tui
+                                .add_privmsg(origin, msg, ts, &msg_target, is_ctcp_action);
+                            if conn.is_our_nick(&target) {
+                                self.tui.set_tab_style(TabStyle::Highlight, &msg_target);
+                            } else {
+                                // not sure if this case can happen
+                                self.tui.set_tab_style(TabStyle::NewMsg, &msg_target);
                             }
-                        };
-                        self.tui
-                            .add_privmsg(origin, msg, ts, &msg_target, is_ctcp_action);
-                        if target == conn.get_nick() {
-                            self.tui.set_tab_style(TabStyle::Highlight, &msg_target);
-                        } else {
-                            // not sure if this case can happen
-                            self.tui.set_tab_style(TabStyle::NewMsg, &msg_target);
                         }
                     }
                 }
             }
 
-            Cmd::JOIN { chan } =>
+            Cmd::JOIN { chans, .. } =>
                 match pfx {
-                    Some(Pfx::User { nick, .. }) => {
+                    Some(Pfx::User { nick, user }) => {
                         let serv_name = conn.get_serv_name();
-                        self.logger
-                            .get_chan_logs(serv_name, &chan)
-                            .write_line(format_args!("JOIN: {}", nick));
-                        if nick == conn.get_nick() {
-                            self.tui.new_chan_tab(serv_name, &chan);
-                        } else {
-                            self.tui.add_nick(
-                                drop_nick_prefix(&nick),
-                                Some(Timestamp::now()),
-                                &MsgTarget::Chan {
+                        for chan in chans {
+                            self.logger
+                                .get_chan_logs(serv_name, &chan)
+                                .write_line_at(tm, format_args!("JOIN: {}", nick));
+                            if conn.is_our_nick(&nick) {
+                                self.tui.new_chan_tab(serv_name, &chan);
+                                // the tab may already exist, e.g. after a kick
+                                self.tui.set_parted(
+                                    false,
+                                    &MsgTarget::Chan {
+                                        serv_name: serv_name,
+                                        chan_name: &chan,
+                                    },
+                                );
+                            } else {
+                                let nick = conn.get_isupport().drop_nick_prefix(&nick);
+                                let chan_target = MsgTarget::Chan {
                                     serv_name: serv_name,
                                     chan_name: &chan,
-                                },
-                            );
+                                };
+                                let casemapping = conn.get_casemapping();
+                                // joins after a netsplit are shown on the next ticks
+                                let is_netjoin =
+                                    self.netsplits.join(casemapping, serv_name, nick, &chan);
+                                let ts = if is_netjoin { None } else { Some(ts) };
+                                self.tui.add_nick(nick, ts, &chan_target);
+                                self.tui.set_member_user(nick, &user, &chan_target);
+                            }
                         }
                     }
                     pfx => {
@@ -639,22 +948,24 @@ impl<'poll> Tiny<'poll> {
                     }
                 },
 
-            Cmd::PART { chan, .. } =>
+            Cmd::PART { chans, .. } =>
                 match pfx {
                     Some(Pfx::User { nick, .. }) =>
-                        if nick != conn.get_nick() {
+                        if !conn.is_our_nick(&nick) {
                             let serv_name = conn.get_serv_name();
-                            self.logger
-                                .get_chan_logs(serv_name, &chan)
-                                .write_line(format_args!("PART: {}", nick));
-                            self.tui.remove_nick(
-                                &nick,
-                                Some(Timestamp::now()),
-                                &MsgTarget::Chan {
-                                    serv_name: serv_name,
-                                    chan_name: &chan,
-                                },
-                            );
+                            for chan in chans {
+                                self.logger
+                                    .get_chan_logs(serv_name, &chan)
+                                    .write_line_at(tm, format_args!("PART: {}", nick));
+                                self.tui.remove_nick(
+                                    &nick,
+                                    Some(ts),
+                                    &MsgTarget::Chan {
+                                        serv_name: serv_name,
+                                        chan_name: &chan,
+                                    },
+                                );
+                            }
                         },
                     pfx => {
                         self.logger
@@ -663,13 +974,23 @@ impl<'poll> Tiny<'poll> {
                     }
                 },
 
-            Cmd::QUIT { .. } =>
+            Cmd::QUIT { msg } =>
                 match pfx {
                     Some(Pfx::User { ref nick, .. }) => {
                         let serv_name = conn.get_serv_name();
+                        let chans = self.tui.get_nick_chans(serv_name, nick);
+                        let reason = msg.as_ref().map(String::as_str).unwrap_or("");
+                        // netsplit quits are shown on the next ticks, in a single line
+                        let is_split = self.netsplits.quit(
+                            conn.get_casemapping(),
+                            serv_name,
+                            nick,
+                            &chans,
+                            reason,
+                        );
                         self.tui.remove_nick(
                             nick,
-                            Some(Timestamp::now()),
+                            if is_split { None } else { Some(ts) },
                             &MsgTarget::AllUserTabs {
                                 serv_name: serv_name,
                                 nick: nick,
@@ -683,6 +1004,102 @@ impl<'poll> Tiny<'poll> {
                     }
                 },
 
+            Cmd::KICK { chan, nick, msg } => {
+                let serv_name = conn.get_serv_name();
+                let kicker = match pfx {
+                    Some(Pfx::Server(ref server)) =>
+                        server.as_str(),
+                    Some(Pfx::User { ref nick, .. }) =>
+                        nick.as_str(),
+                    None =>
+                        serv_name,
+                };
+                let reason = match msg {
+                    None =>
+                        String::new(),
+                    Some(ref msg) =>
+                        format!(" ({})", msg),
+                };
+                self.logger
+                    .get_chan_logs(serv_name, &chan)
+                    .write_line_at(tm, format_args!("KICK: {} kicked {}{}", kicker, nick, reason));
+                let chan_target = MsgTarget::Chan {
+                    serv_name: serv_name,
+                    chan_name: &chan,
+                };
+
+                if conn.is_our_nick(&nick) {
+                    self.tui.add_err_msg(
+                        &format!("You were kicked by {}{}", kicker, reason),
+                        ts,
+                        &chan_target,
+                    );
+                    if let Some(delay) = conn.get_auto_rejoin_delay() {
+                        self.tui.add_client_msg(
+                            &format!("Rejoining in {} seconds", delay),
+                            &chan_target,
+                        );
+                    }
+                    self.tui.clear_nicks(&chan_target);
+                    self.tui.set_parted(true, &chan_target);
+                    self.tui.set_tab_style(TabStyle::Highlight, &chan_target);
+                } else {
+                    self.tui.remove_nick(&nick, None, &chan_target);
+                    self.tui.add_msg(
+                        &format!("{} kicked {}{}", kicker, nick, reason),
+                        ts,
+                        &chan_target,
+                    );
+                }
+            }
+
+            Cmd::INVITE { nick, chan } => {
+                let serv_name = conn.get_serv_name();
+                let inviter = match pfx {
+                    Some(Pfx::Server(ref server)) =>
+                        server.as_str(),
+                    Some(Pfx::User { ref nick, .. }) =>
+                        nick.as_str(),
+                    None =>
+                        serv_name,
+                };
+
+                if conn.is_our_nick(&nick) {
+                    // `Conn` joins the channel when the invite is trusted
+                    let msg = if conn.is_invite_trusted(inviter, &chan) {
+                        format!("{} invited you to {}, joining", inviter, chan)
+                    } else {
+                        format!("{} invited you to {}. Use `/accept` to join.", inviter, chan)
+                    };
+                    let serv_target = MsgTarget::Server {
+                        serv_name: serv_name,
+                    };
+                    self.tui.add_msg(&msg, ts, &serv_target);
+                    self.tui.set_tab_style(TabStyle::Highlight, &serv_target);
+                    self.tui.notify_invite(inviter, &chan, &serv_target);
+
+                    let mentions_target = MsgTarget::Server {
+                        serv_name: "mentions",
+                    };
+                    self.tui.add_msg(
+                        &format!("{} invited you to {}:{}", inviter, serv_name, chan),
+                        ts,
+                        &mentions_target,
+                    );
+                    self.tui.set_tab_style(TabStyle::Highlight, &mentions_target);
+                } else {
+                    // invite-notify
+                    self.tui.add_msg(
+                        &format!("{} invited {} to the channel", inviter, nick),
+                        ts,
+                        &MsgTarget::Chan {
+                            serv_name: serv_name,
+                            chan_name: &chan,
+                        },
+                    );
+                }
+            }
+
             Cmd::NICK { nick } =>
                 match pfx {
                     Some(Pfx::User {
@@ -693,7 +1110,7 @@ impl<'poll> Tiny<'poll> {
                         self.tui.rename_nick(
                             old_nick,
                             &nick,
-                            Timestamp::now(),
+                            ts,
                             &MsgTarget::AllUserTabs {
                                 serv_name: serv_name,
                                 nick: old_nick,
@@ -707,7 +1124,7 @@ impl<'poll> Tiny<'poll> {
                     }
                 },
 
-            Cmd::PING { .. } | Cmd::PONG { .. } =>
+            Cmd::PING { .. } | Cmd::PONG { .. } | Cmd::AUTHENTICATE { .. } =>
                 // ignore
                 {}
 
@@ -715,7 +1132,7 @@ impl<'poll> Tiny<'poll> {
                 let serv_name = conn.get_serv_name();
                 self.tui.add_err_msg(
                     msg,
-                    Timestamp::now(),
+                    ts,
                     &MsgTarget::AllServTabs {
                         serv_name: serv_name,
                     },
@@ -725,7 +1142,7 @@ impl<'poll> Tiny<'poll> {
             Cmd::TOPIC { ref chan, ref topic } => {
                 self.tui.show_topic(
                     topic,
-                    Timestamp::now(),
+                    ts,
                     &MsgTarget::Chan {
                         serv_name: conn.get_serv_name(),
                         chan_name: chan,
@@ -733,137 +1150,333 @@ impl<'poll> Tiny<'poll> {
                 );
             }
 
-            Cmd::Reply { num: n, params } => {
-                if n <= 003 /* RPL_WELCOME, RPL_YOURHOST, RPL_CREATED */
-                        || n == 251 /* RPL_LUSERCLIENT */
-                        || n == 255 /* RPL_LUSERME */
-                        || n == 372 /* RPL_MOTD */
-                        || n == 375 /* RPL_MOTDSTART */
-                        || n == 376
-                /* RPL_ENDOFMOTD */
-                {
-                    debug_assert_eq!(params.len(), 2);
-                    let msg = &params[1];
-                    self.tui.add_msg(
-                        msg,
-                        Timestamp::now(),
-                        &MsgTarget::Server {
-                            serv_name: conn.get_serv_name(),
-                        },
+            Cmd::MODE {
+                ref target,
+                ref modes,
+                ref params,
+            } => {
+                let serv_name = conn.get_serv_name();
+                // who changed the modes, to be shown in the UI
+                let setter = match pfx {
+                    Some(Pfx::Server(ref server)) =>
+                        server.as_str(),
+                    Some(Pfx::User { ref nick, .. }) =>
+                        nick.as_str(),
+                    None =>
+                        serv_name,
+                };
+
+                if conn.is_chan_name(target) {
+                    self.logger.get_chan_logs(serv_name, target).write_line_at(
+                        tm,
+                        format_args!("MODE: {} {} {}", setter, modes, params.join(" ")),
                     );
-                } else if n == 4 // RPL_MYINFO
-                        || n == 5 // RPL_BOUNCE
-                        || (n >= 252 && n <= 254)
-                /* RPL_LUSEROP, RPL_LUSERUNKNOWN, */
-                /* RPL_LUSERCHANNELS */
-                {
-                    let msg = params.into_iter().collect::<Vec<String>>().join(" ");
+                    let chan_target = MsgTarget::Chan {
+                        serv_name: serv_name,
+                        chan_name: target,
+                    };
+                    let isupport = conn.get_isupport();
+                    for change in mode::parse_chan_modes(isupport, modes, params) {
+                        apply_chan_mode(&mut self.tui, conn, target, &change);
+                        self.tui.add_msg(
+                            &format!("{} {}", setter, mode::describe_chan_mode(isupport, &change)),
+                            ts,
+                            &chan_target,
+                        );
+                    }
+                } else {
                     self.tui.add_msg(
-                        &msg,
-                        Timestamp::now(),
+                        &format!("{} sets mode {} on {}", setter, modes, target),
+                        ts,
                         &MsgTarget::Server {
-                            serv_name: conn.get_serv_name(),
+                            serv_name: serv_name,
                         },
                     );
-                } else if n == 265 || n == 266 || n == 250 {
-                    let msg = &params[params.len() - 1];
+                }
+            }
+
+            Cmd::CAP { subcmd, caps, .. } => {
+                // capability negotiation is handled by `Conn`, just report the changes
+                let msg = match subcmd.as_str() {
+                    "ACK" =>
+                        Some("Capabilities enabled"),
+                    "NAK" =>
+                        Some("Capabilities rejected"),
+                    "NEW" =>
+                        Some("New capabilities available"),
+                    "DEL" =>
+                        Some("Capabilities removed"),
+                    _ =>
+                        None,
+                };
+                if let Some(msg) = msg {
                     self.tui.add_msg(
-                        msg,
-                        Timestamp::now(),
+                        &format!("{}: {}", msg, caps.join(", ")),
+                        ts,
                         &MsgTarget::Server {
                             serv_name: conn.get_serv_name(),
                         },
                     );
                 }
-                // RPL_TOPIC
-                else if n == 332 {
-                    // FIXME: RFC 2812 says this will have 2 arguments, but freenode
-                    // sends 3 arguments (extra one being our nick).
-                    assert!(params.len() == 3 || params.len() == 2);
-                    let chan = &params[params.len() - 2];
-                    let topic = &params[params.len() - 1];
-                    self.tui.show_topic(
-                        topic,
-                        Timestamp::now(),
-                        &MsgTarget::Chan {
+            }
+
+            Cmd::Reply(reply) =>
+                match reply {
+                    Reply::Welcome { ref msg, .. }
+                    | Reply::YourHost { ref msg, .. }
+                    | Reply::Created { ref msg, .. }
+                    | Reply::LUserClient { ref msg, .. }
+                    | Reply::LUserMe { ref msg, .. }
+                    | Reply::Motd { ref msg, .. }
+                    | Reply::MotdStart { ref msg, .. }
+                    | Reply::EndOfMotd { ref msg, .. } => {
+                        self.tui.add_msg(
+                            msg,
+                            ts,
+                            &MsgTarget::Server {
+                                serv_name: conn.get_serv_name(),
+                            },
+                        );
+                    }
+
+                    Reply::MyInfo { .. }
+                    | Reply::ISupport { .. }
+                    | Reply::LUserOp { .. }
+                    | Reply::LUserUnknown { .. }
+                    | Reply::LUserChannels { .. } => {
+                        if let Reply::ISupport { .. } = reply {
+                            // ISUPPORT is parsed by `Conn`, show network name in the tab bar and
+                            // update casemapping of the tabs
+                            if let Some(ref network) = conn.get_isupport().network {
+                                self.tui.set_network_name(conn.get_serv_name(), network);
+                            }
+                            self.tui
+                                .set_casemapping(conn.get_serv_name(), conn.get_casemapping());
+                        }
+                        self.tui.add_msg(
+                            &reply.params().join(" "),
+                            ts,
+                            &MsgTarget::Server {
+                                serv_name: conn.get_serv_name(),
+                            },
+                        );
+                    }
+
+                    Reply::LoggedIn { ref msg, .. }
+                    | Reply::LoggedOut { ref msg, .. }
+                    | Reply::SaslSuccess { ref msg, .. } => {
+                        self.tui.add_msg(
+                            msg,
+                            ts,
+                            &MsgTarget::Server {
+                                serv_name: conn.get_serv_name(),
+                            },
+                        );
+                    }
+
+                    Reply::SaslMechs {
+                        ref mechs, ref msg, ..
+                    } => {
+                        self.tui.add_msg(
+                            &format!("{} {}", mechs, msg),
+                            ts,
+                            &MsgTarget::Server {
+                                serv_name: conn.get_serv_name(),
+                            },
+                        );
+                    }
+
+                    Reply::ErrNickLocked { ref msg, .. }
+                    | Reply::ErrSaslFail { ref msg, .. }
+                    | Reply::ErrSaslTooLong { ref msg, .. }
+                    | Reply::ErrSaslAborted { ref msg, .. }
+                    | Reply::ErrSaslAlready { ref msg, .. } => {
+                        self.tui.add_err_msg(
+                            msg,
+                            ts,
+                            &MsgTarget::Server {
+                                serv_name: conn.get_serv_name(),
+                            },
+                        );
+                    }
+
+                    // RPL_STATSCONN, RPL_LOCALUSERS, RPL_GLOBALUSERS. Not in the RFCs and the
+                    // parameters differ between servers, so just show the text.
+                    Reply::Other {
+                        num: 250,
+                        ref params,
+                    }
+                    | Reply::Other {
+                        num: 265,
+                        ref params,
+                    }
+                    | Reply::Other {
+                        num: 266,
+                        ref params,
+                    } if !params.is_empty() => {
+                        self.tui.add_msg(
+                            &params[params.len() - 1],
+                            ts,
+                            &MsgTarget::Server {
+                                serv_name: conn.get_serv_name(),
+                            },
+                        );
+                    }
+
+                    Reply::UModeIs { .. } =>
+                        // parsed by `Conn`
+                        self.tui.add_msg(
+                            &format!("Your user modes: +{}", conn.get_user_modes()),
+                            ts,
+                            &MsgTarget::Server {
+                                serv_name: conn.get_serv_name(),
+                            },
+                        ),
+
+                    Reply::ChannelModeIs {
+                        ref chan,
+                        ref modes,
+                        ref params,
+                        ..
+                    } => {
+                        let chan_target = MsgTarget::Chan {
                             serv_name: conn.get_serv_name(),
                             chan_name: chan,
-                        },
-                    );
-                }
-                // RPL_NAMREPLY: List of users in a channel
-                else if n == 353 {
-                    let chan = &params[2];
-                    let chan_target = MsgTarget::Chan {
-                        serv_name: conn.get_serv_name(),
-                        chan_name: chan,
-                    };
+                        };
+                        self.tui.clear_chan_modes(&chan_target);
+                        for change in mode::parse_chan_modes(conn.get_isupport(), modes, params) {
+                            apply_chan_mode(&mut self.tui, conn, chan, &change);
+                        }
+                        self.tui.add_msg(
+                            &format!("Channel modes: {} {}", modes, params.join(" ")).trim(),
+                            ts,
+                            &chan_target,
+                        );
+                    }
 
-                    for nick in params[3].split_whitespace() {
-                        self.tui.add_nick(drop_nick_prefix(nick), None, &chan_target);
+                    Reply::Topic {
+                        ref chan,
+                        ref topic,
+                        ..
+                    } => {
+                        self.tui.show_topic(
+                            topic,
+                            ts,
+                            &MsgTarget::Chan {
+                                serv_name: conn.get_serv_name(),
+                                chan_name: chan,
+                            },
+                        );
                     }
-                }
-                // RPL_ENDOFNAMES: End of NAMES list
-                else if n == 366 {
-                }
-                // RPL_UNAWAY or RPL_NOWAWAY
-                else if n == 305 || n == 306 {
-                    let msg = &params[1];
-                    self.tui.add_client_msg(
-                        msg,
-                        &MsgTarget::AllServTabs {
+
+                    // List of users in a channel
+                    Reply::NamReply {
+                        ref chan,
+                        ref nicks,
+                        ..
+                    } => {
+                        let chan_target = MsgTarget::Chan {
                             serv_name: conn.get_serv_name(),
-                        },
-                    );
-                }
-                // ERR_NOSUCHNICK
-                else if n == 401 {
-                    let nick = &params[1];
-                    let msg = &params[2];
-                    let serv_name = conn.get_serv_name();
-                    self.tui.add_client_msg(
-                        msg,
-                        &MsgTarget::User {
-                            serv_name: serv_name,
-                            nick: nick,
-                        },
-                    );
-                // RPL_AWAY
-                } else if n == 301 {
-                    let serv_name = conn.get_serv_name();
-                    let nick = &params[1];
-                    let msg = &params[2];
-                    self.tui.add_client_msg(
-                        &format!("{} is away: {}", nick, msg),
-                        &MsgTarget::User { serv_name, nick });
-                } else {
-                    match pfx {
-                        Some(Pfx::Server(msg_serv_name)) => {
-                            let conn_serv_name = conn.get_serv_name();
-                            let msg_target = MsgTarget::Server {
-                                serv_name: conn_serv_name,
-                            };
-                            self.tui.add_privmsg(
-                                &msg_serv_name,
-                                &params.join(" "),
-                                Timestamp::now(),
-                                &msg_target,
-                                false,
-                            );
-                            self.tui.set_tab_style(TabStyle::NewMsg, &msg_target);
-                        }
-                        pfx => {
-                            // add everything else to debug file
-                            self.logger.get_debug_logs().write_line(format_args!(
-                                "Ignoring numeric reply msg:\nPfx: {:?}, num: {:?}, args: {:?}",
-                                pfx,
-                                n,
-                                params
-                            ));
+                            chan_name: chan,
+                        };
+
+                        for nick in nicks.split_whitespace() {
+                            let (prefix, nick) = conn.get_isupport().split_nick_prefix(nick);
+                            self.tui.add_nick(nick, None, &chan_target);
+                            self.tui.set_nick_prefix(nick, prefix, &chan_target);
                         }
                     }
-                }
-            }
+
+                    Reply::EndOfNames { .. } =>
+                        {}
+
+                    Reply::WhoReply { .. } | Reply::WhoxReply { .. } =>
+                        handle_who_reply(&mut self.tui, conn, &reply, ts),
+
+                    Reply::EndOfWho { ref name, ref msg, .. } =>
+                        // replies of channels we're in are only used to update the nick list
+                        if self.tui.get_nicks(conn.get_serv_name(), name).is_none() {
+                            self.tui.add_msg(
+                                &format!("{}: {}", name, msg),
+                                ts,
+                                &MsgTarget::Server {
+                                    serv_name: conn.get_serv_name(),
+                                },
+                            );
+                        },
+
+                    // collected by `Conn`, shown on `ConnEv::Whois`
+                    Reply::WhoisUser { .. }
+                    | Reply::WhoisServer { .. }
+                    | Reply::WhoisOperator { .. }
+                    | Reply::WhoisIdle { .. }
+                    | Reply::WhoisChannels { .. }
+                    | Reply::WhoisAccount { .. }
+                    | Reply::WhoisSecure { .. }
+                    | Reply::EndOfWhois { .. } =>
+                        {}
+
+                    // handled by `Conn`, shown on `ConnEv::Friend`
+                    Reply::MonOnline { .. } | Reply::MonOffline { .. } =>
+                        {}
+
+                    Reply::UnAway { ref msg, .. } | Reply::NowAway { ref msg, .. } => {
+                        self.tui.add_client_msg(
+                            msg,
+                            &MsgTarget::AllServTabs {
+                                serv_name: conn.get_serv_name(),
+                            },
+                        );
+                    }
+
+                    Reply::ErrNoSuchNick {
+                        ref nick, ref msg, ..
+                    } => {
+                        let serv_name = conn.get_serv_name();
+                        self.tui.add_client_msg(
+                            msg,
+                            &MsgTarget::User {
+                                serv_name: serv_name,
+                                nick: nick,
+                            },
+                        );
+                    }
+
+                    Reply::Away {
+                        ref nick, ref msg, ..
+                    } => {
+                        let serv_name = conn.get_serv_name();
+                        self.tui.add_client_msg(
+                            &format!("{} is away: {}", nick, msg),
+                            &MsgTarget::User { serv_name, nick });
+                    }
+
+                    reply =>
+                        match pfx {
+                            Some(Pfx::Server(msg_serv_name)) => {
+                                let conn_serv_name = conn.get_serv_name();
+                                let msg_target = MsgTarget::Server {
+                                    serv_name: conn_serv_name,
+                                };
+                                self.tui.add_privmsg(
+                                    &msg_serv_name,
+                                    &reply.params().join(" "),
+                                    ts,
+                                    &msg_target,
+                                    false,
+                                );
+                                self.tui.set_tab_style(TabStyle::NewMsg, &msg_target);
+                            }
+                            pfx => {
+                                // add everything else to debug file
+                                self.logger.get_debug_logs().write_line(format_args!(
+                                    "Ignoring numeric reply msg:\nPfx: {:?}, reply: {:?}",
+                                    pfx,
+                                    reply
+                                ));
+                            }
+                        },
+                },
 
             Cmd::Other { cmd, params } => {
                 match pfx {
@@ -875,7 +1488,7 @@ impl<'poll> Tiny<'poll> {
                         self.tui.add_privmsg(
                             &msg_serv_name,
                             &params.join(" "),
-                            Timestamp::now(),
+                            ts,
                             &msg_target,
                             false,
                         );
@@ -895,6 +1508,144 @@ impl<'poll> Tiny<'poll> {
     }
 }
 
+/// Show a CTCP request or reply in the sender's tab, or in the server tab if we don't have a tab
+/// for the sender.
+fn show_ctcp(tui: &mut TUI, conn: &Conn, sender: &str, ctcp: &Ctcp, ts: Timestamp) {
+    let serv_name = conn.get_serv_name();
+    let target = if tui.does_user_tab_exist(serv_name, sender) {
+        MsgTarget::User {
+            serv_name: serv_name,
+            nick: sender,
+        }
+    } else {
+        MsgTarget::Server {
+            serv_name: serv_name,
+        }
+    };
+    let msg = match ctcp.kind {
+        CtcpKind::Request =>
+            if ctcp.args.is_empty() {
+                format!("{} sent CTCP {}", sender, ctcp.cmd.name())
+            } else {
+                format!("{} sent CTCP {}: {}", sender, ctcp.cmd.name(), ctcp.args)
+            },
+        CtcpKind::Reply =>
+            match (&ctcp.cmd, ctcp::ping_rtt(ctcp.args)) {
+                (&CtcpCmd::Ping, Some(rtt)) =>
+                    format!("CTCP PING reply from {}: {} ms", sender, rtt),
+                _ =>
+                    format!("CTCP {} reply from {}: {}", ctcp.cmd.name(), sender, ctcp.args),
+            },
+    };
+    tui.add_msg(&msg, ts, &target);
+}
+
+/// Update membership prefixes or channel modes of a channel tab after a mode change. List modes
+/// (e.g. bans) are not tracked.
+fn apply_chan_mode(tui: &mut TUI, conn: &Conn, chan: &str, change: &ModeChange) {
+    let isupport = conn.get_isupport();
+    let serv_name = conn.get_serv_name();
+    let target = MsgTarget::Chan {
+        serv_name: serv_name,
+        chan_name: chan,
+    };
+    if let Some(prefix) = isupport.mode_prefix(change.mode) {
+        if let Some(ref nick) = change.arg {
+            let old_prefix = tui.get_nick_prefix(serv_name, chan, nick)
+                .unwrap_or("")
+                .to_owned();
+            let new_prefix = mode::update_nick_prefix(isupport, &old_prefix, prefix, change.set);
+            tui.set_nick_prefix(nick, &new_prefix, &target);
+        }
+    } else if !isupport.chanmodes.a.contains(change.mode) {
+        tui.apply_chan_mode(
+            change.mode,
+            change.arg.as_ref().map(String::as_str),
+            change.set,
+            &target,
+        );
+    }
+}
+
+/// Update the channel member with a WHO reply. Replies for channels we're not in (e.g. replies of
+/// WHO queries sent with `/quote`) are shown in the server tab.
+fn handle_who_reply(tui: &mut TUI, conn: &Conn, reply: &Reply, ts: Timestamp) {
+    let serv_name = conn.get_serv_name();
+    match who::parse_who_reply(conn.get_isupport(), reply) {
+        Some(ref entry) if tui.get_nicks(serv_name, &entry.chan).is_some() => {
+            let chan_target = MsgTarget::Chan {
+                serv_name: serv_name,
+                chan_name: &entry.chan,
+            };
+            tui.set_member(&entry.nick, &entry.member, &chan_target);
+        }
+        _ =>
+            tui.add_msg(
+                &reply.params()[1..].join(" "),
+                ts,
+                &MsgTarget::Server {
+                    serv_name: serv_name,
+                },
+            ),
+    }
+}
+
+/// Show an incoming DCC CHAT offer in the sender's tab, or in the server tab if we don't have a
+/// tab for the sender.
+fn show_dcc_chat_offer(tui: &mut TUI, conn: &Conn, sender: &str, ts: Timestamp) {
+    let serv_name = conn.get_serv_name();
+    let target = if tui.does_user_tab_exist(serv_name, sender) {
+        MsgTarget::User {
+            serv_name: serv_name,
+            nick: sender,
+        }
+    } else {
+        MsgTarget::Server {
+            serv_name: serv_name,
+        }
+    };
+    tui.add_msg(
+        &format!(
+            "{} offers a DCC CHAT. Use `/dcc chat {}` to accept.",
+            sender,
+            sender
+        ),
+        ts,
+        &target,
+    );
+    tui.set_tab_style(TabStyle::Highlight, &target);
+}
+
+/// Show a friend coming online or going offline in the "friends" tab. The tab is created on the
+/// first message if it doesn't exist, e.g. after `/close`.
+fn show_friend(tui: &mut TUI, serv_name: &str, nick: &str, online: bool) {
+    tui.new_server_tab("friends");
+    let target = MsgTarget::Server {
+        serv_name: "friends",
+    };
+    let status = if online { "online" } else { "offline" };
+    tui.add_msg(
+        &format!("{} is {} ({})", nick, status, serv_name),
+        Timestamp::now(),
+        &target,
+    );
+    tui.set_tab_style(TabStyle::NewMsg, &target);
+    tui.notify_friend(nick, online, &target);
+}
+
+/// Show a message in the "transfers" tab. The tab is created on the first message.
+fn show_transfer_msg(tui: &mut TUI, msg: &str, is_err: bool) {
+    tui.new_server_tab("transfers");
+    let target = MsgTarget::Server {
+        serv_name: "transfers",
+    };
+    if is_err {
+        tui.add_err_msg(msg, Timestamp::now(), &target);
+    } else {
+        tui.add_msg(msg, Timestamp::now(), &target);
+    }
+}
+
 fn find_token_conn_idx(conns: &[Conn], token: Token) -> Option<usize> {
     for (conn_idx, conn) in conns.iter().enumerate() {
         if conn.get_conn_tok() == Some(token) {
@@ -934,7 +1685,8 @@ fn connect_err_msg(err: &ConnErr) -> String {
     }
 }
 
-fn reconnect_err_msg(err: &ConnErr) -> String {
+fn reconnect_err_msg(err: &ConnErr, conn: &Conn) -> String {
+    let secs = conn.get_reconnect_secs().unwrap_or(0);
     match err.cause() {
         Some(other_err) =>
             format!(
@@ -942,30 +1694,14 @@ fn reconnect_err_msg(err: &ConnErr) -> String {
                  Will try to reconnect in {} seconds.",
                 err.description(),
                 other_err.description(),
-                conn::RECONNECT_TICKS
+                secs
             ),
         None =>
             format!(
                 "Connection error: {}. \
                  Will try to reconnect in {} seconds.",
                 err.description(),
-                conn::RECONNECT_TICKS
+                secs
             ),
     }
 }
-
-/// Nicks may have prefixes, indicating it is a operator, founder, or
-/// something else.
-/// Channel Membership Prefixes:
-/// http://modern.ircdocs.horse/#channel-membership-prefixes
-///
-/// Returns the nick without prefix
-fn drop_nick_prefix(nick: &str) -> &str {
-    static PREFIXES: [char; 5] = ['~', '&', '@', '%', '+'];
-
-    if PREFIXES.contains(&nick.chars().nth(0).unwrap()) {
-        &nick[1..]
-    } else {
-        nick
-    }
-}
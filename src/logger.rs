@@ -4,6 +4,7 @@ use std::fs;
 use std::io::Write;
 use std::fmt;
 use std::path::PathBuf;
+use time::Tm;
 use time;
 
 // Using Vec in `fds` for custom lookup functions that don't allocate. This is probably also
@@ -22,8 +23,12 @@ pub struct LogFile<'a> {
 
 impl<'a> LogFile<'a> {
     pub fn write_line(&mut self, args: fmt::Arguments) {
-        let now = time::now();
-        write!(self.fd, "[{}] ", now.rfc822()).unwrap();
+        self.write_line_at(time::now(), args);
+    }
+
+    /// Like `write_line`, but stamps the line with the given time instead of the current time.
+    pub fn write_line_at(&mut self, tm: Tm, args: fmt::Arguments) {
+        write!(self.fd, "[{}] ", tm.rfc822()).unwrap();
         self.fd.write_fmt(args).unwrap();
         writeln!(self.fd, "").unwrap();
     }
@@ -38,6 +43,10 @@ enum LogDest {
     },
     /// For logging raw messages
     ServerRaw(String),
+    DccChat {
+        serv: String,
+        nick: String,
+    },
 }
 
 fn init_log_file(file: &mut Write) {
@@ -166,6 +175,44 @@ impl Logger {
         }
     }
 
+    pub fn get_dcc_chat_logs(&mut self, serv_: &str, nick_: &str) -> LogFile {
+        let pos = self.fds.iter().position(|&(ref dest, _)| {
+            if let LogDest::DccChat { ref serv, ref nick } = *dest {
+                serv == serv_ && nick == nick_
+            } else {
+                false
+            }
+        });
+
+        match pos {
+            Some(idx) =>
+                LogFile {
+                    fd: &mut self.fds[idx].1,
+                },
+            None => {
+                let mut log_path = self.log_dir.clone();
+                log_path.push(format!("{}_dcc_{}.log", serv_, nick_));
+                let mut file = OpenOptions::new()
+                    .append(true)
+                    .create(true)
+                    .open(log_path)
+                    .unwrap();
+                init_log_file(&mut file);
+                let idx = self.fds.len();
+                self.fds.push((
+                    LogDest::DccChat {
+                        serv: serv_.to_owned(),
+                        nick: nick_.to_owned(),
+                    },
+                    file,
+                ));
+                LogFile {
+                    fd: &mut self.fds[idx].1,
+                }
+            }
+        }
+    }
+
     pub fn get_debug_logs(&mut self) -> LogFile {
         LogFile {
             fd: &mut self.debug_fd,
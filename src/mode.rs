@@ -0,0 +1,273 @@
+//! MODE message parsing. Which channel modes take parameters is decided by `CHANMODES` and
+//! `PREFIX` tokens of RPL_ISUPPORT.
+
+use isupport::ISupport;
+
+/// A single mode change, e.g. `+o osa1`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModeChange {
+    /// `true` for `+`, `false` for `-`.
+    pub set: bool,
+    pub mode: char,
+    pub arg: Option<String>,
+}
+
+/// Parse mode string and parameters of a channel MODE message or RPL_CHANNELMODEIS, e.g. `+ov-k`
+/// and `["osa1", "tiny", "hunter2"]`. A mode that should have a parameter but doesn't gets
+/// `None`, extra parameters are ignored.
+pub fn parse_chan_modes(isupport: &ISupport, modes: &str, params: &[String]) -> Vec<ModeChange> {
+    let mut ret = vec![];
+    let mut params = params.iter();
+    let mut set = true;
+    for c in modes.chars() {
+        match c {
+            '+' =>
+                set = true,
+            '-' =>
+                set = false,
+            mode => {
+                let arg = if takes_param(isupport, mode, set) {
+                    params.next().cloned()
+                } else {
+                    None
+                };
+                ret.push(ModeChange { set, mode, arg });
+            }
+        }
+    }
+    ret
+}
+
+/// Parse mode string of a user MODE message. User modes with parameters (e.g. server notice
+/// masks) are rare, so parameters are ignored.
+pub fn parse_user_modes(modes: &str) -> Vec<ModeChange> {
+    let mut ret = vec![];
+    let mut set = true;
+    for c in modes.chars() {
+        match c {
+            '+' =>
+                set = true,
+            '-' =>
+                set = false,
+            mode =>
+                ret.push(ModeChange {
+                    set,
+                    mode,
+                    arg: None,
+                }),
+        }
+    }
+    ret
+}
+
+fn takes_param(isupport: &ISupport, mode: char, set: bool) -> bool {
+    isupport.mode_prefix(mode).is_some() || isupport.chanmodes.a.contains(mode)
+        || isupport.chanmodes.b.contains(mode) || (set && isupport.chanmodes.c.contains(mode))
+}
+
+/// Apply changes to a set of modes without parameters (e.g. our user modes). Modes are kept in
+/// the order they were set.
+pub fn apply_modes(modes: &mut String, changes: &[ModeChange]) {
+    for change in changes {
+        if change.set {
+            if !modes.contains(change.mode) {
+                modes.push(change.mode);
+            }
+        } else {
+            modes.retain(|c| c != change.mode);
+        }
+    }
+}
+
+/// Add or remove a membership prefix (e.g. `@`) to/from prefixes of a nick. Prefixes are kept in
+/// the order of `PREFIX`, highest rank first.
+pub fn update_nick_prefix(isupport: &ISupport, prefixes: &str, prefix: char, set: bool) -> String {
+    isupport
+        .prefix
+        .iter()
+        .map(|&(_, p)| p)
+        .filter(|p| if *p == prefix { set } else { prefixes.contains(*p) })
+        .collect()
+}
+
+/// Describe a channel mode change for the UI, e.g. "gives op to osa1" or "sets ban on *!*@host".
+pub fn describe_chan_mode(isupport: &ISupport, change: &ModeChange) -> String {
+    let sign = if change.set { '+' } else { '-' };
+    let arg = change.arg.as_ref().map(String::as_str).unwrap_or("");
+
+    if isupport.mode_prefix(change.mode).is_some() {
+        let name = match change.mode {
+            'q' =>
+                "owner",
+            'a' =>
+                "admin",
+            'o' =>
+                "op",
+            'h' =>
+                "halfop",
+            'v' =>
+                "voice",
+            _ =>
+                return if change.set {
+                    format!("gives mode +{} to {}", change.mode, arg)
+                } else {
+                    format!("removes mode +{} from {}", change.mode, arg)
+                },
+        };
+        return if change.set {
+            format!("gives {} to {}", name, arg)
+        } else {
+            format!("removes {} from {}", name, arg)
+        };
+    }
+
+    if isupport.chanmodes.a.contains(change.mode) {
+        let name = match change.mode {
+            'b' =>
+                "ban",
+            'e' =>
+                "ban exception",
+            'I' =>
+                "invite exception",
+            'q' =>
+                "quiet",
+            _ =>
+                return format!("sets mode {}{} {}", sign, change.mode, arg),
+        };
+        return if change.set {
+            format!("sets {} on {}", name, arg)
+        } else {
+            format!("removes {} on {}", name, arg)
+        };
+    }
+
+    match (change.mode, change.set) {
+        ('k', true) =>
+            format!("sets channel key to {}", arg),
+        ('k', false) =>
+            "removes channel key".to_owned(),
+        ('l', true) =>
+            format!("sets user limit to {}", arg),
+        ('l', false) =>
+            "removes user limit".to_owned(),
+        (mode, _) =>
+            match change.arg {
+                None =>
+                    format!("sets mode {}{}", sign, mode),
+                Some(ref arg) =>
+                    format!("sets mode {}{} {}", sign, mode, arg),
+            },
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(params: &[&str]) -> Vec<String> {
+        params.iter().map(|s| (*s).to_owned()).collect()
+    }
+
+    fn change(set: bool, mode: char, arg: Option<&str>) -> ModeChange {
+        ModeChange {
+            set,
+            mode,
+            arg: arg.map(str::to_owned),
+        }
+    }
+
+    #[test]
+    fn test_parse_chan_modes() {
+        let isupport = ISupport::default();
+        assert_eq!(
+            parse_chan_modes(&isupport, "+ov-k+lm", &params(&["osa1", "tiny", "key", "10"])),
+            vec![
+                change(true, 'o', Some("osa1")),
+                change(true, 'v', Some("tiny")),
+                change(false, 'k', Some("key")),
+                change(true, 'l', Some("10")),
+                change(true, 'm', None),
+            ]
+        );
+        // -l doesn't take a parameter
+        assert_eq!(
+            parse_chan_modes(&isupport, "-lb", &params(&["*!*@host"])),
+            vec![change(false, 'l', None), change(false, 'b', Some("*!*@host"))]
+        );
+        // missing parameters
+        assert_eq!(
+            parse_chan_modes(&isupport, "+bn", &[]),
+            vec![change(true, 'b', None), change(true, 'n', None)]
+        );
+
+        let mut isupport = ISupport::default();
+        isupport.parse_tokens(&params(&["PREFIX=(qaohv)~&@%+", "CHANMODES=eIbq,k,flj,CFLMPQS"]));
+        assert_eq!(
+            parse_chan_modes(&isupport, "+hqf", &params(&["osa1", "*!*@host", "#overflow"])),
+            vec![
+                change(true, 'h', Some("osa1")),
+                change(true, 'q', Some("*!*@host")),
+                change(true, 'f', Some("#overflow")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_user_modes() {
+        assert_eq!(
+            parse_user_modes("+Zi-w"),
+            vec![change(true, 'Z', None), change(true, 'i', None), change(false, 'w', None)]
+        );
+    }
+
+    #[test]
+    fn test_apply_modes() {
+        let mut modes = String::new();
+        apply_modes(&mut modes, &parse_user_modes("+iw"));
+        assert_eq!(modes, "iw");
+        apply_modes(&mut modes, &parse_user_modes("-i+Zw"));
+        assert_eq!(modes, "wZ");
+    }
+
+    #[test]
+    fn test_update_nick_prefix() {
+        let mut isupport = ISupport::default();
+        isupport.parse_tokens(&params(&["PREFIX=(qaohv)~&@%+"]));
+        assert_eq!(update_nick_prefix(&isupport, "", '+', true), "+");
+        assert_eq!(update_nick_prefix(&isupport, "+", '@', true), "@+");
+        assert_eq!(update_nick_prefix(&isupport, "@+", '@', false), "+");
+        assert_eq!(update_nick_prefix(&isupport, "@", '@', true), "@");
+        assert_eq!(update_nick_prefix(&isupport, "%", '~', true), "~%");
+    }
+
+    #[test]
+    fn test_describe_chan_mode() {
+        let isupport = ISupport::default();
+        assert_eq!(
+            describe_chan_mode(&isupport, &change(true, 'o', Some("osa1"))),
+            "gives op to osa1"
+        );
+        assert_eq!(
+            describe_chan_mode(&isupport, &change(false, 'v', Some("osa1"))),
+            "removes voice from osa1"
+        );
+        assert_eq!(
+            describe_chan_mode(&isupport, &change(true, 'b', Some("*!*@host"))),
+            "sets ban on *!*@host"
+        );
+        assert_eq!(
+            describe_chan_mode(&isupport, &change(true, 'k', Some("hunter2"))),
+            "sets channel key to hunter2"
+        );
+        assert_eq!(
+            describe_chan_mode(&isupport, &change(false, 'l', None)),
+            "removes user limit"
+        );
+        assert_eq!(
+            describe_chan_mode(&isupport, &change(true, 'm', None)),
+            "sets mode +m"
+        );
+    }
+}
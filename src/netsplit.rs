@@ -0,0 +1,292 @@
+//! Netsplit and netjoin detection. QUITs caused by a split are collected and shown as a single
+//! line per channel when no more quits come for a while, same for the JOINs after the split heals.
+
+use isupport::CaseMapping;
+
+/// How many ticks to wait for more quits (or joins) before showing a split (or join).
+const SHOW_TICKS: u8 = 3;
+
+/// How long to remember nicks and channels of a split to recognize their joins as a netjoin.
+const FORGET_TICKS: u16 = 15 * 60;
+
+/// How many nicks to show in netsplit and netjoin lines.
+const MAX_NICKS_SHOWN: usize = 5;
+
+/// Is the QUIT reason caused by a netsplit? Split reasons are two server names separated by a
+/// space, e.g. `hub.a.net leaf.b.net`. Servers hiding their names send `*.net *.split`.
+pub fn parse_split_reason(reason: &str) -> Option<(&str, &str)> {
+    let mut words = reason.split(' ');
+    let (server1, server2) = match (words.next(), words.next(), words.next()) {
+        (Some(server1), Some(server2), None) =>
+            (server1, server2),
+        _ =>
+            return None,
+    };
+    if server1 != server2 && is_server_name(server1) && is_server_name(server2) {
+        Some((server1, server2))
+    } else {
+        None
+    }
+}
+
+fn is_server_name(name: &str) -> bool {
+    name.contains('.') && !name.starts_with('.') && !name.ends_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '*')
+}
+
+/// A line to show in a channel tab.
+#[derive(Debug, PartialEq, Eq)]
+pub struct NetsplitLine {
+    pub serv_name: String,
+    pub chan: String,
+    pub msg: String,
+}
+
+struct Split {
+    serv_name: String,
+    /// `server1 <-> server2`
+    servers: String,
+    /// Nicks that quit in the split, and the channels they quit from, to recognize their joins.
+    /// Removed when the nick rejoins the channel.
+    quit_chans: Vec<(String, String)>,
+    /// Quits not shown yet, per channel.
+    quits: Vec<(String, Vec<String>)>,
+    /// Joins not shown yet, per channel.
+    joins: Vec<(String, Vec<String>)>,
+    /// Ticks since the last quit or join.
+    idle_ticks: u16,
+}
+
+pub struct Netsplits {
+    splits: Vec<Split>,
+}
+
+impl Netsplits {
+    pub fn new() -> Netsplits {
+        Netsplits { splits: vec![] }
+    }
+
+    /// Add a QUIT of a nick in the given channels. Returns `false` if the reason is not a
+    /// netsplit, in which case the quit should be shown as usual.
+    pub fn quit(
+        &mut self,
+        casemapping: CaseMapping,
+        serv_name: &str,
+        nick: &str,
+        chans: &[String],
+        reason: &str,
+    ) -> bool {
+        let servers = match parse_split_reason(reason) {
+            None =>
+                return false,
+            Some((server1, server2)) =>
+                format!("{} <-> {}", server1, server2),
+        };
+        let split = match self.splits
+            .iter()
+            .position(|split| split.serv_name == serv_name && split.servers == servers)
+        {
+            Some(idx) =>
+                &mut self.splits[idx],
+            None => {
+                self.splits.push(Split {
+                    serv_name: serv_name.to_owned(),
+                    servers,
+                    quit_chans: vec![],
+                    quits: vec![],
+                    joins: vec![],
+                    idle_ticks: 0,
+                });
+                self.splits.last_mut().unwrap()
+            }
+        };
+        split.idle_ticks = 0;
+        for chan in chans {
+            split.quit_chans.push((nick.to_owned(), chan.to_owned()));
+            add_nick(casemapping, &mut split.quits, chan, nick);
+        }
+        true
+    }
+
+    /// Add a JOIN. Returns `true` if the nick quit the channel in a netsplit, in which case the
+    /// join is shown as a part of a netjoin line.
+    pub fn join(
+        &mut self,
+        casemapping: CaseMapping,
+        serv_name: &str,
+        nick: &str,
+        chan: &str,
+    ) -> bool {
+        for split in &mut self.splits {
+            if split.serv_name != serv_name {
+                continue;
+            }
+            let idx = split.quit_chans.iter().position(|&(ref nick_, ref chan_)| {
+                casemapping.eq(nick, nick_) && casemapping.eq(chan, chan_)
+            });
+            if let Some(idx) = idx {
+                split.quit_chans.remove(idx);
+                split.idle_ticks = 0;
+                add_nick(casemapping, &mut split.joins, chan, nick);
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Forget splits of a server, e.g. when we disconnect.
+    pub fn clear_serv(&mut self, serv_name: &str) {
+        self.splits.retain(|split| split.serv_name != serv_name);
+    }
+
+    /// Returns lines of splits and joins that didn't get new quits or joins for a while.
+    pub fn tick(&mut self) -> Vec<NetsplitLine> {
+        let mut lines = vec![];
+        for split in &mut self.splits {
+            split.idle_ticks += 1;
+            if split.idle_ticks >= u16::from(SHOW_TICKS) {
+                for (chan, nicks) in split.quits.drain(..) {
+                    lines.push(NetsplitLine {
+                        serv_name: split.serv_name.clone(),
+                        msg: format_line("Netsplit", &split.servers, &nicks, "quits"),
+                        chan,
+                    });
+                }
+                for (chan, nicks) in split.joins.drain(..) {
+                    lines.push(NetsplitLine {
+                        serv_name: split.serv_name.clone(),
+                        msg: format_line("Netjoin", &split.servers, &nicks, "joins"),
+                        chan,
+                    });
+                }
+            }
+        }
+        // forget splits when all nicks rejoined and everything is shown
+        self.splits.retain(|split| {
+            split.idle_ticks < FORGET_TICKS
+                && (!split.quit_chans.is_empty() || !split.quits.is_empty()
+                    || !split.joins.is_empty())
+        });
+        lines
+    }
+}
+
+fn add_nick(
+    casemapping: CaseMapping,
+    chans: &mut Vec<(String, Vec<String>)>,
+    chan: &str,
+    nick: &str,
+) {
+    match chans.iter().position(|&(ref chan_, _)| casemapping.eq(chan_, chan)) {
+        Some(idx) =>
+            chans[idx].1.push(nick.to_owned()),
+        None =>
+            chans.push((chan.to_owned(), vec![nick.to_owned()])),
+    }
+}
+
+/// E.g. `Netsplit a.net <-> b.net: 143 quits (alice, bob, ...)`
+fn format_line(what: &str, servers: &str, nicks: &[String], event: &str) -> String {
+    let mut shown = nicks
+        .iter()
+        .take(MAX_NICKS_SHOWN)
+        .map(String::as_str)
+        .collect::<Vec<&str>>()
+        .join(", ");
+    if nicks.len() > MAX_NICKS_SHOWN {
+        shown.push_str(", ...");
+    }
+    format!("{} {}: {} {} ({})", what, servers, nicks.len(), event, shown)
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_split_reason() {
+        assert_eq!(
+            parse_split_reason("hub.a.net leaf.b.net"),
+            Some(("hub.a.net", "leaf.b.net"))
+        );
+        assert_eq!(parse_split_reason("*.net *.split"), Some(("*.net", "*.split")));
+        assert_eq!(parse_split_reason("Quit: bye"), None);
+        assert_eq!(parse_split_reason("see you.later all.of you"), None);
+        assert_eq!(parse_split_reason("a.net a.net"), None);
+        assert_eq!(parse_split_reason("Ping timeout"), None);
+        assert_eq!(parse_split_reason("http://a.net b.net"), None);
+    }
+
+    #[test]
+    fn test_netsplit() {
+        let cm = CaseMapping::Rfc1459;
+        let mut splits = Netsplits::new();
+        let chans = vec!["#tiny".to_owned(), "#rust".to_owned()];
+        for i in 0..7 {
+            let nick = format!("nick{}", i);
+            assert!(splits.quit(cm, "serv", &nick, &chans[..1 + i % 2], "a.net b.net"));
+        }
+        assert!(!splits.quit(cm, "serv", "osa1", &chans, "Quit: bye"));
+        assert_eq!(splits.tick(), vec![]);
+        assert_eq!(splits.tick(), vec![]);
+        assert_eq!(
+            splits.tick(),
+            vec![
+                NetsplitLine {
+                    serv_name: "serv".to_owned(),
+                    chan: "#tiny".to_owned(),
+                    msg: "Netsplit a.net <-> b.net: 7 quits \
+                          (nick0, nick1, nick2, nick3, nick4, ...)"
+                        .to_owned(),
+                },
+                NetsplitLine {
+                    serv_name: "serv".to_owned(),
+                    chan: "#rust".to_owned(),
+                    msg: "Netsplit a.net <-> b.net: 3 quits (nick1, nick3, nick5)".to_owned(),
+                },
+            ]
+        );
+        assert_eq!(splits.tick(), vec![]);
+
+        assert!(splits.join(cm, "serv", "NICK1", "#rust"));
+        // channel name of the JOIN may differ in case
+        assert!(splits.join(cm, "serv", "nick3", "#RUST"));
+        assert!(!splits.join(cm, "serv", "osa1", "#rust"));
+        assert!(!splits.join(cm, "other_serv", "nick1", "#rust"));
+        // nick0 didn't quit #rust in the split
+        assert!(!splits.join(cm, "serv", "nick0", "#rust"));
+        for _ in 0..SHOW_TICKS - 1 {
+            assert_eq!(splits.tick(), vec![]);
+        }
+        assert_eq!(
+            splits.tick(),
+            vec![NetsplitLine {
+                serv_name: "serv".to_owned(),
+                chan: "#rust".to_owned(),
+                msg: "Netjoin a.net <-> b.net: 2 joins (NICK1, nick3)".to_owned(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_rejoin_after_netjoin() {
+        let cm = CaseMapping::Rfc1459;
+        let mut splits = Netsplits::new();
+        let chans = vec!["#tiny".to_owned(), "#rust".to_owned()];
+        assert!(splits.quit(cm, "serv", "osa1", &chans, "a.net b.net"));
+        assert!(splits.join(cm, "serv", "osa1", "#tiny"));
+        // `/part` and `/join` after the netjoin is a normal join
+        assert!(!splits.join(cm, "serv", "osa1", "#tiny"));
+        assert!(splits.join(cm, "serv", "osa1", "#rust"));
+        assert!(!splits.join(cm, "serv", "osa1", "#rust"));
+        for _ in 0..SHOW_TICKS {
+            splits.tick();
+        }
+        // nothing left to wait for
+        assert!(splits.splits.is_empty());
+    }
+}
@@ -35,7 +35,8 @@ impl Notifier {
                     notify(&format!("{} in {}", sender, chan_name), &format!("{}", msg))
                 }
             }
-            MsgTarget::User { nick: ref nick_sender, .. } => {
+            MsgTarget::User { nick: ref nick_sender, .. }
+            | MsgTarget::Dcc { nick: ref nick_sender, .. } => {
                 if *self != Notifier::Off {
                     notify(
                         &format!("{} sent a private message", nick_sender),
@@ -46,4 +47,17 @@ impl Notifier {
             _ => {}
         }
     }
+
+    pub fn notify_invite(&self, sender: &str, chan: &str) {
+        if *self != Notifier::Off {
+            notify(&format!("{} invited you", sender), chan)
+        }
+    }
+
+    pub fn notify_friend(&self, nick: &str, online: bool) {
+        if *self != Notifier::Off {
+            let status = if online { "online" } else { "offline" };
+            notify(&format!("{} is {}", nick, status), "")
+        }
+    }
 }
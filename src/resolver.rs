@@ -0,0 +1,95 @@
+//! DNS resolution in a worker thread. `getaddrinfo` blocks, so resolving in the event loop would
+//! freeze the UI until the resolver replies or times out.
+
+use mio::{Poll, PollOpt, Ready, Registration, Token};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+use stream::{self, StreamErr};
+
+/// Other tokens are file descriptors, so resolver tokens are allocated from the top of the token
+/// space. `usize::MAX` is reserved by mio.
+static NEXT_TOKEN: AtomicUsize = AtomicUsize::new(::std::usize::MAX - 1);
+
+pub struct Resolver {
+    /// Readable when the result is ready. Deregistered when dropped.
+    _registration: Registration,
+    token: Token,
+    result: Receiver<Result<Vec<SocketAddr>, StreamErr>>,
+}
+
+impl Resolver {
+    /// Start resolving in a new thread. The poll gets a readable event with the resolver's token
+    /// when the result is ready.
+    pub fn new(poll: &Poll, serv_addr: &str, serv_port: u16) -> Resolver {
+        let token = Token(NEXT_TOKEN.fetch_sub(1, Ordering::SeqCst));
+        let (registration, set_readiness) = Registration::new2();
+        poll.register(&registration, token, Ready::readable(), PollOpt::edge())
+            .unwrap();
+        let (sender, result) = mpsc::channel();
+        let serv_addr = serv_addr.to_owned();
+        thread::spawn(move || {
+            let addrs = stream::resolve_addr(&serv_addr, serv_port).map_err(StreamErr::from);
+            // fails if the resolver was dropped, e.g. the server tab was closed
+            if sender.send(addrs).is_ok() {
+                let _ = set_readiness.set_readiness(Ready::readable());
+            }
+        });
+        Resolver {
+            _registration: registration,
+            token,
+            result,
+        }
+    }
+
+    pub fn get_tok(&self) -> Token {
+        self.token
+    }
+
+    /// Resolved addresses, in the order they should be tried. `None` if not ready yet.
+    pub fn get_result(&self) -> Option<Result<Vec<SocketAddr>, StreamErr>> {
+        match self.result.try_recv() {
+            Ok(result) =>
+                Some(result),
+            Err(TryRecvError::Empty) =>
+                None,
+            Err(TryRecvError::Disconnected) =>
+                Some(Err(StreamErr::CantResolveAddr)),
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mio::Events;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_resolver() {
+        let poll = Poll::new().unwrap();
+        let resolver = Resolver::new(&poll, "localhost", 6667);
+        let mut events = Events::with_capacity(10);
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let mut ready = false;
+        while !ready && Instant::now() < deadline {
+            poll.poll(&mut events, Some(Duration::from_millis(100)))
+                .unwrap();
+            ready = events.iter().any(|event| {
+                event.token() == resolver.get_tok() && event.readiness().is_readable()
+            });
+        }
+        assert!(ready);
+        let addrs = resolver.get_result().unwrap().unwrap();
+        assert!(!addrs.is_empty());
+        assert!(
+            addrs
+                .iter()
+                .all(|addr| addr.ip().is_loopback() && addr.port() == 6667)
+        );
+    }
+}
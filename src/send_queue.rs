@@ -0,0 +1,127 @@
+//! Outgoing message queue with flood protection. Servers disconnect clients that send too many
+//! messages in a short time (`Excess Flood`), so lines are sent using a token bucket: `burst`
+//! lines can be sent at once, after that one line per `interval`.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+pub struct SendQueue {
+    burst: u32,
+    /// Zero means no limit.
+    interval: Duration,
+    /// Lines we can send now without waiting. At most `burst`.
+    tokens: u32,
+    /// When `tokens` was last updated.
+    last_refill: Instant,
+    lines: VecDeque<Vec<u8>>,
+}
+
+impl SendQueue {
+    pub fn new(burst: u8, interval: Duration, now: Instant) -> SendQueue {
+        let burst = u32::from(burst.max(1));
+        SendQueue {
+            burst,
+            interval,
+            tokens: burst,
+            last_refill: now,
+            lines: VecDeque::new(),
+        }
+    }
+
+    /// Add a line (with the `\r\n`) to the queue. Use `pop_ready` to get lines to send.
+    pub fn push(&mut self, line: Vec<u8>) {
+        self.lines.push_back(line);
+    }
+
+    /// Number of lines waiting to be sent.
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    /// Drop queued lines and start with a full bucket, e.g. after a reconnect.
+    pub fn clear(&mut self, now: Instant) {
+        self.lines.clear();
+        self.tokens = self.burst;
+        self.last_refill = now;
+    }
+
+    /// Remove and return the lines that can be sent at `now`.
+    pub fn pop_ready(&mut self, now: Instant) -> Vec<Vec<u8>> {
+        if self.interval == Duration::from_secs(0) {
+            return self.lines.drain(..).collect();
+        }
+        self.refill(now);
+        let n = (self.tokens as usize).min(self.lines.len());
+        self.tokens -= n as u32;
+        self.lines.drain(..n).collect()
+    }
+
+    fn refill(&mut self, now: Instant) {
+        if self.tokens == self.burst {
+            self.last_refill = now;
+            return;
+        }
+        let elapsed = duration_millis(now.duration_since(self.last_refill));
+        let new_tokens = elapsed / duration_millis(self.interval);
+        if new_tokens == 0 {
+            return;
+        }
+        if self.tokens as u64 + new_tokens >= self.burst as u64 {
+            self.tokens = self.burst;
+            self.last_refill = now;
+        } else {
+            self.tokens += new_tokens as u32;
+            self.last_refill += self.interval * new_tokens as u32;
+        }
+    }
+}
+
+fn duration_millis(duration: Duration) -> u64 {
+    duration.as_secs() * 1000 + u64::from(duration.subsec_nanos() / 1_000_000)
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(n: u8) -> Vec<u8> {
+        vec![n]
+    }
+
+    #[test]
+    fn test_send_queue() {
+        let t0 = Instant::now();
+        let secs = Duration::from_secs;
+        let mut queue = SendQueue::new(3, secs(2), t0);
+        for n in 0..6 {
+            queue.push(line(n));
+        }
+        // burst
+        assert_eq!(queue.pop_ready(t0), vec![line(0), line(1), line(2)]);
+        assert_eq!(queue.len(), 3);
+        assert_eq!(queue.pop_ready(t0 + secs(1)), Vec::<Vec<u8>>::new());
+        // one line per interval after the burst
+        assert_eq!(queue.pop_ready(t0 + secs(2)), vec![line(3)]);
+        assert_eq!(queue.pop_ready(t0 + secs(3)), Vec::<Vec<u8>>::new());
+        assert_eq!(queue.pop_ready(t0 + secs(6)), vec![line(4), line(5)]);
+        assert_eq!(queue.len(), 0);
+
+        // bucket refills after being idle, but not over the burst size
+        for n in 0..5 {
+            queue.push(line(n));
+        }
+        assert_eq!(queue.pop_ready(t0 + secs(60)), vec![line(0), line(1), line(2)]);
+    }
+
+    #[test]
+    fn test_send_queue_no_limit() {
+        let t0 = Instant::now();
+        let mut queue = SendQueue::new(1, Duration::from_secs(0), t0);
+        for n in 0..5 {
+            queue.push(line(n));
+        }
+        assert_eq!(queue.pop_ready(t0).len(), 5);
+    }
+}
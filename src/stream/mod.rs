@@ -4,8 +4,8 @@ pub mod tcp;
 pub mod tls;
 pub mod utils;
 
-pub use self::tcp::TcpStream;
-pub use self::tls::TlsStream;
+pub use self::tcp::{resolve_addr, TcpStream};
+pub use self::tls::{ClientCert, TlsStream};
 pub use std::io::Error as IoError;
 use mio::Poll;
 use mio::Token;
@@ -16,6 +16,7 @@ use std::error::Error;
 use std::error;
 use std::io::Write;
 use std::io;
+use std::net::SocketAddr;
 use std::result;
 
 pub enum Stream<'poll> {
@@ -92,6 +93,8 @@ impl From<TlsError> for StreamErr {
                 StreamErr::from(err),
             TlsError::TlsError(err) =>
                 StreamErr::TlsError(err),
+            TlsError::CertError(err) =>
+                StreamErr::IoError(err),
         }
     }
 }
@@ -103,18 +106,21 @@ impl From<IoError> for StreamErr {
 }
 
 impl<'poll> Stream<'poll> {
+    /// Connect to `addr`, a resolved address of `serv_addr`. `serv_addr` is used to verify the
+    /// server certificate.
     pub fn new(
         poll: &'poll Poll,
         serv_addr: &str,
-        serv_port: u16,
+        addr: SocketAddr,
         tls: bool,
+        client_cert: Option<&ClientCert>,
     ) -> Result<Stream<'poll>> {
         if tls {
-            TlsStream::new(poll, serv_addr, serv_port)
+            TlsStream::new(poll, serv_addr, addr, client_cert)
                 .map_err(StreamErr::from)
                 .map(Stream::Tls)
         } else {
-            TcpStream::new(poll, serv_addr, serv_port)
+            TcpStream::new(poll, addr)
                 .map_err(StreamErr::from)
                 .map(Stream::Tcp)
         }
@@ -146,6 +152,16 @@ impl<'poll> Stream<'poll> {
                 s.get_tok(),
         }
     }
+
+    /// Local address of the connection. Used as our address in DCC offers.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        match *self {
+            Stream::Tcp(ref s) =>
+                s.local_addr(),
+            Stream::Tls(ref s) =>
+                s.local_addr(),
+        }
+    }
 }
 
 impl<'poll> Write for Stream<'poll> {
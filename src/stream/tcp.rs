@@ -29,16 +29,21 @@ pub enum TcpError {
     ConnectionClosed,
 }
 
+/// Resolve a host name to IPv6 and IPv4 addresses, in the order they should be tried.
+pub fn resolve_addr(serv_addr: &str, serv_port: u16) -> Result<Vec<SocketAddr>, TcpError> {
+    let addrs: Vec<SocketAddr> = (serv_addr, serv_port)
+        .to_socket_addrs()
+        .map_err(TcpError::IoError)?
+        .collect();
+    if addrs.is_empty() {
+        Err(TcpError::CantResolveAddr)
+    } else {
+        Ok(addrs)
+    }
+}
+
 impl<'poll> TcpStream<'poll> {
-    pub fn new(
-        poll: &'poll Poll,
-        serv_addr: &str,
-        serv_port: u16,
-    ) -> Result<TcpStream<'poll>, TcpError> {
-        let mut addr_iter = (serv_addr, serv_port)
-            .to_socket_addrs()
-            .map_err(TcpError::IoError)?;
-        let addr = addr_iter.next().ok_or(TcpError::CantResolveAddr)?;
+    pub fn new(poll: &'poll Poll, addr: SocketAddr) -> Result<TcpStream<'poll>, TcpError> {
         let stream = {
             match addr {
                 SocketAddr::V4(_) =>
@@ -100,6 +105,10 @@ impl<'poll> TcpStream<'poll> {
     pub fn get_tok(&self) -> Token {
         Token(self.inner.as_raw_fd() as usize)
     }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.local_addr()
+    }
 }
 
 // Drop just deregisters the socket
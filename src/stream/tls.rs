@@ -2,9 +2,11 @@ use mio::Poll;
 use mio::Token;
 use take_mut::take;
 use native_tls as tls;
+use std::fs::File;
 use std::io::Read;
 use std::io::Write;
 use std::io;
+use std::net::SocketAddr;
 use std::result::Result;
 
 use stream::tcp::{TcpError, TcpStream};
@@ -21,23 +23,107 @@ pub enum TlsStream<'poll> {
     Broken,
 }
 
+/// A TLS client certificate and its private key.
+pub enum ClientCert {
+    /// A PKCS#12 archive
+    Pkcs12 { der: Vec<u8>, password: String },
+    /// Paths of a certificate (or certificate chain) and a private key in PEM format. Loaded by
+    /// OpenSSL when connecting.
+    Pem { cert_path: String, key_path: String },
+}
+
+impl ClientCert {
+    pub fn from_file(path: &str, password: &str) -> io::Result<ClientCert> {
+        let mut der = vec![];
+        File::open(path)?.read_to_end(&mut der)?;
+        Ok(ClientCert::Pkcs12 {
+            der,
+            password: password.to_owned(),
+        })
+    }
+
+    pub fn from_pem_files(cert_path: &str, key_path: &str) -> io::Result<ClientCert> {
+        // check that the files are readable, to report errors before connecting
+        File::open(cert_path)?;
+        File::open(key_path)?;
+        Ok(ClientCert::Pem {
+            cert_path: cert_path.to_owned(),
+            key_path: key_path.to_owned(),
+        })
+    }
+}
+
 #[derive(Debug)]
 pub enum TlsError {
     TcpError(TcpError),
     TlsError(tls::Error),
+    /// Loading a PEM client certificate failed
+    CertError(io::Error),
+}
+
+fn add_client_cert(
+    builder: &mut tls::TlsConnectorBuilder,
+    cert: &ClientCert,
+) -> Result<(), TlsError> {
+    match *cert {
+        ClientCert::Pkcs12 { ref der, ref password } => {
+            let identity = tls::Pkcs12::from_der(der, password).map_err(TlsError::TlsError)?;
+            builder.identity(identity).map_err(TlsError::TlsError)?;
+            Ok(())
+        }
+        ClientCert::Pem {
+            ref cert_path,
+            ref key_path,
+        } =>
+            add_pem_cert(builder, cert_path, key_path),
+    }
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "ios", target_os = "windows")))]
+fn add_pem_cert(
+    builder: &mut tls::TlsConnectorBuilder,
+    cert_path: &str,
+    key_path: &str,
+) -> Result<(), TlsError> {
+    use native_tls::backend::openssl::TlsConnectorBuilderExt;
+    use openssl::x509::X509_FILETYPE_PEM;
+
+    let ctx = builder.builder_mut().builder_mut();
+    ctx.set_certificate_chain_file(cert_path)
+        .and_then(|()| ctx.set_private_key_file(key_path, X509_FILETYPE_PEM))
+        .and_then(|()| ctx.check_private_key())
+        .map_err(|err| {
+            TlsError::CertError(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Can't load client certificate: {}", err),
+            ))
+        })
+}
+
+/// native-tls uses the platform TLS library, which only takes PKCS#12 identities on macOS and
+/// Windows.
+#[cfg(any(target_os = "macos", target_os = "ios", target_os = "windows"))]
+fn add_pem_cert(_: &mut tls::TlsConnectorBuilder, _: &str, _: &str) -> Result<(), TlsError> {
+    Err(TlsError::CertError(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "PEM client certificates are only supported with OpenSSL, \
+         use a PKCS#12 archive instead",
+    )))
 }
 
 impl<'poll> TlsStream<'poll> {
     pub fn new(
         poll: &'poll Poll,
         serv_addr: &str,
-        serv_port: u16,
+        addr: SocketAddr,
+        client_cert: Option<&ClientCert>,
     ) -> Result<TlsStream<'poll>, TlsError> {
-        let connector = tls::TlsConnector::builder()
-            .map_err(TlsError::TlsError)?
-            .build()
-            .map_err(TlsError::TlsError)?;
-        let tcp_stream = TcpStream::new(poll, serv_addr, serv_port).map_err(TlsError::TcpError)?;
+        let mut builder = tls::TlsConnector::builder().map_err(TlsError::TlsError)?;
+        if let Some(cert) = client_cert {
+            add_client_cert(&mut builder, cert)?;
+        }
+        let connector = builder.build().map_err(TlsError::TlsError)?;
+        let tcp_stream = TcpStream::new(poll, addr).map_err(TlsError::TcpError)?;
         match connector.connect(serv_addr, tcp_stream) {
             Ok(tls_stream) =>
                 Ok(TlsStream::Connected { stream: tls_stream }),
@@ -113,6 +199,17 @@ impl<'poll> TlsStream<'poll> {
                 panic!("get_tok() called on broken tls stream"),
         }
     }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        match *self {
+            TlsStream::Handshake { ref stream, .. } =>
+                stream.get_ref().local_addr(),
+            TlsStream::Connected { ref stream } =>
+                stream.get_ref().local_addr(),
+            TlsStream::Broken =>
+                panic!("local_addr() called on broken tls stream"),
+        }
+    }
 }
 
 impl<'poll> Write for TlsStream<'poll> {
@@ -57,6 +57,37 @@ impl Trie {
         }
     }
 
+    /// Find a word that is equal to `str` after mapping characters of both with `fold` (e.g. to
+    /// lowercase). Returns the word as it was inserted.
+    pub fn find_by<F>(&self, str: &str, fold: &F) -> Option<String>
+    where
+        F: Fn(char) -> char,
+    {
+        let mut chars = str.chars();
+        match chars.next() {
+            None =>
+                if self.word {
+                    Some(String::new())
+                } else {
+                    None
+                },
+            Some(char) => {
+                let char = fold(char);
+                for &(char_, ref trie) in &self.vec {
+                    if fold(char_) == char {
+                        if let Some(rest) = trie.find_by(chars.as_str(), fold) {
+                            let mut ret = String::with_capacity(str.len());
+                            ret.push(char_);
+                            ret.push_str(&rest);
+                            return Some(ret);
+                        }
+                    }
+                }
+                None
+            }
+        }
+    }
+
     // TODO: We need an Iterator instance instead.
     pub fn to_strings(&self, prefix: &str) -> Vec<String> {
         let mut ret = {
@@ -174,6 +205,21 @@ mod tests {
         assert_eq!(vec!["az"], trie.drop_pfx(&mut "b".chars()));
     }
 
+    #[test]
+    fn trie_test_find_by() {
+        let mut trie = Trie::new();
+        trie.insert("Foo");
+        trie.insert("fob");
+        trie.insert("bar");
+        let lower = |c: char| c.to_ascii_lowercase();
+        assert_eq!(trie.find_by("foo", &lower), Some("Foo".to_owned()));
+        assert_eq!(trie.find_by("FOB", &lower), Some("fob".to_owned()));
+        assert_eq!(trie.find_by("Bar", &lower), Some("bar".to_owned()));
+        assert_eq!(trie.find_by("fo", &lower), None);
+        assert_eq!(trie.find_by("baz", &lower), None);
+        assert_eq!(trie.find_by("foo", &|c| c), None);
+    }
+
 } // tests
 
 #[cfg(test)]
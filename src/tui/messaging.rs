@@ -1,6 +1,8 @@
 use termbox_simple::Termbox;
 use term_input::Key;
 
+use std::collections::BTreeMap;
+use std::collections::HashMap;
 use std::convert::From;
 
 use time::Tm;
@@ -9,14 +11,17 @@ use time;
 use config::Colors;
 use config::Style;
 use config;
+use isupport::CaseMapping;
 use trie::Trie;
 use tui::exit_dialogue::ExitDialogue;
 use tui::msg_area::line::SchemeStyle;
 use tui::msg_area::line::SegStyle;
+use tui::msg_area::Line;
 use tui::msg_area::MsgArea;
 use tui::termbox;
 use tui::text_field::TextField;
 use tui::widget::WidgetRet;
+use who::Member;
 
 /// A messaging screen is just a text field to type messages and msg area to
 /// show incoming/sent messages.
@@ -39,11 +44,24 @@ pub struct MessagingUI {
     // properly highlight mentions.
     nicks: Trie,
 
+    /// Membership prefixes (e.g. `@`) and WHO information of nicks in the channel, keyed by
+    /// casemapped nick. Nicks we don't know anything about are not in the map.
+    members: HashMap<String, Member>,
+
+    /// Channel modes other than list and membership modes, with their parameters.
+    chan_modes: BTreeMap<char, Option<String>>,
+
+    /// Casemapping of the server, used when looking up nicks in `nicks`.
+    casemapping: CaseMapping,
+
     current_nick: Option<String>,
     draw_current_nick: bool,
 
     last_activity_line: Option<ActivityLine>,
     last_activity_ts: Option<Timestamp>,
+
+    /// Index of the "Reconnecting in ..." line in `msg_area`, updated every second.
+    reconnect_line: Option<usize>,
 }
 
 /// Like `time::Tm`, but we only care about hour and minute parts.
@@ -91,9 +109,13 @@ impl MessagingUI {
             height: height,
             show_status: status,
             nicks: Trie::new(),
+            members: HashMap::new(),
+            chan_modes: BTreeMap::new(),
+            casemapping: CaseMapping::Rfc1459,
             current_nick: None,
             draw_current_nick: true,
             last_activity_line: None,
+            reconnect_line: None,
             last_activity_ts: None,
         }
     }
@@ -110,6 +132,14 @@ impl MessagingUI {
         self.current_nick.as_ref().map(String::as_str)
     }
 
+    pub fn set_casemapping(&mut self, casemapping: CaseMapping) {
+        self.casemapping = casemapping;
+    }
+
+    pub fn get_casemapping(&self) -> CaseMapping {
+        self.casemapping
+    }
+
     fn draw_input_field(&self, tb: &mut Termbox, colors: &Colors, pos_x: i32, pos_y: i32) {
         match self.exit_dialogue {
             Some(ref exit_dialogue) =>
@@ -370,6 +400,36 @@ impl MessagingUI {
 
     pub fn clear(&mut self) {
         self.msg_area.clear();
+        self.reconnect_line = None;
+    }
+
+    /// Show seconds left before reconnecting. The line is updated in place, `None` stops updating
+    /// it.
+    pub fn show_reconnect_countdown(&mut self, secs: Option<u16>) {
+        let secs = match secs {
+            None => {
+                self.reconnect_line = None;
+                return;
+            }
+            Some(secs) =>
+                secs,
+        };
+        let msg = format!("Reconnecting in {}s", secs);
+        match self.reconnect_line {
+            Some(line_idx) =>
+                self.msg_area.modify_line(line_idx, |line| {
+                    *line = Line::new();
+                    line.set_style(SegStyle::SchemeStyle(SchemeStyle::Faded));
+                    line.add_text(&msg);
+                }),
+            None => {
+                self.reset_activity_line();
+                self.msg_area
+                    .set_style(SegStyle::SchemeStyle(SchemeStyle::Faded));
+                self.msg_area.add_text(&msg);
+                self.reconnect_line = Some(self.msg_area.flush_line());
+            }
+        }
     }
 
     fn get_nick_color(&self, sender: &str) -> usize {
@@ -388,9 +448,12 @@ impl MessagingUI {
 impl MessagingUI {
     pub fn clear_nicks(&mut self) {
         self.nicks.clear();
+        self.members.clear();
+        self.chan_modes.clear();
     }
 
     pub fn join(&mut self, nick: &str, ts: Option<Timestamp>) {
+        self.remove_nick(nick);
         self.nicks.insert(nick);
 
         if self.show_status {
@@ -408,7 +471,7 @@ impl MessagingUI {
     }
 
     pub fn part(&mut self, nick: &str, ts: Option<Timestamp>) {
-        self.nicks.remove(nick);
+        self.remove_nick(nick);
 
         if self.show_status {
             if let Some(ts) = ts {
@@ -440,8 +503,13 @@ impl MessagingUI {
     }
 
     pub fn nick(&mut self, old_nick: &str, new_nick: &str, ts: Timestamp) {
-        self.nicks.remove(old_nick);
+        let member = self.members.remove(&self.casemapping.to_lower(old_nick));
+        self.remove_nick(old_nick);
+        self.remove_nick(new_nick);
         self.nicks.insert(new_nick);
+        if let Some(member) = member {
+            self.set_member(new_nick, member);
+        }
 
         let line_idx = self.get_activity_line_idx(ts);
         self.msg_area.modify_line(line_idx, |line| {
@@ -456,7 +524,21 @@ impl MessagingUI {
     }
 
     pub fn has_nick(&self, nick: &str) -> bool {
-        self.nicks.contains(nick)
+        self.find_nick(nick).is_some()
+    }
+
+    /// Find the nick in the nick list, as it was added.
+    fn find_nick(&self, nick: &str) -> Option<String> {
+        let casemapping = self.casemapping;
+        self.nicks.find_by(nick, &|c| casemapping.to_lower_char(c))
+    }
+
+    fn remove_nick(&mut self, nick: &str) {
+        if let Some(nick) = self.find_nick(nick) {
+            self.nicks.remove(&nick);
+        }
+        let key = self.casemapping.to_lower(nick);
+        self.members.remove(&key);
     }
 
     fn reset_activity_line(&mut self) {
@@ -480,3 +562,69 @@ impl MessagingUI {
         line_idx
     }
 }
+
+////////////////////////////////////////////////////////////////////////////////
+// Channel modes and membership prefixes
+
+impl MessagingUI {
+    /// Membership prefixes of a nick, e.g. `@+`. Empty if the nick has none.
+    pub fn get_nick_prefix(&self, nick: &str) -> &str {
+        self.get_member(nick)
+            .map(|member| member.prefix.as_str())
+            .unwrap_or("")
+    }
+
+    pub fn set_nick_prefix(&mut self, nick: &str, prefix: &str) {
+        let key = self.casemapping.to_lower(nick);
+        self.members.entry(key).or_insert_with(Member::default).prefix = prefix.to_owned();
+    }
+
+    pub fn get_member(&self, nick: &str) -> Option<&Member> {
+        self.members.get(&self.casemapping.to_lower(nick))
+    }
+
+    /// Update information of a channel member from a WHO reply.
+    pub fn set_member(&mut self, nick: &str, member: Member) {
+        let key = self.casemapping.to_lower(nick);
+        self.members.insert(key, member);
+    }
+
+    /// Set `user@host` of a member, e.g. from the prefix of a JOIN message.
+    pub fn set_member_user(&mut self, nick: &str, user: &str) {
+        let key = self.casemapping.to_lower(nick);
+        self.members.entry(key).or_insert_with(Member::default).user = Some(user.to_owned());
+    }
+
+    /// Set or unset a channel mode. `arg` is the mode parameter, if the mode has one.
+    pub fn apply_chan_mode(&mut self, mode: char, arg: Option<&str>, set: bool) {
+        if set {
+            self.chan_modes.insert(mode, arg.map(str::to_owned));
+        } else {
+            self.chan_modes.remove(&mode);
+        }
+    }
+
+    pub fn clear_chan_modes(&mut self) {
+        self.chan_modes.clear();
+    }
+
+    /// Channel modes in MODE syntax, e.g. `+klnt hunter2 10`. Empty if no modes are known.
+    pub fn get_chan_modes(&self) -> String {
+        if self.chan_modes.is_empty() {
+            return String::new();
+        }
+        let mut ret = String::from("+");
+        let mut args = vec![];
+        for (mode, arg) in &self.chan_modes {
+            ret.push(*mode);
+            if let Some(ref arg) = *arg {
+                args.push(arg.as_str());
+            }
+        }
+        for arg in args {
+            ret.push(' ');
+            ret.push_str(arg);
+        }
+        ret
+    }
+}
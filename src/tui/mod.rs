@@ -12,10 +12,12 @@ use config::Colors;
 use self::tabbed::{MsgSource, TabStyle, Tabbed, TabbedRet};
 pub use self::messaging::Timestamp;
 
+use isupport::CaseMapping;
 use notifier::Notifier;
 use term_input::{Event, Key};
 use termbox_simple::{OutputMode, Termbox};
 use trie::Trie;
+use who::Member;
 
 pub struct TUI {
     /// Termbox instance
@@ -92,10 +94,33 @@ impl TUI {
         self.ui.close_user_tab(serv_name, nick);
     }
 
+    pub fn new_dcc_tab(&mut self, serv_name: &str, nick: &str) {
+        self.ui.new_dcc_tab(serv_name, nick);
+    }
+
+    pub fn close_dcc_tab(&mut self, serv_name: &str, nick: &str) {
+        self.ui.close_dcc_tab(serv_name, nick);
+    }
+
     pub fn set_tab_style(&mut self, style: TabStyle, target: &MsgTarget) {
         self.ui.set_tab_style(style, target);
     }
 
+    /// Set casemapping used for comparing channel names and nicks in tabs of a server.
+    pub fn set_casemapping(&mut self, serv_name: &str, casemapping: CaseMapping) {
+        self.ui.set_casemapping(
+            casemapping,
+            &MsgTarget::AllServTabs {
+                serv_name: serv_name,
+            },
+        );
+    }
+
+    /// Show network name instead of the server address in the server tab.
+    pub fn set_network_name(&mut self, serv_name: &str, network: &str) {
+        self.ui.set_serv_tab_name(serv_name, network);
+    }
+
     pub fn set_nick(&mut self, serv_name: &str, nick: &str) {
         self.ui.set_nick(
             nick,
@@ -109,9 +134,29 @@ impl TUI {
         self.ui.get_nicks(serv_name, chan_name)
     }
 
+    /// Membership prefixes (e.g. `@`) of a nick in a channel.
+    pub fn get_nick_prefix(&self, serv_name: &str, chan_name: &str, nick: &str) -> Option<&str> {
+        self.ui.get_nick_prefix(serv_name, chan_name, nick)
+    }
+
+    /// What we know about a channel member: prefixes and information from WHO replies.
+    pub fn get_member(&self, serv_name: &str, chan_name: &str, nick: &str) -> Option<&Member> {
+        self.ui.get_member(serv_name, chan_name, nick)
+    }
+
+    /// Channel modes in MODE syntax, e.g. `+nt`.
+    pub fn get_chan_modes(&self, serv_name: &str, chan_name: &str) -> Option<String> {
+        self.ui.get_chan_modes(serv_name, chan_name)
+    }
+
     pub fn does_user_tab_exist(&self, serv_name: &str, nick: &str) -> bool {
         self.ui.does_user_tab_exist(serv_name, nick)
     }
+
+    /// Channels of the server with the nick in their nick lists.
+    pub fn get_nick_chans(&self, serv_name: &str, nick: &str) -> Vec<String> {
+        self.ui.get_nick_chans(serv_name, nick)
+    }
 }
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -194,6 +239,10 @@ pub enum MsgTarget<'a> {
         serv_name: &'a str,
         nick: &'a str,
     },
+    Dcc {
+        serv_name: &'a str,
+        nick: &'a str,
+    },
 
     /// Show the message in all tabs of a server.
     AllServTabs {
@@ -262,6 +311,17 @@ impl TUI {
         self.ui.add_msg(msg, ts, target);
     }
 
+    /// Desktop notification for an invite. Uses notification settings of the target tab.
+    pub fn notify_invite(&mut self, sender: &str, chan: &str, target: &MsgTarget) {
+        self.ui.notify_invite(sender, chan, target);
+    }
+
+    /// Desktop notification for a friend coming online or going offline. Uses notification
+    /// settings of the target tab.
+    pub fn notify_friend(&mut self, nick: &str, online: bool, target: &MsgTarget) {
+        self.ui.notify_friend(nick, online, target);
+    }
+
     /// Error messages related with the protocol - e.g. can't join a channel,
     /// nickname is in use etc. Timestamped and logged.
     pub fn add_err_msg(&mut self, msg: &str, ts: Timestamp, target: &MsgTarget) {
@@ -280,6 +340,48 @@ impl TUI {
         self.ui.add_nick(nick, ts, target);
     }
 
+    pub fn set_nick_prefix(&mut self, nick: &str, prefix: &str, target: &MsgTarget) {
+        self.ui.set_nick_prefix(nick, prefix, target);
+    }
+
+    pub fn set_member(&mut self, nick: &str, member: &Member, target: &MsgTarget) {
+        self.ui.set_member(nick, member, target);
+    }
+
+    pub fn set_member_user(&mut self, nick: &str, user: &str, target: &MsgTarget) {
+        self.ui.set_member_user(nick, user, target);
+    }
+
+    pub fn apply_chan_mode(
+        &mut self,
+        mode: char,
+        arg: Option<&str>,
+        set: bool,
+        target: &MsgTarget,
+    ) {
+        self.ui.apply_chan_mode(mode, arg, set, target);
+    }
+
+    pub fn clear_chan_modes(&mut self, target: &MsgTarget) {
+        self.ui.clear_chan_modes(target);
+    }
+
+    /// Show seconds left before reconnecting in a line updated in place. `None` stops updating
+    /// the line.
+    pub fn show_reconnect_countdown(&mut self, secs: Option<u16>, target: &MsgTarget) {
+        self.ui.show_reconnect_countdown(secs, target);
+    }
+
+    /// Show number of messages waiting in the flood protection queue in tab names.
+    pub fn set_queued_lines(&mut self, queued_lines: usize, target: &MsgTarget) {
+        self.ui.set_queued_lines(queued_lines, target);
+    }
+
+    /// Mark channel tabs as parted (e.g. after a kick) or joined.
+    pub fn set_parted(&mut self, parted: bool, target: &MsgTarget) {
+        self.ui.set_parted(parted, target);
+    }
+
     pub fn toggle_ignore(&mut self, target: &MsgTarget) {
         self.ui.toggle_ignore(target);
     }
@@ -3,12 +3,14 @@ use termbox_simple::{Termbox, TB_UNDERLINE};
 
 use config::Colors;
 use config::Style;
+use isupport::CaseMapping;
 use notifier::Notifier;
 use trie::Trie;
 use tui::messaging::MessagingUI;
 use tui::messaging::Timestamp;
 use tui::MsgTarget;
 use tui::widget::WidgetRet;
+use who::Member;
 
 const LEFT_ARROW: char = '<';
 const RIGHT_ARROW: char = '>';
@@ -31,6 +33,14 @@ struct Tab {
     /// Alt-character to use to switch to this tab.
     switch: Option<char>,
     notifier: Notifier,
+    /// Name to show in the tab bar instead of the source name. Used for showing network names in
+    /// server tabs.
+    name: Option<String>,
+    /// Set when we're no longer in the channel (e.g. kicked). Tab name is drawn faded.
+    parted: bool,
+    /// Number of messages in the server's flood protection queue. Drawn after the tab name when
+    /// non-zero.
+    queued_lines: usize,
 }
 
 // NOTE: Keep the variants sorted in increasing significance, to avoid updating
@@ -70,6 +80,10 @@ pub enum MsgSource {
 
     /// Message sent to a privmsg tab.
     User { serv_name: String, nick: String },
+
+    /// Message sent to a DCC CHAT tab. These messages are sent directly to the user, not to the
+    /// server.
+    Dcc { serv_name: String, nick: String },
 }
 
 impl MsgSource {
@@ -77,7 +91,8 @@ impl MsgSource {
         match *self {
             MsgSource::Serv { ref serv_name }
             | MsgSource::Chan { ref serv_name, .. }
-            | MsgSource::User { ref serv_name, .. } =>
+            | MsgSource::User { ref serv_name, .. }
+            | MsgSource::Dcc { ref serv_name, .. } =>
                 serv_name,
         }
     }
@@ -99,6 +114,11 @@ impl MsgSource {
                 ref nick,
             } =>
                 MsgTarget::User { serv_name, nick },
+            MsgSource::Dcc {
+                ref serv_name,
+                ref nick,
+            } =>
+                MsgTarget::Dcc { serv_name, nick },
         }
     }
 
@@ -108,7 +128,7 @@ impl MsgSource {
                 serv_name,
             MsgSource::Chan { ref chan_name, .. } =>
                 chan_name,
-            MsgSource::User { ref nick, .. } =>
+            MsgSource::User { ref nick, .. } | MsgSource::Dcc { ref nick, .. } =>
                 nick,
         }
     }
@@ -116,7 +136,12 @@ impl MsgSource {
 
 impl Tab {
     pub fn visible_name(&self) -> &str {
-        self.src.visible_name()
+        match self.name {
+            None =>
+                self.src.visible_name(),
+            Some(ref name) =>
+                name,
+        }
     }
 
     fn set_style(&mut self, style: TabStyle) {
@@ -133,7 +158,17 @@ impl Tab {
     pub fn width(&self) -> i32 {
         // TODO: assuming ASCII string here. We should probably switch to a AsciiStr type.
         self.visible_name().len() as i32 +
-            if self.widget.get_ignore_state() { 0 } else { 3 }
+            if self.widget.get_ignore_state() { 0 } else { 3 } +
+            self.queued_lines_indicator().len() as i32
+    }
+
+    /// E.g. `[+3]` when 3 messages are waiting in the send queue.
+    fn queued_lines_indicator(&self) -> String {
+        if self.queued_lines == 0 {
+            String::new()
+        } else {
+            format!("[+{}]", self.queued_lines)
+        }
     }
 
     pub fn draw(
@@ -146,6 +181,8 @@ impl Tab {
     ) {
         let style: Style = if active {
             colors.tab_active
+        } else if self.parted {
+            colors.faded
         } else {
             self.style.get_style(colors)
         };
@@ -162,7 +199,9 @@ impl Tab {
         }
         if !self.widget.get_ignore_state() {
             ::tui::termbox::print_chars(tb, pos_x, pos_y, style, "[i]".chars());
+            pos_x += 3;
         }
+        ::tui::termbox::print_chars(tb, pos_x, pos_y, style, self.queued_lines_indicator().chars());
     }
 }
 
@@ -226,6 +265,9 @@ impl Tabbed {
                 style: TabStyle::Normal,
                 switch,
                 notifier,
+                name: None,
+                parted: false,
+                queued_lines: 0,
             },
         );
     }
@@ -298,6 +340,8 @@ impl Tabbed {
                         if let Some(nick) = self.tabs[serv_tab_idx].widget.get_nick().map(str::to_owned) {
                             self.tabs[tab_idx].widget.set_nick(nick);
                         }
+                        let casemapping = self.tabs[serv_tab_idx].widget.get_casemapping();
+                        self.tabs[tab_idx].widget.set_casemapping(casemapping);
                         Some(tab_idx)
                     }
                 },
@@ -337,6 +381,41 @@ impl Tabbed {
                         if let Some(nick) = self.tabs[tab_idx].widget.get_nick().map(str::to_owned) {
                             self.tabs[tab_idx + 1].widget.set_nick(nick);
                         }
+                        let casemapping = self.tabs[tab_idx].widget.get_casemapping();
+                        self.tabs[tab_idx + 1].widget.set_casemapping(casemapping);
+                        Some(tab_idx + 1)
+                    }
+                },
+            Some(_) =>
+                None,
+        }
+    }
+
+    /// Returns index of the new tab if a new tab is created. DCC CHAT tabs are shown as `=nick`.
+    pub fn new_dcc_tab(&mut self, serv_name: &str, nick: &str) -> Option<usize> {
+        match self.find_dcc_tab_idx(serv_name, nick) {
+            None =>
+                match self.find_last_serv_tab_idx(serv_name) {
+                    None => {
+                        self.new_server_tab(serv_name);
+                        self.new_dcc_tab(serv_name, nick)
+                    }
+                    Some(tab_idx) => {
+                        self.new_tab(
+                            tab_idx + 1,
+                            MsgSource::Dcc {
+                                serv_name: serv_name.to_owned(),
+                                nick: nick.to_owned(),
+                            },
+                            true,
+                            Notifier::Messages
+                        );
+                        self.tabs[tab_idx + 1].name = Some(format!("={}", nick));
+                        if let Some(nick) = self.tabs[tab_idx].widget.get_nick().map(str::to_owned) {
+                            self.tabs[tab_idx + 1].widget.set_nick(nick);
+                        }
+                        let casemapping = self.tabs[tab_idx].widget.get_casemapping();
+                        self.tabs[tab_idx + 1].widget.set_casemapping(casemapping);
                         Some(tab_idx + 1)
                     }
                 },
@@ -345,6 +424,15 @@ impl Tabbed {
         }
     }
 
+    pub fn close_dcc_tab(&mut self, serv_name: &str, nick: &str) {
+        if let Some(tab_idx) = self.find_dcc_tab_idx(serv_name, nick) {
+            self.tabs.remove(tab_idx);
+            if self.active_idx == tab_idx {
+                self.select_tab(if tab_idx == 0 { 0 } else { tab_idx - 1 });
+            }
+        }
+    }
+
     pub fn close_user_tab(&mut self, serv_name: &str, nick: &str) {
         if let Some(tab_idx) = self.find_user_tab_idx(serv_name, nick) {
             self.tabs.remove(tab_idx);
@@ -493,6 +581,21 @@ impl Tabbed {
                 Some(self.tabs[i].widget.get_nicks()),
         }
     }
+
+    pub fn get_nick_prefix(&self, serv_name: &str, chan_name: &str, nick: &str) -> Option<&str> {
+        self.find_chan_tab_idx(serv_name, chan_name)
+            .map(|i| self.tabs[i].widget.get_nick_prefix(nick))
+    }
+
+    pub fn get_member(&self, serv_name: &str, chan_name: &str, nick: &str) -> Option<&Member> {
+        self.find_chan_tab_idx(serv_name, chan_name)
+            .and_then(|i| self.tabs[i].widget.get_member(nick))
+    }
+
+    pub fn get_chan_modes(&self, serv_name: &str, chan_name: &str) -> Option<String> {
+        self.find_chan_tab_idx(serv_name, chan_name)
+            .map(|i| self.tabs[i].widget.get_chan_modes())
+    }
 }
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -672,7 +775,7 @@ impl Tabbed {
                         next_idx = tab_idx;
                         break;
                     },
-                MsgSource::User { ref nick, .. } =>
+                MsgSource::User { ref nick, .. } | MsgSource::Dcc { ref nick, .. } =>
                     if nick.contains(string) {
                         next_idx = tab_idx;
                         break;
@@ -813,7 +916,9 @@ impl Tabbed {
                         chan_name: ref chan_name_,
                     } = tab.src
                     {
-                        if serv_name == serv_name_ && chan_name == chan_name_ {
+                        if serv_name == serv_name_
+                            && tab.widget.get_casemapping().eq(chan_name, chan_name_)
+                        {
                             target_idxs.push(tab_idx);
                             break;
                         }
@@ -827,13 +932,18 @@ impl Tabbed {
                         nick: ref nick_,
                     } = tab.src
                     {
-                        if serv_name == serv_name_ && nick == nick_ {
+                        if serv_name == serv_name_ && tab.widget.get_casemapping().eq(nick, nick_) {
                             target_idxs.push(tab_idx);
                             break;
                         }
                     }
                 },
 
+            MsgTarget::Dcc { serv_name, nick } =>
+                if let Some(tab_idx) = self.find_dcc_tab_idx(serv_name, nick) {
+                    target_idxs.push(tab_idx);
+                },
+
             MsgTarget::AllServTabs { serv_name } =>
                 for (tab_idx, tab) in self.tabs.iter().enumerate() {
                     if tab.src.serv_name() == serv_name {
@@ -844,7 +954,9 @@ impl Tabbed {
             MsgTarget::AllUserTabs { serv_name, nick } =>
                 for (tab_idx, tab) in self.tabs.iter().enumerate() {
                     match tab.src {
-                        MsgSource::Serv { .. } =>
+                        // DCC chats don't go through the server, so e.g. quit messages are not
+                        // relevant
+                        MsgSource::Serv { .. } | MsgSource::Dcc { .. } =>
                             {}
                         MsgSource::Chan {
                             serv_name: ref serv_name_,
@@ -857,7 +969,9 @@ impl Tabbed {
                             serv_name: ref serv_name_,
                             nick: ref nick_,
                         } =>
-                            if serv_name_ == serv_name && nick_ == nick {
+                            if serv_name_ == serv_name
+                                && tab.widget.get_casemapping().eq(nick_, nick)
+                            {
                                 target_idxs.push(tab_idx);
                             },
                     }
@@ -897,6 +1011,9 @@ impl Tabbed {
             MsgTarget::User { serv_name, nick } =>
                 self.new_user_tab(serv_name, nick),
 
+            MsgTarget::Dcc { serv_name, nick } =>
+                self.new_dcc_tab(serv_name, nick),
+
             _ =>
                 None,
         }
@@ -963,6 +1080,22 @@ impl Tabbed {
         });
     }
 
+    /// Show a desktop notification for an invite, according to notification settings of the
+    /// target tab.
+    pub fn notify_invite(&mut self, sender: &str, chan: &str, target: &MsgTarget) {
+        self.apply_to_target(target, &|tab: &mut Tab, _| {
+            tab.notifier.notify_invite(sender, chan);
+        });
+    }
+
+    /// Show a desktop notification for a friend coming online or going offline, according to
+    /// notification settings of the target tab.
+    pub fn notify_friend(&mut self, nick: &str, online: bool, target: &MsgTarget) {
+        self.apply_to_target(target, &|tab: &mut Tab, _| {
+            tab.notifier.notify_friend(nick, online);
+        });
+    }
+
     pub fn add_msg(&mut self, msg: &str, ts: Timestamp, target: &MsgTarget) {
         self.apply_to_target(target, &|tab: &mut Tab, _| {
             tab.widget.add_msg(msg, ts);
@@ -999,6 +1132,60 @@ impl Tabbed {
         });
     }
 
+    pub fn set_nick_prefix(&mut self, nick: &str, prefix: &str, target: &MsgTarget) {
+        self.apply_to_target(target, &|tab: &mut Tab, _| {
+            tab.widget.set_nick_prefix(nick, prefix);
+        });
+    }
+
+    pub fn set_member(&mut self, nick: &str, member: &Member, target: &MsgTarget) {
+        self.apply_to_target(target, &|tab: &mut Tab, _| {
+            tab.widget.set_member(nick, member.clone());
+        });
+    }
+
+    pub fn set_member_user(&mut self, nick: &str, user: &str, target: &MsgTarget) {
+        self.apply_to_target(target, &|tab: &mut Tab, _| {
+            tab.widget.set_member_user(nick, user);
+        });
+    }
+
+    pub fn apply_chan_mode(
+        &mut self,
+        mode: char,
+        arg: Option<&str>,
+        set: bool,
+        target: &MsgTarget,
+    ) {
+        self.apply_to_target(target, &|tab: &mut Tab, _| {
+            tab.widget.apply_chan_mode(mode, arg, set);
+        });
+    }
+
+    pub fn clear_chan_modes(&mut self, target: &MsgTarget) {
+        self.apply_to_target(target, &|tab: &mut Tab, _| {
+            tab.widget.clear_chan_modes();
+        });
+    }
+
+    pub fn show_reconnect_countdown(&mut self, secs: Option<u16>, target: &MsgTarget) {
+        self.apply_to_target(target, &|tab: &mut Tab, _| {
+            tab.widget.show_reconnect_countdown(secs);
+        });
+    }
+
+    pub fn set_queued_lines(&mut self, queued_lines: usize, target: &MsgTarget) {
+        self.apply_to_target(target, &|tab: &mut Tab, _| {
+            tab.queued_lines = queued_lines;
+        });
+    }
+
+    pub fn set_parted(&mut self, parted: bool, target: &MsgTarget) {
+        self.apply_to_target(target, &|tab: &mut Tab, _| {
+            tab.parted = parted;
+        });
+    }
+
     pub fn rename_nick(
         &mut self,
         old_nick: &str,
@@ -1017,6 +1204,18 @@ impl Tabbed {
         });
     }
 
+    pub fn set_casemapping(&mut self, casemapping: CaseMapping, target: &MsgTarget) {
+        self.apply_to_target(target, &|tab: &mut Tab, _| {
+            tab.widget.set_casemapping(casemapping)
+        });
+    }
+
+    pub fn set_serv_tab_name(&mut self, serv_name: &str, name: &str) {
+        if let Some(tab_idx) = self.find_serv_tab_idx(serv_name) {
+            self.tabs[tab_idx].name = Some(name.to_owned());
+        }
+    }
+
     pub fn set_nick(&mut self, new_nick: &str, target: &MsgTarget) {
         self.apply_to_target(target, &|tab: &mut Tab, _| {
             tab.widget.set_nick(new_nick.to_owned())
@@ -1057,7 +1256,7 @@ impl Tabbed {
     pub fn does_user_tab_exist(&self, serv_name_: &str, nick_: &str) -> bool {
         for tab in &self.tabs {
             if let MsgSource::User { ref serv_name, ref nick } = tab.src {
-                if serv_name_ == serv_name && nick_ == nick {
+                if serv_name_ == serv_name && tab.widget.get_casemapping().eq(nick_, nick) {
                     return true;
                 }
             }
@@ -1065,6 +1264,19 @@ impl Tabbed {
         false
     }
 
+    /// Channels of the server with the nick in their nick lists.
+    pub fn get_nick_chans(&self, serv_name_: &str, nick: &str) -> Vec<String> {
+        let mut chans = vec![];
+        for tab in &self.tabs {
+            if let MsgSource::Chan { ref serv_name, ref chan_name } = tab.src {
+                if serv_name_ == serv_name && tab.widget.has_nick(nick) {
+                    chans.push(chan_name.to_owned());
+                }
+            }
+        }
+        chans
+    }
+
     pub fn set_notifier(&mut self, notifier: Notifier, target: &MsgTarget){
         self.apply_to_target(target, &|tab: &mut Tab, _| {
             tab.notifier = notifier;
@@ -1107,7 +1319,7 @@ impl Tabbed {
                 ref chan_name,
             } = tab.src
             {
-                if serv_name_ == serv_name && chan_name_ == chan_name {
+                if serv_name_ == serv_name && tab.widget.get_casemapping().eq(chan_name_, chan_name) {
                     return Some(tab_idx);
                 }
             }
@@ -1122,7 +1334,22 @@ impl Tabbed {
                 ref nick,
             } = tab.src
             {
-                if serv_name_ == serv_name && nick_ == nick {
+                if serv_name_ == serv_name && tab.widget.get_casemapping().eq(nick_, nick) {
+                    return Some(tab_idx);
+                }
+            }
+        }
+        None
+    }
+
+    fn find_dcc_tab_idx(&self, serv_name_: &str, nick_: &str) -> Option<usize> {
+        for (tab_idx, tab) in self.tabs.iter().enumerate() {
+            if let MsgSource::Dcc {
+                ref serv_name,
+                ref nick,
+            } = tab.src
+            {
+                if serv_name_ == serv_name && tab.widget.get_casemapping().eq(nick_, nick) {
                     return Some(tab_idx);
                 }
             }
@@ -1144,7 +1371,7 @@ impl Tabbed {
         match self.tabs[idx].src {
             MsgSource::Serv { .. } =>
                 true,
-            MsgSource::Chan { .. } | MsgSource::User { .. } =>
+            MsgSource::Chan { .. } | MsgSource::User { .. } | MsgSource::Dcc { .. } =>
                 false,
         }
     }
@@ -141,6 +141,34 @@ impl<'a> Iterator for SplitIterator<'a> {
 
 ////////////////////////////////////////////////////////////////////////////////
 
+const BASE64_CHARS: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Standard base64 encoding (with padding), used for SASL payloads.
+pub fn base64_encode(bytes: &[u8]) -> String {
+    let mut ret = String::with_capacity((bytes.len() + 2) / 3 * 4);
+    for chunk in bytes.chunks(3) {
+        let b0 = chunk[0] as usize;
+        let b1 = chunk.get(1).cloned().unwrap_or(0) as usize;
+        let b2 = chunk.get(2).cloned().unwrap_or(0) as usize;
+        ret.push(BASE64_CHARS[b0 >> 2] as char);
+        ret.push(BASE64_CHARS[((b0 & 0b11) << 4) | (b1 >> 4)] as char);
+        if chunk.len() > 1 {
+            ret.push(BASE64_CHARS[((b1 & 0b1111) << 2) | (b2 >> 6)] as char);
+        } else {
+            ret.push('=');
+        }
+        if chunk.len() > 2 {
+            ret.push(BASE64_CHARS[b2 & 0b11_1111] as char);
+        } else {
+            ret.push('=');
+        }
+    }
+    ret
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
 #[cfg(test)]
 mod tests {
 
@@ -177,6 +205,22 @@ mod tests {
         assert_eq!(idxs, vec![2, 9, 19]);
     }
 
+    #[test]
+    fn test_base64_encode() {
+        assert_eq!(base64_encode(b""), "");
+        assert_eq!(base64_encode(b"f"), "Zg==");
+        assert_eq!(base64_encode(b"fo"), "Zm8=");
+        assert_eq!(base64_encode(b"foo"), "Zm9v");
+        assert_eq!(base64_encode(b"foob"), "Zm9vYg==");
+        assert_eq!(base64_encode(b"fooba"), "Zm9vYmE=");
+        assert_eq!(base64_encode(b"foobar"), "Zm9vYmFy");
+        assert_eq!(
+            base64_encode(b"tiny\0tiny\0hunter2"),
+            "dGlueQB0aW55AGh1bnRlcjI="
+        );
+        assert_eq!(base64_encode(&[0xff, 0xfe, 0xfd]), "//79");
+    }
+
     #[test]
     fn test_split_iterator_1() {
         let iter = split_iterator("yada yada yada", 5);
@@ -0,0 +1,179 @@
+//! Channel member information from WHO (352 RPL_WHOREPLY) and WHOX (354 RPL_WHOSPCRPL) replies.
+//! WHOX (advertised with the `WHOX` ISUPPORT token) also gives us accounts.
+
+use isupport::ISupport;
+use wire::Reply;
+
+/// Sent in WHOX queries to recognize replies to our queries.
+pub const WHOX_TOKEN: &str = "152";
+
+/// WHOX fields we request: token, channel, user, host, nick, flags, account, realname. The order
+/// of fields in replies is fixed, see `Reply::WhoxReply`.
+pub const WHOX_FIELDS: &str = "tcuhnfar";
+
+/// What we know about a channel member.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct Member {
+    /// Membership prefixes, e.g. `@+`. Empty if the member has none.
+    pub prefix: String,
+    /// `user@host`
+    pub user: Option<String>,
+    /// Services account. WHOX only.
+    pub account: Option<String>,
+    pub away: bool,
+    pub realname: Option<String>,
+}
+
+/// A member from a WHO or WHOX reply.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct WhoEntry {
+    pub chan: String,
+    pub nick: String,
+    pub member: Member,
+}
+
+/// Parse a WHO or WHOX reply. WHOX replies to queries other than ours are ignored.
+pub fn parse_who_reply(isupport: &ISupport, reply: &Reply) -> Option<WhoEntry> {
+    match *reply {
+        Reply::WhoReply {
+            ref chan,
+            ref user,
+            ref host,
+            ref nick,
+            ref flags,
+            ref realname,
+            ..
+        } => {
+            // realname starts with the hop count
+            let realname = match realname.find(' ') {
+                Some(idx) =>
+                    &realname[idx + 1..],
+                None =>
+                    "",
+            };
+            let user = format!("{}@{}", user, host);
+            Some(who_entry(isupport, chan, nick, flags, user, None, realname))
+        }
+        Reply::WhoxReply {
+            ref token,
+            ref chan,
+            ref user,
+            ref host,
+            ref nick,
+            ref flags,
+            ref account,
+            ref realname,
+            ..
+        } if token == WHOX_TOKEN => {
+            // "0" means not logged in
+            let account = if account == "0" {
+                None
+            } else {
+                Some(account.as_str())
+            };
+            let user = format!("{}@{}", user, host);
+            Some(who_entry(isupport, chan, nick, flags, user, account, realname))
+        }
+        _ =>
+            None,
+    }
+}
+
+fn who_entry(
+    isupport: &ISupport,
+    chan: &str,
+    nick: &str,
+    flags: &str,
+    user: String,
+    account: Option<&str>,
+    realname: &str,
+) -> WhoEntry {
+    // flags: `H` (here) or `G` (gone), optional `*` (IRC operator), membership prefixes
+    let prefix = flags
+        .chars()
+        .filter(|&c| isupport.prefix.iter().any(|&(_, prefix)| prefix == c))
+        .collect();
+    WhoEntry {
+        chan: chan.to_owned(),
+        nick: nick.to_owned(),
+        member: Member {
+            prefix,
+            user: Some(user),
+            account: account.map(str::to_owned),
+            away: flags.starts_with('G'),
+            realname: if realname.is_empty() {
+                None
+            } else {
+                Some(realname.to_owned())
+            },
+        },
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wire::reply;
+
+    #[test]
+    fn test_parse_who_reply() {
+        let isupport = ISupport::default();
+        assert_eq!(
+            parse_who_reply(
+                &isupport,
+                &reply(
+                    352,
+                    &["me", "#tiny", "~osa1", "host.com", "irc.server.net", "osa1", "G*@",
+                      "0 Ömer Sinan"]
+                )
+            ),
+            Some(WhoEntry {
+                chan: "#tiny".to_owned(),
+                nick: "osa1".to_owned(),
+                member: Member {
+                    prefix: "@".to_owned(),
+                    user: Some("~osa1@host.com".to_owned()),
+                    account: None,
+                    away: true,
+                    realname: Some("Ömer Sinan".to_owned()),
+                },
+            })
+        );
+
+        assert_eq!(
+            parse_who_reply(
+                &isupport,
+                &reply(
+                    354,
+                    &["me", WHOX_TOKEN, "#tiny", "~osa1", "host.com", "osa1", "H+", "osa1_acc",
+                      "Ömer"]
+                )
+            ).map(|entry| entry.member),
+            Some(Member {
+                prefix: "+".to_owned(),
+                user: Some("~osa1@host.com".to_owned()),
+                account: Some("osa1_acc".to_owned()),
+                away: false,
+                realname: Some("Ömer".to_owned()),
+            })
+        );
+
+        // not logged in
+        let entry = parse_who_reply(
+            &isupport,
+            &reply(354, &["me", WHOX_TOKEN, "#tiny", "u", "h", "osa1", "H", "0", "Ömer"]),
+        ).unwrap();
+        assert_eq!(entry.member.account, None);
+
+        // not our query
+        assert_eq!(
+            parse_who_reply(
+                &isupport,
+                &reply(354, &["me", "999", "#tiny", "u", "h", "osa1", "H", "0", "Ömer"])
+            ),
+            None
+        );
+    }
+}
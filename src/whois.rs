@@ -0,0 +1,278 @@
+//! Collects WHOIS replies (311, 312, 313, 317, 319, 330, 671, and 301 for away users) until 318
+//! RPL_ENDOFWHOIS, to show them as a single block.
+
+use time;
+
+use isupport::CaseMapping;
+use wire::Reply;
+
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct Whois {
+    pub nick: String,
+    /// `user@host`
+    pub host: Option<String>,
+    pub realname: Option<String>,
+    /// Server name and info
+    pub server: Option<(String, String)>,
+    pub operator: bool,
+    pub idle_secs: Option<u64>,
+    /// Seconds since the epoch
+    pub signon: Option<i64>,
+    /// Channels with membership prefixes. 319 can be sent multiple times for users in lots of
+    /// channels.
+    pub chans: Vec<String>,
+    pub account: Option<String>,
+    pub secure: bool,
+    pub away: Option<String>,
+}
+
+impl Whois {
+    fn new(nick: &str) -> Whois {
+        Whois {
+            nick: nick.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    /// Lines to show in the UI.
+    pub fn format(&self) -> Vec<String> {
+        let mut lines = vec![];
+        match (&self.host, &self.realname) {
+            (&Some(ref host), &Some(ref realname)) =>
+                lines.push(format!("{} ({}): {}", self.nick, host, realname)),
+            _ =>
+                lines.push(self.nick.clone()),
+        }
+        if let Some((ref server, ref info)) = self.server {
+            lines.push(format!("  server: {} ({})", server, info));
+        }
+        if let Some(ref account) = self.account {
+            lines.push(format!("  account: {}", account));
+        }
+        if !self.chans.is_empty() {
+            lines.push(format!("  channels: {}", self.chans.join(" ")));
+        }
+        if let Some(idle_secs) = self.idle_secs {
+            lines.push(format!("  idle: {}", humanize_secs(idle_secs)));
+        }
+        if let Some(signon) = self.signon {
+            let tm = time::at(time::Timespec::new(signon, 0));
+            if let Ok(signon) = time::strftime("%Y-%m-%d %H:%M:%S", &tm) {
+                lines.push(format!("  signon: {}", signon));
+            }
+        }
+        if let Some(ref away) = self.away {
+            lines.push(format!("  away: {}", away));
+        }
+        if self.operator {
+            lines.push("  is an IRC operator".to_owned());
+        }
+        if self.secure {
+            lines.push("  is using a secure connection".to_owned());
+        }
+        lines
+    }
+}
+
+/// E.g. `1 day 2 hours`, `3 minutes 20 seconds`. Only the two most significant units are shown.
+pub fn humanize_secs(secs: u64) -> String {
+    let units = [
+        (secs / 86_400, "day"),
+        (secs / 3600 % 24, "hour"),
+        (secs / 60 % 60, "minute"),
+        (secs % 60, "second"),
+    ];
+    let first = units
+        .iter()
+        .position(|&(n, _)| n != 0)
+        .unwrap_or(units.len() - 1);
+    units[first..]
+        .iter()
+        .take(2)
+        .filter(|&&(n, _)| n != 0 || first == units.len() - 1)
+        .map(|&(n, unit)| format!("{} {}{}", n, unit, if n == 1 { "" } else { "s" }))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// WHOIS replies received so far, per nick.
+#[derive(Default)]
+pub struct WhoisCollector {
+    pending: Vec<Whois>,
+}
+
+impl WhoisCollector {
+    pub fn new() -> WhoisCollector {
+        WhoisCollector::default()
+    }
+
+    pub fn clear(&mut self) {
+        self.pending.clear();
+    }
+
+    /// Are we collecting WHOIS replies for the nick?
+    pub fn is_pending(&self, casemapping: CaseMapping, nick: &str) -> bool {
+        self.pending
+            .iter()
+            .any(|whois| casemapping.eq(&whois.nick, nick))
+    }
+
+    /// Add a reply. Returns the collected information on RPL_ENDOFWHOIS. `None` is returned for
+    /// nicks that don't exist (the server only sends 401 ERR_NOSUCHNICK and 318).
+    pub fn add(&mut self, casemapping: CaseMapping, reply: &Reply) -> Option<Whois> {
+        match *reply {
+            Reply::WhoisUser {
+                ref nick,
+                ref user,
+                ref host,
+                ref realname,
+                ..
+            } => {
+                let whois = self.get(casemapping, nick);
+                whois.host = Some(format!("{}@{}", user, host));
+                whois.realname = Some(realname.clone());
+            }
+            Reply::WhoisServer {
+                ref nick,
+                ref server,
+                ref info,
+                ..
+            } =>
+                self.get(casemapping, nick).server = Some((server.clone(), info.clone())),
+            Reply::WhoisOperator { ref nick, .. } =>
+                self.get(casemapping, nick).operator = true,
+            Reply::WhoisIdle {
+                ref nick,
+                ref secs,
+                ref signon,
+                ..
+            } => {
+                let whois = self.get(casemapping, nick);
+                whois.idle_secs = secs.parse().ok();
+                whois.signon = signon.parse().ok();
+            }
+            Reply::WhoisChannels {
+                ref nick,
+                ref chans,
+                ..
+            } =>
+                self.get(casemapping, nick)
+                    .chans
+                    .extend(chans.split_whitespace().map(str::to_owned)),
+            Reply::WhoisAccount {
+                ref nick,
+                ref account,
+                ..
+            } =>
+                self.get(casemapping, nick).account = Some(account.clone()),
+            Reply::WhoisSecure { ref nick, .. } =>
+                self.get(casemapping, nick).secure = true,
+            // RPL_AWAY is also sent when we message an away user, only collect it during a WHOIS
+            Reply::Away {
+                ref nick, ref msg, ..
+            } if self.is_pending(casemapping, nick) =>
+                self.get(casemapping, nick).away = Some(msg.clone()),
+            Reply::EndOfWhois { ref nick, .. } => {
+                let idx = self.pending
+                    .iter()
+                    .position(|whois| casemapping.eq(&whois.nick, nick))?;
+                let whois = self.pending.remove(idx);
+                if whois.host.is_some() {
+                    return Some(whois);
+                }
+            }
+            _ =>
+                {}
+        }
+        None
+    }
+
+    fn get(&mut self, casemapping: CaseMapping, nick: &str) -> &mut Whois {
+        let idx = match self.pending
+            .iter()
+            .position(|whois| casemapping.eq(&whois.nick, nick))
+        {
+            Some(idx) =>
+                idx,
+            None => {
+                self.pending.push(Whois::new(nick));
+                self.pending.len() - 1
+            }
+        };
+        &mut self.pending[idx]
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wire::reply;
+
+    #[test]
+    fn test_humanize_secs() {
+        assert_eq!(humanize_secs(0), "0 seconds");
+        assert_eq!(humanize_secs(1), "1 second");
+        assert_eq!(humanize_secs(200), "3 minutes 20 seconds");
+        assert_eq!(humanize_secs(3600), "1 hour");
+        assert_eq!(humanize_secs(90_061), "1 day 1 hour");
+        assert_eq!(humanize_secs(86_460), "1 day");
+    }
+
+    #[test]
+    fn test_whois_collector() {
+        let cm = CaseMapping::Rfc1459;
+        let mut collector = WhoisCollector::new();
+        let replies = vec![
+            reply(311, &["me", "osa1", "~osa1", "host.com", "*", "Ömer"]),
+            reply(312, &["me", "osa1", "irc.server.net", "Server info"]),
+            reply(319, &["me", "OSA1", "@#tiny #rust"]),
+            reply(319, &["me", "osa1", "+#haskell"]),
+            reply(301, &["me", "osa1", "gone"]),
+            reply(330, &["me", "osa1", "osa1_acc", "is logged in as"]),
+            reply(671, &["me", "osa1", "is using a secure connection"]),
+            reply(317, &["me", "osa1", "200", "1500000000", "seconds idle, signon time"]),
+        ];
+        for reply in &replies {
+            assert_eq!(collector.add(cm, reply), None);
+        }
+        assert!(collector.is_pending(cm, "Osa1"));
+        let whois = collector
+            .add(cm, &reply(318, &["me", "osa1", "End of /WHOIS list."]))
+            .unwrap();
+        assert!(!collector.is_pending(cm, "osa1"));
+        assert_eq!(whois.host, Some("~osa1@host.com".to_owned()));
+        assert_eq!(whois.realname, Some("Ömer".to_owned()));
+        assert_eq!(
+            whois.server,
+            Some(("irc.server.net".to_owned(), "Server info".to_owned()))
+        );
+        assert_eq!(whois.chans, vec!["@#tiny", "#rust", "+#haskell"]);
+        assert_eq!(whois.away, Some("gone".to_owned()));
+        assert_eq!(whois.account, Some("osa1_acc".to_owned()));
+        assert!(whois.secure);
+        assert!(!whois.operator);
+        assert_eq!(whois.idle_secs, Some(200));
+        assert_eq!(whois.signon, Some(1500000000));
+
+        let lines = whois.format();
+        assert_eq!(lines[0], "osa1 (~osa1@host.com): Ömer");
+        assert!(lines.contains(&"  idle: 3 minutes 20 seconds".to_owned()));
+        assert!(lines.contains(&"  channels: @#tiny #rust +#haskell".to_owned()));
+    }
+
+    #[test]
+    fn test_whois_no_such_nick() {
+        let cm = CaseMapping::Rfc1459;
+        let mut collector = WhoisCollector::new();
+        assert_eq!(collector.add(cm, &reply(301, &["me", "osa1", "gone"])), None);
+        assert!(!collector.is_pending(cm, "osa1"));
+        assert_eq!(
+            collector.add(cm, &reply(318, &["me", "osa1", "End of /WHOIS list."])),
+            None
+        );
+    }
+}
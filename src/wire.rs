@@ -1,55 +1,192 @@
 //! IRC wire protocol message parsers and generators. Incomplete; new messages are added as needed.
 
+use std::collections::BTreeMap;
 use std::io::Write;
 use std::str;
 use std;
 
+use ctcp;
 use logger::LogFile;
+use time::Tm;
+use time;
+use utils;
+use who;
 
 pub fn pass<W: Write>(sink: &mut W, pass: &str) -> std::io::Result<()> {
-    write!(sink, "PASS {}\r\n", pass)
+    write_cmd(sink, other("PASS", &[pass]))
 }
 
 pub fn user<W: Write>(sink: &mut W, hostname: &str, realname: &str) -> std::io::Result<()> {
-    write!(sink, "USER {} 8 * :{}\r\n", hostname, realname)
+    write_cmd(sink, other("USER", &[hostname, "8", "*", realname]))
 }
 
 pub fn nick<W: Write>(sink: &mut W, arg: &str) -> std::io::Result<()> {
-    write!(sink, "NICK {}\r\n", arg)
+    write_cmd(sink, Cmd::NICK { nick: arg.to_owned() })
 }
 
 pub fn ping<W: Write>(sink: &mut W, arg: &str) -> std::io::Result<()> {
-    write!(sink, "PING {}\r\n", arg)
+    write_cmd(sink, Cmd::PING { server: arg.to_owned() })
 }
 
 pub fn pong<W: Write>(sink: &mut W, arg: &str) -> std::io::Result<()> {
-    write!(sink, "PONG {}\r\n", arg)
+    write_cmd(sink, Cmd::PONG { server: arg.to_owned() })
 }
 
-pub fn join<W: Write>(sink: &mut W, chans: &[&str]) -> std::io::Result<()> {
-    write!(sink, "JOIN {}\r\n", chans.join(","))
+/// Channels with keys should come first in `chans`, `keys[i]` is the key of `chans[i]`.
+pub fn join<W: Write>(sink: &mut W, chans: &[&str], keys: &[&str]) -> std::io::Result<()> {
+    write_cmd(
+        sink,
+        Cmd::JOIN {
+            chans: chans.iter().map(|s| (*s).to_owned()).collect(),
+            keys: keys.iter().map(|s| (*s).to_owned()).collect(),
+        },
+    )
 }
 
 pub fn part<W: Write>(sink: &mut W, channel: &str) -> std::io::Result<()> {
-    write!(sink, "PART {}\r\n", channel)
+    write_cmd(
+        sink,
+        Cmd::PART {
+            chans: vec![channel.to_owned()],
+            msg: None,
+        },
+    )
 }
 
 pub fn privmsg<W: Write>(sink: &mut W, msgtarget: &str, msg: &str) -> std::io::Result<()> {
     assert!(msgtarget.len() + msg.len() + 12 <= 512);
-    write!(sink, "PRIVMSG {} :{}\r\n", msgtarget, msg)
+    write_cmd(
+        sink,
+        Cmd::PRIVMSG {
+            targets: msg_targets(msgtarget),
+            msg: msg.to_owned(),
+            is_notice: false,
+        },
+    )
 }
 
 pub fn ctcp_action<W: Write>(sink: &mut W, msgtarget: &str, msg: &str) -> std::io::Result<()> {
     assert!(msgtarget.len() + msg.len() + 21 <= 512);
-    write!(sink, "PRIVMSG {} :\x01ACTION {}\x01\r\n", msgtarget, msg)
+    write_cmd(
+        sink,
+        Cmd::PRIVMSG {
+            targets: msg_targets(msgtarget),
+            msg: format!("\x01ACTION {}\x01", msg),
+            is_notice: false,
+        },
+    )
+}
+
+pub fn ctcp_request<W: Write>(
+    sink: &mut W,
+    msgtarget: &str,
+    cmd: &str,
+    args: &str,
+) -> std::io::Result<()> {
+    write_cmd(
+        sink,
+        Cmd::PRIVMSG {
+            targets: msg_targets(msgtarget),
+            msg: ctcp::format_ctcp(cmd, args),
+            is_notice: false,
+        },
+    )
+}
+
+pub fn ctcp_reply<W: Write>(
+    sink: &mut W,
+    msgtarget: &str,
+    cmd: &str,
+    args: &str,
+) -> std::io::Result<()> {
+    write_cmd(
+        sink,
+        Cmd::PRIVMSG {
+            targets: msg_targets(msgtarget),
+            msg: ctcp::format_ctcp(cmd, args),
+            is_notice: true,
+        },
+    )
+}
+
+pub fn cap_ls<W: Write>(sink: &mut W) -> std::io::Result<()> {
+    write_cmd(sink, other("CAP", &["LS", "302"]))
+}
+
+pub fn cap_req<W: Write>(sink: &mut W, caps: &[&str]) -> std::io::Result<()> {
+    write_cmd(sink, other("CAP", &["REQ", &caps.join(" ")]))
+}
+
+pub fn cap_end<W: Write>(sink: &mut W) -> std::io::Result<()> {
+    write_cmd(sink, other("CAP", &["END"]))
+}
+
+pub fn authenticate<W: Write>(sink: &mut W, param: &str) -> std::io::Result<()> {
+    write_cmd(sink, Cmd::AUTHENTICATE { param: param.to_owned() })
+}
+
+/// Send a SASL response. The payload is base64 encoded and split into 400-byte `AUTHENTICATE`
+/// messages. When the last chunk is exactly 400 bytes (or the payload is empty) an extra
+/// `AUTHENTICATE +` marks the end of the response.
+pub fn authenticate_payload<W: Write>(sink: &mut W, payload: &[u8]) -> std::io::Result<()> {
+    let encoded = utils::base64_encode(payload);
+    for chunk in encoded.as_bytes().chunks(400) {
+        // base64 output is ASCII
+        authenticate(sink, unsafe { str::from_utf8_unchecked(chunk) })?;
+    }
+    if encoded.len() % 400 == 0 {
+        authenticate(sink, "+")?;
+    }
+    Ok(())
 }
 
 pub fn away<W: Write>(sink: &mut W, msg: Option<&str>) -> std::io::Result<()> {
     match msg {
         None =>
-            write!(sink, "AWAY\r\n"),
+            write_cmd(sink, other("AWAY", &[])),
         Some(msg) =>
-            write!(sink, "AWAY :{}\r\n", msg),
+            write_cmd(sink, other("AWAY", &[msg])),
+    }
+}
+
+pub fn whois<W: Write>(sink: &mut W, nick: &str) -> std::io::Result<()> {
+    write_cmd(sink, other("WHOIS", &[nick]))
+}
+
+/// Add nicks to the MONITOR list. We get 730 RPL_MONONLINE and 731 RPL_MONOFFLINE for them.
+pub fn monitor_add<W: Write>(sink: &mut W, nicks: &[&str]) -> std::io::Result<()> {
+    write_cmd(sink, other("MONITOR", &["+", &nicks.join(",")]))
+}
+
+/// Check which nicks are online, for servers without MONITOR. Answered with 303 RPL_ISON.
+pub fn ison<W: Write>(sink: &mut W, nicks: &[&str]) -> std::io::Result<()> {
+    write_cmd(sink, other("ISON", nicks))
+}
+
+/// Request members of a channel. With `whox` a WHOX query with `who::WHOX_FIELDS` is sent.
+pub fn who<W: Write>(sink: &mut W, chan: &str, whox: bool) -> std::io::Result<()> {
+    if whox {
+        let query = format!("%{},{}", who::WHOX_FIELDS, who::WHOX_TOKEN);
+        write_cmd(sink, other("WHO", &[chan, &query]))
+    } else {
+        write_cmd(sink, other("WHO", &[chan]))
+    }
+}
+
+/// Send a command without tags or prefix, as clients do.
+fn write_cmd<W: Write>(sink: &mut W, cmd: Cmd) -> std::io::Result<()> {
+    Msg {
+        tags: Tags::new(),
+        pfx: None,
+        cmd,
+    }.write(sink)
+}
+
+/// A command we don't have a `Cmd` variant for.
+fn other(cmd: &str, params: &[&str]) -> Cmd {
+    Cmd::Other {
+        cmd: cmd.to_owned(),
+        params: params.iter().map(|s| (*s).to_owned()).collect(),
     }
 }
 
@@ -78,38 +215,42 @@ pub enum Pfx {
     },
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub enum MsgTarget {
     Chan(String),
     User(String),
 }
 
-#[derive(Debug, PartialEq, Eq)]
+/// IRCv3 message tags. A tag without a value (`@foo`) maps to an empty string, as the spec says
+/// missing and empty values are equivalent.
+pub type Tags = BTreeMap<String, String>;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub struct Msg {
+    pub tags: Tags,
     pub pfx: Option<Pfx>,
     pub cmd: Cmd,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub enum Cmd {
     /// A PRIVMSG or NOTICE. Check `is_notice` field.
     PRIVMSG {
-        // TODO: In theory this should be a list of targets, but in practice I've never
-        // encountered that case.
-        target: MsgTarget,
+        /// Comma-separated targets in the message. Usually just one.
+        targets: Vec<MsgTarget>,
         msg: String,
         is_notice: bool,
     },
 
     JOIN {
-        // TODO: Same as above, this should be a list ...
-        chan: String, // TODO: key field might be useful when joining restricted channels. In practice I've never
-                      // needed it.
+        chans: Vec<String>,
+        /// Keys of the channels, in the same order as `chans`. Channels with keys come first in a
+        /// JOIN message so this can be shorter than `chans`.
+        keys: Vec<String>,
     },
 
     PART {
-        // TODO: List of channels
-        chan: String,
+        chans: Vec<String>,
         msg: Option<String>,
     },
 
@@ -117,6 +258,20 @@ pub enum Cmd {
         msg: Option<String>,
     },
 
+    KICK {
+        chan: String,
+        /// The user being kicked.
+        nick: String,
+        msg: Option<String>,
+    },
+
+    INVITE {
+        /// The user being invited. Usually us, but with `invite-notify` capability we also get
+        /// invites to other users in channels we're in.
+        nick: String,
+        chan: String,
+    },
+
     NICK {
         nick: String,
     },
@@ -138,18 +293,355 @@ pub enum Cmd {
         topic: String,
     },
 
+    /// A channel or user MODE. `modes` is the mode string (e.g. `+ov-k`), `params` are the mode
+    /// parameters. See `mode::parse_chan_modes` for matching the two.
+    MODE {
+        target: String,
+        modes: String,
+        params: Vec<String>,
+    },
+
+    /// IRCv3 capability negotiation.
+    CAP {
+        /// Our nick, or `*` before registration.
+        client: String,
+        /// LS, ACK, NAK, NEW, DEL, LIST ...
+        subcmd: String,
+        /// E.g. `["sasl=PLAIN,EXTERNAL", "server-time"]`.
+        caps: Vec<String>,
+        /// Set when this is not the last line of a multi-line LS or LIST reply.
+        more: bool,
+    },
+
+    /// SASL authentication. `param` is `+` or a (possibly partial) base64 encoded challenge.
+    AUTHENTICATE {
+        param: String,
+    },
+
     /// An IRC message other than the ones listed above.
     Other {
         cmd: String,
         params: Vec<String>,
     },
 
-    /// Numeric replies are kept generic as there are just too many replies and we probably only
-    /// need to handle a small subset of them.
-    Reply {
-        num: u16,
-        params: Vec<String>,
-    },
+    /// A numeric reply. See `Reply`.
+    Reply(Reply),
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Numeric replies
+
+/// Generates the `Reply` type from a table of `<num> => <Variant> { <params> }`. Every variant
+/// gets a `client` field for the first parameter (our nick, or `*` before registration), and
+/// `Reply::parse` only picks a variant when the number of parameters matches exactly. Anything
+/// else becomes `Reply::Other`, so no information is lost and `params()` always gives back the
+/// parameters the reply was parsed from.
+macro_rules! replies {
+    ( $( $num:tt => $name:ident { $( $field:ident ),* } ),* $(,)* ) => {
+        #[derive(Debug, PartialEq, Eq, Clone)]
+        pub enum Reply {
+            $( $name { client: String, $( $field: String ),* }, )*
+
+            /// 005 RPL_ISUPPORT. RFC 2812 defines 005 as RPL_BOUNCE but no server uses it that
+            /// way anymore.
+            ISupport {
+                client: String,
+                /// E.g. `["CHANTYPES=#", "NETWORK=freenode"]`.
+                tokens: Vec<String>,
+                msg: String,
+            },
+
+            /// 324 RPL_CHANNELMODEIS: `<channel> <mode> *<mode params>`
+            ChannelModeIs {
+                client: String,
+                chan: String,
+                modes: String,
+                params: Vec<String>,
+            },
+
+            /// 332 RPL_TOPIC: `<channel> :<topic>`. RFC 2812 has no `client` parameter, but most
+            /// servers (e.g. freenode) send it.
+            Topic {
+                client: Option<String>,
+                chan: String,
+                topic: String,
+            },
+
+            /// A reply that's not in the table, or one with unexpected number of parameters.
+            Other {
+                num: u16,
+                params: Vec<String>,
+            },
+        }
+
+        impl Reply {
+            pub fn parse(num: u16, params: Vec<String>) -> Reply {
+                match num {
+                    $(
+                        $num if params.len() == 1 + [$( stringify!($field) ),*].len() => {
+                            let mut params = params.into_iter();
+                            Reply::$name {
+                                client: params.next().unwrap(),
+                                $( $field: params.next().unwrap(), )*
+                            }
+                        }
+                    )*
+                    005 if params.len() >= 2 => {
+                        let mut params = params;
+                        let msg = params.pop().unwrap();
+                        let client = params.remove(0);
+                        Reply::ISupport { client, tokens: params, msg }
+                    }
+                    324 if params.len() >= 3 => {
+                        let mut params = params.into_iter();
+                        Reply::ChannelModeIs {
+                            client: params.next().unwrap(),
+                            chan: params.next().unwrap(),
+                            modes: params.next().unwrap(),
+                            params: params.collect(),
+                        }
+                    }
+                    332 if params.len() == 2 || params.len() == 3 => {
+                        let mut params = params;
+                        let topic = params.pop().unwrap();
+                        let chan = params.pop().unwrap();
+                        Reply::Topic { client: params.pop(), chan, topic }
+                    }
+                    _ =>
+                        Reply::Other { num, params },
+                }
+            }
+
+            pub fn num(&self) -> u16 {
+                match *self {
+                    $( Reply::$name { .. } => $num, )*
+                    Reply::ISupport { .. } =>
+                        005,
+                    Reply::ChannelModeIs { .. } =>
+                        324,
+                    Reply::Topic { .. } =>
+                        332,
+                    Reply::Other { num, .. } =>
+                        num,
+                }
+            }
+
+            /// Parameters of the reply, in the order they appear in the message.
+            pub fn params(&self) -> Vec<&str> {
+                match *self {
+                    $(
+                        Reply::$name { ref client, $( ref $field ),* } =>
+                            vec![client.as_str(), $( $field.as_str() ),*],
+                    )*
+                    Reply::ISupport { ref client, ref tokens, ref msg } => {
+                        let mut ret = vec![client.as_str()];
+                        ret.extend(tokens.iter().map(String::as_str));
+                        ret.push(msg.as_str());
+                        ret
+                    }
+                    Reply::ChannelModeIs { ref client, ref chan, ref modes, ref params } => {
+                        let mut ret = vec![client.as_str(), chan.as_str(), modes.as_str()];
+                        ret.extend(params.iter().map(String::as_str));
+                        ret
+                    }
+                    Reply::Topic { ref client, ref chan, ref topic } => {
+                        let mut ret: Vec<&str> = client.iter().map(String::as_str).collect();
+                        ret.push(chan.as_str());
+                        ret.push(topic.as_str());
+                        ret
+                    }
+                    Reply::Other { ref params, .. } =>
+                        params.iter().map(String::as_str).collect(),
+                }
+            }
+        }
+    }
+}
+
+// Formats are from RFC 2812 section 5 unless stated otherwise. `msg` is the human-readable text
+// at the end of a reply.
+replies! {
+    001 => Welcome { msg },
+    002 => YourHost { msg },
+    003 => Created { msg },
+    // RFC 2812 lists four parameters but servers also send channel modes that take parameters.
+    004 => MyInfo { server, version, user_modes, chan_modes, param_chan_modes },
+
+    // `kind` of trace and stats replies is the literal first word (`Link`, `Try.`, `Serv` ...)
+    200 => TraceLink { kind, version, destination, next_server, protocol_version, uptime,
+                       backstream_sendq, upstream_sendq },
+    201 => TraceConnecting { kind, class, server },
+    202 => TraceHandshake { kind, class, server },
+    203 => TraceUnknown { kind, class, addr },
+    204 => TraceOperator { kind, class, nick },
+    205 => TraceUser { kind, class, nick },
+    206 => TraceServer { kind, class, servers, clients, server, mask, protocol_version },
+    207 => TraceService { kind, class, name, service_type, active_type },
+    208 => TraceNewType { new_type, zero, client_name },
+    209 => TraceClass { kind, class, count },
+    211 => StatsLinkInfo { link_name, sendq, sent_msgs, sent_kbytes, recvd_msgs, recvd_kbytes,
+                           time_open },
+    212 => StatsCommands { command, count, byte_count, remote_count },
+    219 => EndOfStats { stats_letter, msg },
+    221 => UModeIs { modes },
+    234 => ServList { name, server, mask, service_type, hopcount, info },
+    235 => ServListEnd { mask, service_type, msg },
+    242 => StatsUptime { msg },
+    243 => StatsOLine { kind, host_mask, star, name },
+    251 => LUserClient { msg },
+    252 => LUserOp { count, msg },
+    253 => LUserUnknown { count, msg },
+    254 => LUserChannels { count, msg },
+    255 => LUserMe { msg },
+    256 => AdminMe { server, msg },
+    257 => AdminLoc1 { msg },
+    258 => AdminLoc2 { msg },
+    259 => AdminEmail { msg },
+    261 => TraceLog { kind, logfile, debug_level },
+    262 => TraceEnd { server, version, msg },
+    263 => TryAgain { command, msg },
+
+    301 => Away { nick, msg },
+    // `replies` is a space-separated list of `<nick>['*']'='<'+'|'-'><hostname>`.
+    302 => UserHost { replies },
+    // `nicks` is space-separated.
+    303 => IsOn { nicks },
+    305 => UnAway { msg },
+    306 => NowAway { msg },
+    311 => WhoisUser { nick, user, host, star, realname },
+    312 => WhoisServer { nick, server, info },
+    313 => WhoisOperator { nick, msg },
+    314 => WhowasUser { nick, user, host, star, realname },
+    315 => EndOfWho { name, msg },
+    // RFC 2812 has no `signon` (seconds since the epoch), but all modern servers send it.
+    317 => WhoisIdle { nick, secs, signon, msg },
+    318 => EndOfWhois { nick, msg },
+    // `chans` is space-separated, each channel may have a membership prefix.
+    319 => WhoisChannels { nick, chans },
+    322 => List { chan, visible, topic },
+    323 => ListEnd { msg },
+    325 => UniqOpIs { chan, nick },
+    // Not in RFC 2812. Sent by servers with services accounts (e.g. freenode, ircu).
+    330 => WhoisAccount { nick, account, msg },
+    331 => NoTopic { chan, msg },
+    // RFC 2812 has `<channel> <nick>`, servers send the nick first.
+    341 => Inviting { nick, chan },
+    342 => Summoning { user, msg },
+    346 => InviteList { chan, mask },
+    347 => EndOfInviteList { chan, msg },
+    348 => ExceptList { chan, mask },
+    349 => EndOfExceptList { chan, msg },
+    351 => Version { version, server, comments },
+    // `flags` is `H` (here) or `G` (gone), followed by an optional `*` (IRC operator) and
+    // membership prefix. `realname` starts with the hop count.
+    352 => WhoReply { chan, user, host, server, nick, flags, realname },
+    // `chan_type` is `=` (public), `*` (private) or `@` (secret). `nicks` is space-separated,
+    // each nick may have a membership prefix.
+    353 => NamReply { chan_type, chan, nicks },
+    // Not in RFC 2812. WHOX reply, fields depend on the query. These are the fields requested by
+    // `who::WHOX_FIELDS`, see https://ircv3.net/specs/extensions/whox.
+    354 => WhoxReply { token, chan, user, host, nick, flags, account, realname },
+    364 => Links { mask, server, info },
+    365 => EndOfLinks { mask, msg },
+    366 => EndOfNames { chan, msg },
+    367 => BanList { chan, mask },
+    368 => EndOfBanList { chan, msg },
+    369 => EndOfWhowas { nick, msg },
+    371 => Info { msg },
+    372 => Motd { msg },
+    374 => EndOfInfo { msg },
+    375 => MotdStart { msg },
+    376 => EndOfMotd { msg },
+    381 => YoureOper { msg },
+    382 => Rehashing { config_file, msg },
+    383 => YoureService { msg },
+    391 => Time { server, time },
+    392 => UsersStart { msg },
+    393 => Users { msg },
+    394 => EndOfUsers { msg },
+    395 => NoUsers { msg },
+    // Not in RFC 2812. Sent when the server (or services) set a hidden host.
+    396 => HostHidden { host, msg },
+
+    401 => ErrNoSuchNick { nick, msg },
+    402 => ErrNoSuchServer { server, msg },
+    403 => ErrNoSuchChannel { chan, msg },
+    404 => ErrCannotSendToChan { chan, msg },
+    405 => ErrTooManyChannels { chan, msg },
+    406 => ErrWasNoSuchNick { nick, msg },
+    407 => ErrTooManyTargets { target, msg },
+    408 => ErrNoSuchService { service, msg },
+    409 => ErrNoOrigin { msg },
+    411 => ErrNoRecipient { msg },
+    412 => ErrNoTextToSend { msg },
+    413 => ErrNoTopLevel { mask, msg },
+    414 => ErrWildTopLevel { mask, msg },
+    415 => ErrBadMask { mask, msg },
+    421 => ErrUnknownCommand { command, msg },
+    422 => ErrNoMotd { msg },
+    423 => ErrNoAdminInfo { server, msg },
+    424 => ErrFileError { msg },
+    431 => ErrNoNicknameGiven { msg },
+    432 => ErrErroneusNickname { nick, msg },
+    433 => ErrNicknameInUse { nick, msg },
+    // Not in RFC 2812. Sent by freenode when we can't change nick because we're banned in a
+    // channel (see #29). `client` is our current nick, `nick` is the one we wanted.
+    435 => ErrBanOnChan { nick, chan, msg },
+    436 => ErrNickCollision { nick, msg },
+    437 => ErrUnavailResource { target, msg },
+    441 => ErrUserNotInChannel { nick, chan, msg },
+    442 => ErrNotOnChannel { chan, msg },
+    443 => ErrUserOnChannel { user, chan, msg },
+    444 => ErrNoLogin { user, msg },
+    445 => ErrSummonDisabled { msg },
+    446 => ErrUsersDisabled { msg },
+    451 => ErrNotRegistered { msg },
+    461 => ErrNeedMoreParams { command, msg },
+    462 => ErrAlreadyRegistred { msg },
+    463 => ErrNoPermForHost { msg },
+    464 => ErrPasswdMismatch { msg },
+    465 => ErrYoureBannedCreep { msg },
+    466 => ErrYouWillBeBanned { msg },
+    467 => ErrKeySet { chan, msg },
+    471 => ErrChannelIsFull { chan, msg },
+    472 => ErrUnknownMode { mode_char, msg },
+    473 => ErrInviteOnlyChan { chan, msg },
+    474 => ErrBannedFromChan { chan, msg },
+    475 => ErrBadChannelKey { chan, msg },
+    476 => ErrBadChanMask { chan, msg },
+    477 => ErrNoChanModes { chan, msg },
+    478 => ErrBanListFull { chan, mode_char, msg },
+    481 => ErrNoPrivileges { msg },
+    482 => ErrChanOPrivsNeeded { chan, msg },
+    483 => ErrCantKillServer { msg },
+    484 => ErrRestricted { msg },
+    485 => ErrUniqOpPrivsNeeded { msg },
+    491 => ErrNoOperHost { msg },
+    501 => ErrUModeUnknownFlag { msg },
+    502 => ErrUsersDontMatch { msg },
+
+    // Not in RFC 2812. Sent when the user is connected via TLS.
+    671 => WhoisSecure { nick, msg },
+
+    // MONITOR, from https://ircv3.net/specs/extensions/monitor. `targets` is a comma-separated
+    // list of `nick!user@host` (730) or nicks.
+    730 => MonOnline { targets },
+    731 => MonOffline { targets },
+    732 => MonList { targets },
+    733 => EndOfMonList { msg },
+    734 => ErrMonListFull { limit, targets, msg },
+
+    // SASL, from https://ircv3.net/specs/extensions/sasl-3.1.html
+    900 => LoggedIn { mask, account, msg },
+    901 => LoggedOut { mask, msg },
+    902 => ErrNickLocked { msg },
+    903 => SaslSuccess { msg },
+    904 => ErrSaslFail { msg },
+    905 => ErrSaslTooLong { msg },
+    906 => ErrSaslAborted { msg },
+    907 => ErrSaslAlready { msg },
+    // `mechs` is a comma-separated list of mechanisms.
+    908 => SaslMechs { mechs, msg },
 }
 
 /// An intermediate type used during parsing.
@@ -161,6 +653,46 @@ enum MsgType<'a> {
 static CRLF: [u8; 2] = [b'\r', b'\n'];
 
 impl Msg {
+    /// Value of a tag, if the message has it. Tags without values return `Some("")`.
+    pub fn get_tag(&self, key: &str) -> Option<&str> {
+        self.tags.get(key).map(String::as_str)
+    }
+
+    /// Time of the message in local time, if the server sent an IRCv3 `server-time` tag.
+    pub fn server_time(&self) -> Option<Tm> {
+        self.get_tag("time").and_then(parse_server_time)
+    }
+
+    /// Write the message in wire format, including the trailing CRLF. Tag values are escaped,
+    /// the last parameter is written as a trailing parameter (with `:`) when necessary.
+    pub fn write<W: Write>(&self, sink: &mut W) -> std::io::Result<()> {
+        if !self.tags.is_empty() {
+            sink.write_all(b"@")?;
+            for (tag_idx, (key, value)) in self.tags.iter().enumerate() {
+                if tag_idx != 0 {
+                    sink.write_all(b";")?;
+                }
+                sink.write_all(key.as_bytes())?;
+                if !value.is_empty() {
+                    write!(sink, "={}", escape_tag_value(value))?;
+                }
+            }
+            sink.write_all(b" ")?;
+        }
+
+        match self.pfx {
+            None =>
+                {}
+            Some(Pfx::Server(ref server)) =>
+                write!(sink, ":{} ", server)?,
+            Some(Pfx::User { ref nick, ref user }) =>
+                write!(sink, ":{}!{} ", nick, user)?,
+        }
+
+        self.cmd.write(sink)?;
+        sink.write_all(&CRLF)
+    }
+
     /// Try to read an IRC message off a buffer. Drops the message when parsing is successful.
     /// Otherwise the buffer is left unchanged.
     pub fn read(buf: &mut Vec<u8>, logger: Option<LogFile>) -> Option<Msg> {
@@ -189,8 +721,22 @@ impl Msg {
                 }
             }
 
+            let tags: Tags = {
+                if slice.first() == Some(&b'@') {
+                    // parse tags. A line with only tags has no command, it becomes an empty
+                    // `Cmd::Other`.
+                    let ws_idx = find_byte(slice, b' ').unwrap_or_else(|| slice.len());
+                    let (tags, slice_) = slice.split_at(ws_idx);
+                    slice = skip_spaces(slice_);
+                    // drop the @ from tags
+                    parse_tags(&tags[1..])
+                } else {
+                    Tags::new()
+                }
+            };
+
             let pfx: Option<Pfx> = {
-                if slice[0] == b':' {
+                if slice.first() == Some(&b':') {
                     // parse prefix
                     let ws_idx = find_byte(slice, b' ').unwrap();
                     let (mut pfx, slice_) = slice.split_at(ws_idx);
@@ -204,9 +750,19 @@ impl Msg {
             };
 
             let msg_ty: MsgType = {
-                let ws_idx = find_byte(slice, b' ').unwrap();
-                let (cmd, slice_) = slice.split_at(ws_idx);
-                slice = &slice_[1..]; // drop the space
+                let cmd = match find_byte(slice, b' ') {
+                    None => {
+                        // no params
+                        let cmd = slice;
+                        slice = &[];
+                        cmd
+                    }
+                    Some(ws_idx) => {
+                        let (cmd, slice_) = slice.split_at(ws_idx);
+                        slice = &slice_[1..]; // drop the space
+                        cmd
+                    }
+                };
                 match parse_reply_num(cmd) {
                     None =>
                         MsgType::Cmd(unsafe {
@@ -223,23 +779,23 @@ impl Msg {
             let cmd = match msg_ty {
                 MsgType::Cmd("PRIVMSG") | MsgType::Cmd("NOTICE") if params.len() == 2 => {
                     let is_notice = if let MsgType::Cmd("NOTICE") = msg_ty { true } else { false };
-                    let target = params[0];
+                    let targets = params[0];
                     let msg = params[1];
-                    let target = if target.chars().nth(0) == Some('#') {
-                        MsgTarget::Chan(target.to_owned())
-                    } else {
-                        MsgTarget::User(target.to_owned())
-                    };
                     Cmd::PRIVMSG {
-                        target,
+                        targets: msg_targets(targets),
                         msg: msg.to_owned(),
                         is_notice,
                     }
                 }
-                MsgType::Cmd("JOIN") if params.len() == 1 => {
-                    let chan = params[0];
+                MsgType::Cmd("JOIN") if params.len() == 1 || params.len() == 2 => {
+                    let keys = if params.len() == 2 {
+                        split_list(params[1])
+                    } else {
+                        vec![]
+                    };
                     Cmd::JOIN {
-                        chan: chan.to_owned(),
+                        chans: split_list(params[0]),
+                        keys,
                     }
                 }
                 MsgType::Cmd("PART") if params.len() == 1 || params.len() == 2 => {
@@ -249,7 +805,7 @@ impl Msg {
                         None
                     };
                     Cmd::PART {
-                        chan: params[0].to_owned(),
+                        chans: split_list(params[0]),
                         msg: mb_msg,
                     }
                 }
@@ -261,6 +817,17 @@ impl Msg {
                     };
                     Cmd::QUIT { msg: mb_msg }
                 }
+                MsgType::Cmd("KICK") if params.len() == 2 || params.len() == 3 =>
+                    Cmd::KICK {
+                        chan: params[0].to_owned(),
+                        nick: params[1].to_owned(),
+                        msg: params.get(2).map(|s| (*s).to_owned()),
+                    },
+                MsgType::Cmd("INVITE") if params.len() == 2 =>
+                    Cmd::INVITE {
+                        nick: params[0].to_owned(),
+                        chan: params[1].to_owned(),
+                    },
                 MsgType::Cmd("NICK") if params.len() == 1 => {
                     let nick = params[0];
                     Cmd::NICK {
@@ -284,11 +851,34 @@ impl Msg {
                         chan: params[0].to_owned(),
                         topic: params[1].to_owned(),
                     },
-                MsgType::Num(n) =>
-                    Cmd::Reply {
-                        num: n,
-                        params: params.into_iter().map(|s| s.to_owned()).collect(),
+                MsgType::Cmd("MODE") if params.len() >= 2 =>
+                    Cmd::MODE {
+                        target: params[0].to_owned(),
+                        modes: params[1].to_owned(),
+                        params: params[2..].iter().map(|s| (*s).to_owned()).collect(),
                     },
+                MsgType::Cmd("CAP") if params.len() == 3 || params.len() == 4 => {
+                    // CAP <client> <subcmd> [*] :<caps>
+                    let more = params.len() == 4 && params[2] == "*";
+                    Cmd::CAP {
+                        client: params[0].to_owned(),
+                        subcmd: params[1].to_owned(),
+                        caps: params[params.len() - 1]
+                            .split_whitespace()
+                            .map(str::to_owned)
+                            .collect(),
+                        more,
+                    }
+                }
+                MsgType::Cmd("AUTHENTICATE") if params.len() == 1 =>
+                    Cmd::AUTHENTICATE {
+                        param: params[0].to_owned(),
+                    },
+                MsgType::Num(n) =>
+                    Cmd::Reply(Reply::parse(
+                        n,
+                        params.into_iter().map(|s| s.to_owned()).collect(),
+                    )),
                 MsgType::Cmd(cmd) =>
                     Cmd::Other {
                         cmd: cmd.to_owned(),
@@ -296,7 +886,7 @@ impl Msg {
                     },
             };
 
-            Msg { pfx: pfx, cmd: cmd }
+            Msg { tags, pfx, cmd }
         };
 
         buf.drain(0..crlf_idx + 2);
@@ -304,6 +894,250 @@ impl Msg {
     }
 }
 
+impl Cmd {
+    /// Write command and parameters. `Msg::write` adds tags, prefix and CRLF.
+    fn write<W: Write>(&self, sink: &mut W) -> std::io::Result<()> {
+        // Owned strings for the cases that need to allocate
+        let list_str: String;
+        let keys_str: String;
+        let caps_str: String;
+        let num_str: String;
+
+        // `trailing`: whether to always write the last parameter as a trailing parameter.
+        // Otherwise it's only done when necessary.
+        let (cmd, params, trailing): (&str, Vec<&str>, bool) = match *self {
+            Cmd::PRIVMSG {
+                ref targets,
+                ref msg,
+                is_notice,
+            } => {
+                list_str = targets
+                    .iter()
+                    .map(|target| match *target {
+                        MsgTarget::Chan(ref name) | MsgTarget::User(ref name) =>
+                            name.as_str(),
+                    })
+                    .collect::<Vec<&str>>()
+                    .join(",");
+                (
+                    if is_notice { "NOTICE" } else { "PRIVMSG" },
+                    vec![&list_str, msg],
+                    true,
+                )
+            }
+            Cmd::JOIN {
+                ref chans,
+                ref keys,
+            } => {
+                list_str = chans.join(",");
+                if keys.is_empty() {
+                    ("JOIN", vec![&list_str], false)
+                } else {
+                    keys_str = keys.join(",");
+                    ("JOIN", vec![&list_str, &keys_str], false)
+                }
+            }
+            Cmd::PART { ref chans, ref msg } => {
+                list_str = chans.join(",");
+                match *msg {
+                    None =>
+                        ("PART", vec![&list_str], false),
+                    Some(ref msg) =>
+                        ("PART", vec![&list_str, msg], true),
+                }
+            }
+            Cmd::QUIT { ref msg } =>
+                match *msg {
+                    None =>
+                        ("QUIT", vec![], false),
+                    Some(ref msg) =>
+                        ("QUIT", vec![msg], true),
+                },
+            Cmd::KICK {
+                ref chan,
+                ref nick,
+                ref msg,
+            } =>
+                match *msg {
+                    None =>
+                        ("KICK", vec![chan, nick], false),
+                    Some(ref msg) =>
+                        ("KICK", vec![chan, nick, msg], true),
+                },
+            Cmd::INVITE { ref nick, ref chan } =>
+                ("INVITE", vec![nick, chan], false),
+            Cmd::NICK { ref nick } =>
+                ("NICK", vec![nick], false),
+            Cmd::PING { ref server } =>
+                ("PING", vec![server], false),
+            Cmd::PONG { ref server } =>
+                ("PONG", vec![server], false),
+            Cmd::ERROR { ref msg } =>
+                ("ERROR", vec![msg], true),
+            Cmd::TOPIC {
+                ref chan,
+                ref topic,
+            } =>
+                ("TOPIC", vec![chan, topic], true),
+            Cmd::MODE {
+                ref target,
+                ref modes,
+                ref params,
+            } => {
+                let mut all_params = vec![target.as_str(), modes];
+                all_params.extend(params.iter().map(String::as_str));
+                ("MODE", all_params, false)
+            }
+            Cmd::CAP {
+                ref client,
+                ref subcmd,
+                ref caps,
+                more,
+            } => {
+                caps_str = caps.join(" ");
+                let mut params = vec![client.as_str(), subcmd];
+                if more {
+                    params.push("*");
+                }
+                params.push(&caps_str);
+                ("CAP", params, true)
+            }
+            Cmd::AUTHENTICATE { ref param } =>
+                ("AUTHENTICATE", vec![param], false),
+            Cmd::Other {
+                ref cmd,
+                ref params,
+            } =>
+                (cmd, params.iter().map(String::as_str).collect(), false),
+            Cmd::Reply(ref reply) => {
+                num_str = format!("{:03}", reply.num());
+                (&num_str, reply.params(), true)
+            }
+        };
+
+        sink.write_all(cmd.as_bytes())?;
+        for (param_idx, param) in params.iter().enumerate() {
+            let last = param_idx == params.len() - 1;
+            let needs_colon = param.is_empty() || param.contains(' ') || param.starts_with(':');
+            if last && (trailing || needs_colon) {
+                write!(sink, " :{}", param)?;
+            } else {
+                write!(sink, " {}", param)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Targets of a PRIVMSG or NOTICE. Only `#` channels are recognized, `Conn` fixes the targets
+/// according to the server's `CHANTYPES`.
+fn msg_targets(targets: &str) -> Vec<MsgTarget> {
+    targets
+        .split(',')
+        .map(|target| {
+            if target.starts_with('#') {
+                MsgTarget::Chan(target.to_owned())
+            } else {
+                MsgTarget::User(target.to_owned())
+            }
+        })
+        .collect()
+}
+
+/// Split a comma-separated list of channels or keys.
+fn split_list(list: &str) -> Vec<String> {
+    list.split(',').map(str::to_owned).collect()
+}
+
+/// Parse a `server-time` tag value (ISO 8601 in UTC, e.g. `2011-10-19T16:40:51.620Z`) to local
+/// time. Milliseconds are optional.
+pub fn parse_server_time(time: &str) -> Option<Tm> {
+    time::strptime(time, "%Y-%m-%dT%H:%M:%S.%fZ")
+        .or_else(|_| time::strptime(time, "%Y-%m-%dT%H:%M:%SZ"))
+        .ok()
+        .map(|tm| tm.to_local())
+}
+
+/// <tags> ::= <tag> [';' <tag>]*
+/// <tag>  ::= <key> ['=' <escaped value>]
+fn parse_tags(tags: &[u8]) -> Tags {
+    let mut ret = Tags::new();
+    for tag in tags.split(|b| *b == b';') {
+        if tag.is_empty() {
+            continue;
+        }
+        let (key, value) = match find_byte(tag, b'=') {
+            None =>
+                (tag, &[][..]),
+            Some(idx) =>
+                (&tag[..idx], &tag[idx + 1..]),
+        };
+        // From the spec: "If a tag key is duplicated, the last value is used"
+        ret.insert(
+            String::from_utf8_lossy(key).into_owned(),
+            unescape_tag_value(&String::from_utf8_lossy(value)),
+        );
+    }
+    ret
+}
+
+/// Unescapes a tag value as described in https://ircv3.net/specs/core/message-tags-3.2.html.
+/// Unknown escapes drop the backslash, a trailing backslash is dropped.
+fn unescape_tag_value(value: &str) -> String {
+    let mut ret = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some(':') =>
+                    ret.push(';'),
+                Some('s') =>
+                    ret.push(' '),
+                Some('r') =>
+                    ret.push('\r'),
+                Some('n') =>
+                    ret.push('\n'),
+                Some(c) =>
+                    ret.push(c),
+                None =>
+                    {}
+            }
+        } else {
+            ret.push(c);
+        }
+    }
+    ret
+}
+
+/// Inverse of `unescape_tag_value`.
+fn escape_tag_value(value: &str) -> String {
+    let mut ret = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            ';' =>
+                ret.push_str("\\:"),
+            ' ' =>
+                ret.push_str("\\s"),
+            '\\' =>
+                ret.push_str("\\\\"),
+            '\r' =>
+                ret.push_str("\\r"),
+            '\n' =>
+                ret.push_str("\\n"),
+            c =>
+                ret.push(c),
+        }
+    }
+    ret
+}
+
+fn skip_spaces(mut slice: &[u8]) -> &[u8] {
+    while slice.first() == Some(&b' ') {
+        slice = &slice[1..];
+    }
+    slice
+}
+
 fn parse_pfx(pfx: &[u8]) -> Pfx {
     match find_byte(pfx, b'!') {
         None =>
@@ -341,7 +1175,8 @@ fn parse_params(chrs: &str) -> Vec<&str> {
 
     let mut slice_begins = 0;
     for (char_idx, char) in chrs.char_indices() {
-        if char == ':' {
+        if char == ':' && char_idx == slice_begins {
+            // trailing parameter, `:` is only special at the beginning of a parameter
             ret.push(unsafe { chrs.slice_unchecked(char_idx + 1, chrs.len()) });
             return ret;
         } else if char == ' ' {
@@ -366,6 +1201,12 @@ pub fn find_byte(buf: &[u8], byte0: u8) -> Option<usize> {
     None
 }
 
+/// Build a reply from its numeric and parameters, for tests of modules that handle replies.
+#[cfg(test)]
+pub fn reply(num: u16, params: &[&str]) -> Reply {
+    Reply::parse(num, params.iter().map(|s| (*s).to_owned()).collect())
+}
+
 static CTCP_PREFIX: &'static str = "\x01ACTION ";
 
 pub fn check_ctcp_action_msg(msg: &str) -> (&str, bool) {
@@ -387,6 +1228,7 @@ pub fn check_ctcp_action_msg(msg: &str) -> (&str, bool) {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use quickcheck::{Arbitrary, Gen, QuickCheck};
     use std::io::Write;
 
     #[test]
@@ -396,6 +1238,10 @@ mod tests {
         assert_eq!(parse_params(""), v);
         assert_eq!(parse_params(":foo bar baz "), vec!["foo bar baz "]);
         assert_eq!(parse_params(":"), vec![""]);
+        assert_eq!(
+            parse_params("tiny CHANLIMIT=#:120 :are supported"),
+            vec!["tiny", "CHANLIMIT=#:120", "are supported"]
+        );
     }
 
     #[test]
@@ -408,12 +1254,13 @@ mod tests {
         assert_eq!(
             Msg::read(&mut buf, None),
             Some(Msg {
+                tags: Tags::new(),
                 pfx: Some(Pfx::User {
                     nick: "nick".to_owned(),
                     user: "~nick@unaffiliated/nick".to_owned(),
                 }),
                 cmd: Cmd::PRIVMSG {
-                    target: MsgTarget::User("tiny".to_owned()),
+                    targets: vec![MsgTarget::User("tiny".to_owned())],
                     msg: "a b c".to_owned(),
                     is_notice: false,
                 },
@@ -432,9 +1279,10 @@ mod tests {
         assert_eq!(
             Msg::read(&mut buf, None),
             Some(Msg {
+                tags: Tags::new(),
                 pfx: Some(Pfx::Server("barjavel.freenode.net".to_owned())),
                 cmd: Cmd::PRIVMSG {
-                    target: MsgTarget::User("*".to_owned()),
+                    targets: vec![MsgTarget::User("*".to_owned())],
                     msg: "*** Looking up your hostname...".to_owned(),
                     is_notice: true,
                 },
@@ -474,6 +1322,119 @@ mod tests {
         }
 
         assert_eq!(msgs.len(), 4);
+
+        assert_eq!(
+            msgs[0].cmd,
+            Cmd::Reply(Reply::Welcome {
+                client: "tiny".to_owned(),
+                msg: "Welcome to the freenode Internet Relay Chat Network tiny".to_owned(),
+            })
+        );
+        match msgs[2].cmd {
+            Cmd::Reply(Reply::MyInfo { ref server, ref param_chan_modes, .. }) => {
+                assert_eq!(server, "barjavel.freenode.net");
+                assert_eq!(param_chan_modes, "bkloveqjfI");
+            }
+            ref cmd =>
+                panic!("Unexpected cmd: {:?}", cmd),
+        }
+        match msgs[3].cmd {
+            Cmd::Reply(Reply::ISupport { ref client, ref tokens, ref msg }) => {
+                assert_eq!(client, "tiny_test");
+                assert_eq!(tokens.len(), 12);
+                assert_eq!(tokens[0], "CHANTYPES=#");
+                assert_eq!(msg, "are supported by this server");
+            }
+            ref cmd =>
+                panic!("Unexpected cmd: {:?}", cmd),
+        }
+    }
+
+    #[test]
+    fn test_reply_parse() {
+        fn params(params: &[&str]) -> Vec<String> {
+            params.iter().map(|s| (*s).to_owned()).collect()
+        }
+
+        assert_eq!(
+            Reply::parse(353, params(&["tiny", "=", "#tiny", "@osa1 +tiny foo"])),
+            Reply::NamReply {
+                client: "tiny".to_owned(),
+                chan_type: "=".to_owned(),
+                chan: "#tiny".to_owned(),
+                nicks: "@osa1 +tiny foo".to_owned(),
+            }
+        );
+        assert_eq!(
+            Reply::parse(324, params(&["tiny", "#tiny", "+kl", "hunter2", "10"])),
+            Reply::ChannelModeIs {
+                client: "tiny".to_owned(),
+                chan: "#tiny".to_owned(),
+                modes: "+kl".to_owned(),
+                params: params(&["hunter2", "10"]),
+            }
+        );
+        assert_eq!(
+            Reply::parse(332, params(&["tiny", "#tiny", "topic"])),
+            Reply::Topic {
+                client: Some("tiny".to_owned()),
+                chan: "#tiny".to_owned(),
+                topic: "topic".to_owned(),
+            }
+        );
+        // RFC 2812 form, without our nick
+        assert_eq!(
+            Reply::parse(332, params(&["#tiny", "topic"])),
+            Reply::Topic {
+                client: None,
+                chan: "#tiny".to_owned(),
+                topic: "topic".to_owned(),
+            }
+        );
+
+        // unknown numerics and unexpected number of params are kept as they are
+        assert_eq!(
+            Reply::parse(999, params(&["tiny", "foo"])),
+            Reply::Other {
+                num: 999,
+                params: params(&["tiny", "foo"]),
+            }
+        );
+        assert_eq!(
+            Reply::parse(332, params(&["#tiny"])),
+            Reply::Other {
+                num: 332,
+                params: params(&["#tiny"]),
+            }
+        );
+        assert_eq!(
+            Reply::parse(001, vec![]),
+            Reply::Other {
+                num: 001,
+                params: vec![],
+            }
+        );
+    }
+
+    #[test]
+    fn test_reply_num_params() {
+        let replies: Vec<(u16, Vec<&str>)> = vec![
+            (001, vec!["tiny", "Welcome"]),
+            (005, vec!["tiny", "are supported by this server"]),
+            (005, vec!["tiny", "NETWORK=freenode", "are supported by this server"]),
+            (324, vec!["tiny", "#tiny", "+nt"]),
+            (332, vec!["tiny", "#tiny", "topic"]),
+            (332, vec!["tiny", "#tiny"]),
+            (401, vec!["tiny", "osa1", "No such nick/channel"]),
+            (435, vec!["tiny", "tiny_", "#tiny", "Cannot change nickname while banned on channel"]),
+            (908, vec!["tiny", "PLAIN,EXTERNAL", "are available SASL mechanisms"]),
+            (999, vec![]),
+        ];
+        for (num, params) in replies {
+            let reply = Reply::parse(num, params.iter().map(|s| (*s).to_owned()).collect());
+            assert_eq!(reply.num(), num);
+            assert_eq!(reply.params(), params);
+        }
     }
 
     #[test]
@@ -483,12 +1444,13 @@ mod tests {
         assert_eq!(
             Msg::read(&mut buf, None),
             Some(Msg {
+                tags: Tags::new(),
                 pfx: Some(Pfx::User {
                     nick: "tiny".to_owned(),
                     user: "~tiny@123.123.123.123".to_owned(),
                 }),
                 cmd: Cmd::PART {
-                    chan: "#haskell".to_owned(),
+                    chans: vec!["#haskell".to_owned()],
                     msg: None,
                 },
             })
@@ -502,18 +1464,117 @@ mod tests {
         assert_eq!(
             Msg::read(&mut buf, None),
             Some(Msg {
+                tags: Tags::new(),
                 pfx: Some(Pfx::User {
                     nick: "tiny".to_owned(),
                     user: "~tiny@192.168.0.1".to_owned(),
                 }),
                 cmd: Cmd::JOIN {
-                    chan: "#haskell".to_owned(),
+                    chans: vec!["#haskell".to_owned()],
+                    keys: vec![],
                 },
             })
         );
         assert_eq!(buf.len(), 0);
     }
 
+    #[test]
+    fn test_multi_target_parsing() {
+        let mut buf = vec![];
+        write!(&mut buf, ":tiny!~tiny@192.168.0.1 JOIN #a,#b,#c key1,key2\r\n").unwrap();
+        write!(&mut buf, ":tiny!~tiny@192.168.0.1 PART #a,#b :bye\r\n").unwrap();
+        write!(&mut buf, ":osa1!~omer@localhost PRIVMSG #a,@#b,tiny :hi\r\n").unwrap();
+        assert_eq!(
+            Msg::read(&mut buf, None).unwrap().cmd,
+            Cmd::JOIN {
+                chans: vec!["#a".to_owned(), "#b".to_owned(), "#c".to_owned()],
+                keys: vec!["key1".to_owned(), "key2".to_owned()],
+            }
+        );
+        assert_eq!(
+            Msg::read(&mut buf, None).unwrap().cmd,
+            Cmd::PART {
+                chans: vec!["#a".to_owned(), "#b".to_owned()],
+                msg: Some("bye".to_owned()),
+            }
+        );
+        assert_eq!(
+            Msg::read(&mut buf, None).unwrap().cmd,
+            Cmd::PRIVMSG {
+                targets: vec![
+                    MsgTarget::Chan("#a".to_owned()),
+                    MsgTarget::User("@#b".to_owned()),
+                    MsgTarget::User("tiny".to_owned()),
+                ],
+                msg: "hi".to_owned(),
+                is_notice: false,
+            }
+        );
+        assert_eq!(buf.len(), 0);
+    }
+
+    #[test]
+    fn test_mode_parsing() {
+        let mut buf = vec![];
+        write!(&mut buf, ":osa1!~omer@localhost MODE #tiny +ov-k tiny osa1 :key\r\n").unwrap();
+        write!(&mut buf, ":tiny MODE tiny :+Zi\r\n").unwrap();
+        assert_eq!(
+            Msg::read(&mut buf, None).unwrap().cmd,
+            Cmd::MODE {
+                target: "#tiny".to_owned(),
+                modes: "+ov-k".to_owned(),
+                params: vec!["tiny".to_owned(), "osa1".to_owned(), "key".to_owned()],
+            }
+        );
+        assert_eq!(
+            Msg::read(&mut buf, None).unwrap().cmd,
+            Cmd::MODE {
+                target: "tiny".to_owned(),
+                modes: "+Zi".to_owned(),
+                params: vec![],
+            }
+        );
+        assert_eq!(buf.len(), 0);
+    }
+
+    #[test]
+    fn test_kick_parsing() {
+        let mut buf = vec![];
+        write!(&mut buf, ":osa1!~omer@localhost KICK #tiny tiny :spam\r\n").unwrap();
+        write!(&mut buf, ":osa1!~omer@localhost KICK #tiny tiny\r\n").unwrap();
+        assert_eq!(
+            Msg::read(&mut buf, None).unwrap().cmd,
+            Cmd::KICK {
+                chan: "#tiny".to_owned(),
+                nick: "tiny".to_owned(),
+                msg: Some("spam".to_owned()),
+            }
+        );
+        assert_eq!(
+            Msg::read(&mut buf, None).unwrap().cmd,
+            Cmd::KICK {
+                chan: "#tiny".to_owned(),
+                nick: "tiny".to_owned(),
+                msg: None,
+            }
+        );
+        assert_eq!(buf.len(), 0);
+    }
+
+    #[test]
+    fn test_invite_parsing() {
+        let mut buf = vec![];
+        write!(&mut buf, ":osa1!~omer@localhost INVITE tiny :#tiny\r\n").unwrap();
+        assert_eq!(
+            Msg::read(&mut buf, None).unwrap().cmd,
+            Cmd::INVITE {
+                nick: "tiny".to_owned(),
+                chan: "#tiny".to_owned(),
+            }
+        );
+        assert_eq!(buf.len(), 0);
+    }
+
     #[test]
     fn test_ctcp_action_parsing() {
         assert_eq!(
@@ -548,6 +1609,7 @@ mod tests {
         assert_eq!(
             Msg::read(&mut buf, None),
             Some(Msg {
+                tags: Tags::new(),
                 pfx: None,
                 cmd: Cmd::ERROR {
                     msg: "Closing Link: 212.252.143.51 (Excess Flood)".to_owned(),
@@ -555,4 +1617,565 @@ mod tests {
             }),
         );
     }
+
+    #[test]
+    fn test_unescape_tag_value() {
+        assert_eq!(unescape_tag_value(""), "");
+        assert_eq!(unescape_tag_value("plain"), "plain");
+        assert_eq!(unescape_tag_value("a\\:b\\sc\\\\d"), "a;b c\\d");
+        assert_eq!(unescape_tag_value("\\r\\n"), "\r\n");
+        // unknown escapes drop the backslash
+        assert_eq!(unescape_tag_value("\\b"), "b");
+        // trailing backslash is dropped
+        assert_eq!(unescape_tag_value("foo\\"), "foo");
+    }
+
+    #[test]
+    fn test_server_time_tag_parsing() {
+        let mut buf = vec![];
+        write!(
+            &mut buf,
+            "@time=2018-04-03T10:37:41.123Z :nick!~nick@unaffiliated/nick PRIVMSG #tiny :hi\r\n"
+        ).unwrap();
+        let mut tags = Tags::new();
+        tags.insert("time".to_owned(), "2018-04-03T10:37:41.123Z".to_owned());
+        assert_eq!(
+            Msg::read(&mut buf, None),
+            Some(Msg {
+                tags,
+                pfx: Some(Pfx::User {
+                    nick: "nick".to_owned(),
+                    user: "~nick@unaffiliated/nick".to_owned(),
+                }),
+                cmd: Cmd::PRIVMSG {
+                    targets: vec![MsgTarget::Chan("#tiny".to_owned())],
+                    msg: "hi".to_owned(),
+                    is_notice: false,
+                },
+            })
+        );
+        assert_eq!(buf.len(), 0);
+    }
+
+    #[test]
+    fn test_parse_server_time() {
+        let tm = parse_server_time("2011-10-19T16:40:51.620Z").unwrap();
+        assert_eq!(tm.to_timespec(), time::Timespec::new(1319042451, 620_000_000));
+
+        let tm = parse_server_time("2011-10-19T16:40:51Z").unwrap();
+        assert_eq!(tm.to_timespec(), time::Timespec::new(1319042451, 0));
+
+        assert!(parse_server_time("").is_none());
+        assert!(parse_server_time("yesterday").is_none());
+    }
+
+    #[test]
+    fn test_twitch_tags_parsing() {
+        let mut buf = vec![];
+        write!(
+            &mut buf,
+            "@badge-info=;badges=broadcaster/1;color=#0000FF;display-name=Ronni;emotes=;\
+             id=db25007f-7a18-43eb-9379-80131e44d633;mod=0;room-id=1337;subscriber=0;\
+             tmi-sent-ts=1507246572675;turbo=1;user-id=1337;user-type= \
+             :ronni!ronni@ronni.tmi.twitch.tv PRIVMSG #ronni :Kappa Keepo Kappa\r\n"
+        ).unwrap();
+        let msg = Msg::read(&mut buf, None).unwrap();
+        assert_eq!(msg.tags.len(), 13);
+        assert_eq!(msg.get_tag("badge-info"), Some(""));
+        assert_eq!(msg.get_tag("badges"), Some("broadcaster/1"));
+        assert_eq!(msg.get_tag("color"), Some("#0000FF"));
+        assert_eq!(msg.get_tag("user-type"), Some(""));
+        assert_eq!(msg.get_tag("time"), None);
+        assert_eq!(
+            msg.pfx,
+            Some(Pfx::User {
+                nick: "ronni".to_owned(),
+                user: "ronni@ronni.tmi.twitch.tv".to_owned(),
+            })
+        );
+        assert_eq!(
+            msg.cmd,
+            Cmd::PRIVMSG {
+                targets: vec![MsgTarget::Chan("#ronni".to_owned())],
+                msg: "Kappa Keepo Kappa".to_owned(),
+                is_notice: false,
+            }
+        );
+    }
+
+    #[test]
+    fn test_escaped_tags_parsing() {
+        let mut buf = vec![];
+        write!(
+            &mut buf,
+            "@+example=raw+:=,escaped\\:\\s\\\\;account=bob;draft/flag \
+             :irc.example.com NOTICE #tiny :tags\r\n"
+        ).unwrap();
+        let msg = Msg::read(&mut buf, None).unwrap();
+        assert_eq!(msg.get_tag("+example"), Some("raw+:=,escaped; \\"));
+        assert_eq!(msg.get_tag("account"), Some("bob"));
+        assert_eq!(msg.get_tag("draft/flag"), Some(""));
+        assert_eq!(msg.pfx, Some(Pfx::Server("irc.example.com".to_owned())));
+    }
+
+    #[test]
+    fn test_tags_without_prefix_parsing() {
+        let mut buf = vec![];
+        write!(&mut buf, "@time=2018-04-03T10:37:41.123Z PING :irc.example.com\r\n").unwrap();
+        let msg = Msg::read(&mut buf, None).unwrap();
+        assert_eq!(msg.get_tag("time"), Some("2018-04-03T10:37:41.123Z"));
+        assert_eq!(msg.pfx, None);
+        assert_eq!(
+            msg.cmd,
+            Cmd::PING {
+                server: "irc.example.com".to_owned(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_tags_only_parsing() {
+        let mut buf = vec![];
+        write!(&mut buf, "@a=b\r\nPING :irc.example.com\r\n").unwrap();
+        let msg = Msg::read(&mut buf, None).unwrap();
+        assert_eq!(msg.get_tag("a"), Some("b"));
+        assert_eq!(msg.pfx, None);
+        assert_eq!(
+            msg.cmd,
+            Cmd::Other {
+                cmd: "".to_owned(),
+                params: vec![],
+            }
+        );
+        assert_eq!(
+            Msg::read(&mut buf, None).unwrap().cmd,
+            Cmd::PING {
+                server: "irc.example.com".to_owned(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_cap_parsing() {
+        let mut buf = vec![];
+        write!(
+            &mut buf,
+            ":tolkien.freenode.net CAP * LS * :account-notify away-notify cap-notify \
+             chghost extended-join identify-msg multi-prefix sasl\r\n"
+        ).unwrap();
+        write!(&mut buf, ":irc.example.com CAP * LS :server-time\r\n").unwrap();
+        write!(&mut buf, ":irc.example.com CAP tiny ACK :server-time multi-prefix \r\n").unwrap();
+        write!(&mut buf, ":irc.example.com CAP tiny NEW :sasl=PLAIN,EXTERNAL\r\n").unwrap();
+
+        assert_eq!(
+            Msg::read(&mut buf, None).unwrap().cmd,
+            Cmd::CAP {
+                client: "*".to_owned(),
+                subcmd: "LS".to_owned(),
+                caps: vec![
+                    "account-notify".to_owned(),
+                    "away-notify".to_owned(),
+                    "cap-notify".to_owned(),
+                    "chghost".to_owned(),
+                    "extended-join".to_owned(),
+                    "identify-msg".to_owned(),
+                    "multi-prefix".to_owned(),
+                    "sasl".to_owned(),
+                ],
+                more: true,
+            }
+        );
+        assert_eq!(
+            Msg::read(&mut buf, None).unwrap().cmd,
+            Cmd::CAP {
+                client: "*".to_owned(),
+                subcmd: "LS".to_owned(),
+                caps: vec!["server-time".to_owned()],
+                more: false,
+            }
+        );
+        assert_eq!(
+            Msg::read(&mut buf, None).unwrap().cmd,
+            Cmd::CAP {
+                client: "tiny".to_owned(),
+                subcmd: "ACK".to_owned(),
+                caps: vec!["server-time".to_owned(), "multi-prefix".to_owned()],
+                more: false,
+            }
+        );
+        assert_eq!(
+            Msg::read(&mut buf, None).unwrap().cmd,
+            Cmd::CAP {
+                client: "tiny".to_owned(),
+                subcmd: "NEW".to_owned(),
+                caps: vec!["sasl=PLAIN,EXTERNAL".to_owned()],
+                more: false,
+            }
+        );
+        assert_eq!(buf.len(), 0);
+    }
+
+    #[test]
+    fn test_authenticate_parsing() {
+        let mut buf = vec![];
+        write!(&mut buf, "AUTHENTICATE +\r\n").unwrap();
+        assert_eq!(
+            Msg::read(&mut buf, None).unwrap().cmd,
+            Cmd::AUTHENTICATE {
+                param: "+".to_owned(),
+            }
+        );
+        assert_eq!(buf.len(), 0);
+    }
+
+    #[test]
+    fn test_authenticate_payload() {
+        let mut buf = vec![];
+        authenticate_payload(&mut buf, b"tiny\0tiny\0hunter2").unwrap();
+        assert_eq!(buf, b"AUTHENTICATE dGlueQB0aW55AGh1bnRlcjI=\r\n".to_vec());
+
+        let mut buf = vec![];
+        authenticate_payload(&mut buf, b"").unwrap();
+        assert_eq!(buf, b"AUTHENTICATE +\r\n".to_vec());
+
+        // 300 bytes encode to exactly 400 base64 characters, so an empty chunk follows
+        let mut buf = vec![];
+        authenticate_payload(&mut buf, &[0; 300]).unwrap();
+        let lines: Vec<&[u8]> = buf.split(|b| *b == b'\n').collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0].len(), "AUTHENTICATE ".len() + 400 + 1);
+        assert_eq!(lines[1], b"AUTHENTICATE +\r");
+        assert_eq!(lines[2], b"");
+
+        let mut buf = vec![];
+        authenticate_payload(&mut buf, &[0; 301]).unwrap();
+        let lines: Vec<&[u8]> = buf.split(|b| *b == b'\n').collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[1], b"AUTHENTICATE AA==\r");
+    }
+
+    #[test]
+    fn test_writers() {
+        fn written<F: Fn(&mut Vec<u8>) -> std::io::Result<()>>(f: F) -> String {
+            let mut buf = vec![];
+            f(&mut buf).unwrap();
+            String::from_utf8(buf).unwrap()
+        }
+
+        assert_eq!(written(|buf| nick(buf, "tiny")), "NICK tiny\r\n");
+        assert_eq!(written(|buf| join(buf, &["#a", "#b"], &[])), "JOIN #a,#b\r\n");
+        assert_eq!(written(|buf| join(buf, &["#a", "#b"], &["key"])), "JOIN #a,#b key\r\n");
+        assert_eq!(written(|buf| part(buf, "#a")), "PART #a\r\n");
+        assert_eq!(written(|buf| privmsg(buf, "#a", "hi")), "PRIVMSG #a :hi\r\n");
+        assert_eq!(
+            written(|buf| ctcp_action(buf, "osa1", "waves")),
+            "PRIVMSG osa1 :\x01ACTION waves\x01\r\n"
+        );
+        assert_eq!(
+            written(|buf| ctcp_request(buf, "osa1", "VERSION", "")),
+            "PRIVMSG osa1 :\x01VERSION\x01\r\n"
+        );
+        assert_eq!(
+            written(|buf| ctcp_reply(buf, "osa1", "PING", "1234")),
+            "NOTICE osa1 :\x01PING 1234\x01\r\n"
+        );
+        assert_eq!(
+            written(|buf| user(buf, "tiny", "tiny user")),
+            "USER tiny 8 * :tiny user\r\n"
+        );
+        assert_eq!(
+            written(|buf| cap_req(buf, &["sasl", "server-time"])),
+            "CAP REQ :sasl server-time\r\n"
+        );
+        assert_eq!(written(|buf| cap_end(buf)), "CAP END\r\n");
+        assert_eq!(written(|buf| away(buf, None)), "AWAY\r\n");
+        assert_eq!(written(|buf| away(buf, Some("not here"))), "AWAY :not here\r\n");
+        assert_eq!(written(|buf| whois(buf, "osa1")), "WHOIS osa1\r\n");
+        assert_eq!(written(|buf| who(buf, "#tiny", false)), "WHO #tiny\r\n");
+        assert_eq!(
+            written(|buf| who(buf, "#tiny", true)),
+            "WHO #tiny %tcuhnfar,152\r\n"
+        );
+        assert_eq!(
+            written(|buf| monitor_add(buf, &["osa1", "tiny"])),
+            "MONITOR + osa1,tiny\r\n"
+        );
+        assert_eq!(written(|buf| ison(buf, &["osa1", "tiny"])), "ISON osa1 tiny\r\n");
+    }
+
+    #[test]
+    fn test_msg_write() {
+        let mut tags = Tags::new();
+        tags.insert("+draft/x".to_owned(), "a;b c\\".to_owned());
+        tags.insert("account".to_owned(), "".to_owned());
+        let msg = Msg {
+            tags,
+            pfx: Some(Pfx::User {
+                nick: "osa1".to_owned(),
+                user: "~omer@localhost".to_owned(),
+            }),
+            cmd: Cmd::PRIVMSG {
+                targets: vec![MsgTarget::Chan("#tiny".to_owned())],
+                msg: ":)".to_owned(),
+                is_notice: true,
+            },
+        };
+        let mut buf = vec![];
+        msg.write(&mut buf).unwrap();
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "@+draft/x=a\\:b\\sc\\\\;account :osa1!~omer@localhost NOTICE #tiny ::)\r\n"
+        );
+
+        let msg = Msg {
+            tags: Tags::new(),
+            pfx: Some(Pfx::Server("irc.example.com".to_owned())),
+            cmd: Cmd::Reply(Reply::Topic {
+                client: Some("tiny".to_owned()),
+                chan: "#tiny".to_owned(),
+                topic: "".to_owned(),
+            }),
+        };
+        let mut buf = vec![];
+        msg.write(&mut buf).unwrap();
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            ":irc.example.com 332 tiny #tiny :\r\n"
+        );
+    }
+
+    #[test]
+    fn test_no_params_parsing() {
+        let mut buf = vec![];
+        write!(&mut buf, ":tiny!~tiny@localhost QUIT\r\n").unwrap();
+        assert_eq!(
+            Msg::read(&mut buf, None).unwrap().cmd,
+            Cmd::QUIT { msg: None }
+        );
+        assert_eq!(buf.len(), 0);
+    }
+
+    ////////////////////////////////////////////////////////////////////////////
+    // Round-trip tests
+
+    static NICK_CHARS: &[char] = &[
+        'a', 'b', 'z', 'A', 'Z', '0', '9', '_', '-', '[', ']', '\\', '`', '^', '{', '}', '|',
+    ];
+
+    static HOST_CHARS: &[char] = &['a', 'b', 'z', '0', '9', '.', '-'];
+
+    static TAG_KEY_CHARS: &[char] = &['a', 'b', 'z', 'A', 'Z', '0', '9', '-', '/', '.', '+'];
+
+    static CAP_CHARS: &[char] = &['a', 'b', 'z', '0', '9', '-', '/', '.', '=', ','];
+
+    static MODE_CHARS: &[char] = &['+', '-', 'o', 'v', 'b', 'k', 'l', 'i', 'm', 'n', 't'];
+
+    /// Commands that are parsed as `Cmd::Other`.
+    static OTHER_CMDS: &[&str] = &["WALLOPS", "SETNAME", "CHGHOST", "ACCOUNT", "BATCH"];
+
+    /// A non-empty string of the given characters.
+    fn arbitrary_word<G: Gen>(g: &mut G, chars: &[char]) -> String {
+        let len = g.gen_range(1, 10);
+        (0..len).map(|_| *g.choose(chars).unwrap()).collect()
+    }
+
+    /// A parameter that can appear anywhere in a message: non-empty, no spaces, doesn't start
+    /// with `:`.
+    fn arbitrary_param<G: Gen>(g: &mut G) -> String {
+        let param: String = String::arbitrary(g)
+            .chars()
+            .filter(|c| !"\0\r\n ".contains(*c))
+            .skip_while(|c| *c == ':')
+            .collect();
+        if param.is_empty() {
+            "param".to_owned()
+        } else {
+            param
+        }
+    }
+
+    /// A parameter that can only appear at the end of a message.
+    fn arbitrary_trailing<G: Gen>(g: &mut G) -> String {
+        String::arbitrary(g)
+            .chars()
+            .filter(|c| !"\0\r\n".contains(*c))
+            .collect()
+    }
+
+    fn arbitrary_params<G: Gen>(g: &mut G) -> Vec<String> {
+        let n_params = g.gen_range(0, 5);
+        let mut params: Vec<String> = (0..n_params).map(|_| arbitrary_param(g)).collect();
+        if bool::arbitrary(g) {
+            params.push(arbitrary_trailing(g));
+        }
+        params
+    }
+
+    fn arbitrary_chan<G: Gen>(g: &mut G) -> String {
+        format!("#{}", arbitrary_param(g).replace(',', ""))
+    }
+
+    /// Channels of a JOIN or PART message.
+    fn arbitrary_chans<G: Gen>(g: &mut G) -> Vec<String> {
+        let n_chans = g.gen_range(1, 4);
+        (0..n_chans).map(|_| arbitrary_chan(g)).collect()
+    }
+
+    fn arbitrary_msg<G: Gen>(g: &mut G) -> Option<String> {
+        if bool::arbitrary(g) {
+            Some(arbitrary_trailing(g))
+        } else {
+            None
+        }
+    }
+
+    impl Arbitrary for Msg {
+        fn arbitrary<G: Gen>(g: &mut G) -> Msg {
+            let mut tags = Tags::new();
+            for _ in 0..g.gen_range(0, 3) {
+                tags.insert(arbitrary_word(g, TAG_KEY_CHARS), arbitrary_trailing(g));
+            }
+
+            let pfx = match g.gen_range(0, 3) {
+                0 =>
+                    None,
+                1 =>
+                    Some(Pfx::Server(arbitrary_word(g, HOST_CHARS))),
+                _ =>
+                    Some(Pfx::User {
+                        nick: arbitrary_word(g, NICK_CHARS),
+                        user: format!(
+                            "~{}@{}",
+                            arbitrary_word(g, NICK_CHARS),
+                            arbitrary_word(g, HOST_CHARS)
+                        ),
+                    }),
+            };
+
+            let cmd = match g.gen_range(0, 16) {
+                0 => {
+                    let n_targets = g.gen_range(1, 4);
+                    Cmd::PRIVMSG {
+                        targets: (0..n_targets)
+                            .map(|_| {
+                                if bool::arbitrary(g) {
+                                    MsgTarget::Chan(arbitrary_chan(g))
+                                } else {
+                                    MsgTarget::User(arbitrary_word(g, NICK_CHARS))
+                                }
+                            })
+                            .collect(),
+                        msg: arbitrary_trailing(g),
+                        is_notice: bool::arbitrary(g),
+                    }
+                }
+                1 => {
+                    let chans = arbitrary_chans(g);
+                    let n_keys = g.gen_range(0, chans.len() + 1);
+                    Cmd::JOIN {
+                        chans,
+                        keys: (0..n_keys).map(|_| arbitrary_word(g, NICK_CHARS)).collect(),
+                    }
+                }
+                2 =>
+                    Cmd::PART {
+                        chans: arbitrary_chans(g),
+                        msg: arbitrary_msg(g),
+                    },
+                3 =>
+                    Cmd::QUIT {
+                        msg: arbitrary_msg(g),
+                    },
+                4 =>
+                    Cmd::NICK {
+                        nick: arbitrary_word(g, NICK_CHARS),
+                    },
+                5 =>
+                    Cmd::PING {
+                        server: arbitrary_param(g),
+                    },
+                6 =>
+                    Cmd::PONG {
+                        server: arbitrary_param(g),
+                    },
+                7 =>
+                    Cmd::ERROR {
+                        msg: arbitrary_trailing(g),
+                    },
+                8 =>
+                    Cmd::TOPIC {
+                        chan: arbitrary_chan(g),
+                        topic: arbitrary_trailing(g),
+                    },
+                9 => {
+                    let n_caps = g.gen_range(0, 4);
+                    Cmd::CAP {
+                        client: arbitrary_word(g, NICK_CHARS),
+                        subcmd: (*g.choose(&["LS", "LIST", "ACK", "NAK", "NEW", "DEL"]).unwrap())
+                            .to_owned(),
+                        caps: (0..n_caps).map(|_| arbitrary_word(g, CAP_CHARS)).collect(),
+                        more: bool::arbitrary(g),
+                    }
+                }
+                10 =>
+                    Cmd::AUTHENTICATE {
+                        param: arbitrary_param(g),
+                    },
+                11 =>
+                    Cmd::Other {
+                        cmd: (*g.choose(OTHER_CMDS).unwrap()).to_owned(),
+                        params: arbitrary_params(g),
+                    },
+                12 =>
+                    Cmd::MODE {
+                        target: arbitrary_param(g),
+                        modes: arbitrary_word(g, MODE_CHARS),
+                        params: arbitrary_params(g),
+                    },
+                13 =>
+                    Cmd::KICK {
+                        chan: arbitrary_chan(g),
+                        nick: arbitrary_word(g, NICK_CHARS),
+                        msg: arbitrary_msg(g),
+                    },
+                14 =>
+                    Cmd::INVITE {
+                        nick: arbitrary_word(g, NICK_CHARS),
+                        chan: arbitrary_chan(g),
+                    },
+                _ => {
+                    let num = g.gen_range(0, 1000);
+                    Cmd::Reply(Reply::parse(num, arbitrary_params(g)))
+                }
+            };
+
+            Msg { tags, pfx, cmd }
+        }
+    }
+
+    #[test]
+    fn msg_write_read_prop() {
+        fn prop(msg: Msg) -> bool {
+            let mut buf = vec![];
+            msg.write(&mut buf).unwrap();
+            Msg::read(&mut buf, None) == Some(msg) && buf.is_empty()
+        }
+
+        QuickCheck::new()
+            .tests(1000)
+            .quickcheck(prop as fn(Msg) -> bool);
+    }
+
+    #[test]
+    fn reply_params_prop() {
+        fn prop(num: u16, params: Vec<String>) -> bool {
+            let num = num % 1000;
+            let reply = Reply::parse(num, params.clone());
+            reply.num() == num && reply.params() == params
+        }
+
+        QuickCheck::new()
+            .tests(1000)
+            .quickcheck(prop as fn(u16, Vec<String>) -> bool);
+    }
 }